@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use crate::{
+    collider::Collider,
+    dto::SolutionDto,
+    scoring::breakdown::{MusicianBreakdown, ScoreBreakdown},
+    solvers::Problem,
+};
+
+pub fn breakdown(
+    problem_path: &Path,
+    solution_path: &Path,
+    json: bool,
+    pairs: bool,
+    top: usize,
+) -> Result<(), std::io::Error> {
+    let problem = Problem::load(problem_path)?;
+    let solution = SolutionDto::load(solution_path)?;
+    let breakdown = ScoreBreakdown::new(
+        &problem.data,
+        &solution.placements,
+        solution.volumes.as_ref(),
+        pairs,
+    );
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&breakdown)?);
+        return Ok(());
+    }
+
+    println!("Problem {}: score={}", problem.id, breakdown.total);
+    println!("------------------------------------");
+    println!("{:>5} {:>14} {:>8}", "instr", "score", "players");
+    for (instrument, ins) in breakdown.instruments.iter().enumerate() {
+        if ins.musicians > 0 {
+            println!("{:>5} {:>14} {:>8}", instrument, ins.score, ins.musicians);
+        }
+    }
+
+    let mut musicians = breakdown.musicians.iter().enumerate().collect::<Vec<_>>();
+    musicians.sort_by_key(|(_, m)| -m.score);
+    let top = top.min(musicians.len());
+    let collider = Collider::new(&problem.data, &solution.placements);
+    print_musicians("Best musicians", &musicians[..top], &collider);
+    print_musicians(
+        "Worst musicians",
+        &musicians[musicians.len() - top..],
+        &collider,
+    );
+
+    let musician_count = breakdown.musicians.len();
+    let fully_blocked = breakdown
+        .attendees
+        .iter()
+        .filter(|a| a.blocked_musicians == musician_count)
+        .count();
+    let unhappy = breakdown.attendees.iter().filter(|a| a.score < 0).count();
+    let blocked_pairs: usize = breakdown
+        .attendees
+        .iter()
+        .map(|a| a.blocked_musicians)
+        .sum();
+    println!("------------------------------------");
+    println!(
+        "Attendees: {} total, {} hear nobody, {} unhappy",
+        breakdown.attendees.len(),
+        fully_blocked,
+        unhappy
+    );
+    println!(
+        "Blocked pairs: {} out of {} ({:.1}%)",
+        blocked_pairs,
+        breakdown.attendees.len() * musician_count,
+        blocked_pairs as f64 * 100.0 / (breakdown.attendees.len() * musician_count) as f64
+    );
+    if let Some(pairs) = &breakdown.pairs {
+        println!("Non-zero pairs: {}", pairs.len());
+    }
+
+    Ok(())
+}
+
+fn print_musicians(title: &str, musicians: &[(usize, &MusicianBreakdown)], collider: &Collider) {
+    println!("------------------------------------");
+    println!("{title}");
+    println!(
+        "{:>5} {:>5} {:>6} {:>9} {:>14} {:>14} {:>14} {:>8} {:>8}",
+        "idx", "instr", "volume", "closeness", "score", "positive", "negative", "blocked", "blocks"
+    );
+    for (musician_i, m) in musicians {
+        // the pairs (attendee, other musician) this one is on the way of
+        let blocks = collider.blocked_by(*musician_i).len();
+        println!(
+            "{:>5} {:>5} {:>6.1} {:>9.4} {:>14} {:>14} {:>14} {:>8} {:>8}",
+            musician_i,
+            m.instrument,
+            m.volume,
+            m.closeness,
+            m.score,
+            m.positive,
+            m.negative,
+            m.blocked_attendees,
+            blocks
+        );
+    }
+}
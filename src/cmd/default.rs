@@ -1,18 +1,50 @@
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use log::info;
+use log::{info, warn};
 use rayon::prelude::{ParallelBridge, ParallelIterator};
 
 use crate::{
     gui::gui_main,
-    solvers::{create_solver, Problem, Solution, Solver},
+    solvers::{
+        checkpoint::{Checkpoint, Checkpointer},
+        create_solver, Problem, Solution, Solver,
+    },
 };
 
+/// How long a run may take, for every problem or for specific ones
+#[derive(Default, Debug, Clone)]
+pub struct Budgets {
+    pub default: Option<Duration>,
+    pub per_problem: HashMap<String, Duration>,
+}
+
+impl Budgets {
+    pub fn for_problem(&self, problem_id: &str) -> Option<Duration> {
+        self.per_problem.get(problem_id).copied().or(self.default)
+    }
+}
+
+/// Where the runs save their solutions and checkpoints
+pub const BASE_SOLUTION_DIR: &str = "./solutions/";
+
+/// How every solver runs
+#[derive(Default, Debug, Clone)]
+pub struct RunOptions {
+    pub budgets: Budgets,
+    /// No checkpoints when `None`
+    pub checkpoint_interval: Option<Duration>,
+    /// A random one for every run when `None`, it is saved with the solution anyway
+    pub seed: Option<u64>,
+}
+
 fn solve_problem(
-    solvers: &[Box<dyn Solver>],
+    solvers: &[(String, Box<dyn Solver>)],
+    options: &RunOptions,
     base_solution_dir: &Path,
     problem_path: &Path,
 ) -> std::io::Result<()> {
@@ -20,93 +52,197 @@ fn solve_problem(
 
     let solvers = solvers.to_owned();
 
-    for mut solver in solvers {
+    for (pipeline, mut solver) in solvers {
         // solve
-        info!("solving problem {} using {}", problem.id, solver.name());
-        let solution = solver.solve(&problem);
-
-        print!(
-            "{:15}{}: {} ",
-            format!("[problem {}]", problem.id),
+        let budget = options.budgets.for_problem(&problem.id);
+        let seed = options.seed.unwrap_or_else(rand::random);
+        info!(
+            "solving problem {} using {} (budget {:?}, seed {})",
+            problem.id,
             solver.name(),
-            solution.score.0
+            budget,
+            seed
         );
+        solver.set_time_budget(budget);
+        solver.set_seed(seed);
+        let mut checkpointer = options.checkpoint_interval.map(|interval| {
+            let path = base_solution_dir
+                .join("checkpoints")
+                .join(solver.name())
+                .join(format!("{}.json", problem.id));
+            Checkpointer::new(&pipeline, Some(seed), path, interval)
+        });
+        solver.initialize(&problem, Default::default());
+        let mut solution = solver.finish(&problem, checkpointer.as_mut());
+        solution.seed = Some(seed);
 
-        let full_solver_name = solver.name();
-        let cur_solver_dir = &base_solution_dir.join("current").join(&full_solver_name);
-        let best_dir = &base_solution_dir.join("best");
-        std::fs::create_dir_all(cur_solver_dir)?;
-        std::fs::create_dir_all(best_dir)?;
+        save_solution(&*solver, &problem, &solution, base_solution_dir)?;
+        remove_checkpoint(checkpointer.as_ref());
+    }
+    Ok(())
+}
 
-        // write the solution
-        solution.save(full_solver_name.clone(), &problem, cur_solver_dir)?;
+/// Runs a solver again from a checkpoint file, with the budget of its problem
+pub fn resume(
+    checkpoint_path: &Path,
+    options: &RunOptions,
+    base_solution_dir: &Path,
+) -> std::io::Result<()> {
+    let invalid = |message: String| {
+        eprintln!("{message}");
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid checkpoint")
+    };
+    let checkpoint = Checkpoint::load(checkpoint_path)?;
+    let mut solver = create_solver(&checkpoint.pipeline).map_err(invalid)?;
+    if solver.name() != checkpoint.solver {
+        return Err(invalid(format!(
+            "the checkpoint is for {}, but {} creates {}",
+            checkpoint.solver,
+            checkpoint.pipeline,
+            solver.name()
+        )));
+    }
+    let problem = Problem::load(&checkpoint.problem)?;
 
-        if solution.score.0 < 0 {
-            println!("Saved, but won't compare with best");
-            continue;
-        }
+    let budget = options.budgets.for_problem(&problem.id);
+    info!(
+        "resuming problem {} using {} (budget {:?}, seed {:?})",
+        problem.id,
+        solver.name(),
+        budget,
+        checkpoint.seed
+    );
+    solver.set_time_budget(budget);
+    if let Some(seed) = checkpoint.seed {
+        solver.set_seed(seed);
+    }
+    solver
+        .restore(&problem, checkpoint.state)
+        .map_err(invalid)?;
+    let mut checkpointer = options.checkpoint_interval.map(|interval| {
+        Checkpointer::new(
+            &checkpoint.pipeline,
+            checkpoint.seed,
+            checkpoint_path.to_owned(),
+            interval,
+        )
+    });
+    let mut solution = solver.finish(&problem, checkpointer.as_mut());
+    solution.seed = checkpoint.seed;
+
+    save_solution(&*solver, &problem, &solution, base_solution_dir)?;
+    remove_checkpoint(checkpointer.as_ref());
+    Ok(())
+}
+
+/// The run is over, it won't be resumed
+fn remove_checkpoint(checkpointer: Option<&Checkpointer>) {
+    let Some(path) = checkpointer.map(|c| c.path()) else {
+        return;
+    };
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("can't remove checkpoint {}: {}", path.display(), e),
+    }
+}
 
-        // compare with the best solution
-        let best_sol = match Solution::load(best_dir, &problem) {
-            Ok(sol) => Some(sol),
-            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => None,
-            Err(e) => return Err(e),
-        };
+fn save_solution(
+    solver: &dyn Solver,
+    problem: &Problem,
+    solution: &Solution,
+    base_solution_dir: &Path,
+) -> std::io::Result<()> {
+    print!(
+        "{:15}{}: {} ",
+        format!("[problem {}]", problem.id),
+        solver.name(),
+        solution.score.0
+    );
 
-        let new_best_sol = match &best_sol {
-            Some((_, best_sol)) => solution.score.0.cmp(&best_sol.score),
-            None => Ordering::Greater,
-        };
+    let full_solver_name = solver.name();
+    let cur_solver_dir = &base_solution_dir.join("current").join(&full_solver_name);
+    let best_dir = &base_solution_dir.join("best");
+    std::fs::create_dir_all(cur_solver_dir)?;
+    std::fs::create_dir_all(best_dir)?;
 
-        if new_best_sol == Ordering::Greater {
-            solution.save(full_solver_name, &problem, best_dir)?;
-        }
+    // write the solution
+    solution.save(full_solver_name.clone(), problem, cur_solver_dir)?;
 
-        match (&best_sol, &new_best_sol) {
-            // new best
-            (Some((_, best_sol)), Ordering::Greater) => {
-                let improvement = solution.score.0 - best_sol.score;
-                println!(
-                    "!!! WE ARE WINNING SON !!!, improvement of {}! previous best: {}",
-                    improvement, best_sol.score
-                );
-            }
-            // likely the same solver
-            (Some((_, _)), Ordering::Equal) => {
-                println!("ties current best");
-            }
-            // nothing special, no new best
-            (Some((_, best_sol)), Ordering::Less) => {
-                println!("worse than best: {}", best_sol.score);
-            }
-            // first solution ever
-            (None, _) => {
-                println!("!!! FIRST BLOOD !!!");
-            }
+    if solution.score.0 < 0 {
+        println!("Saved, but won't compare with best");
+        return Ok(());
+    }
+
+    // compare with the best solution
+    let best_sol = match Solution::load(best_dir, problem) {
+        Ok(sol) => Some(sol),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    let new_best_sol = match &best_sol {
+        Some((_, best_sol)) => solution.score.0.cmp(&best_sol.score),
+        None => Ordering::Greater,
+    };
+
+    if new_best_sol == Ordering::Greater {
+        solution.save(full_solver_name, problem, best_dir)?;
+    }
+
+    match (&best_sol, &new_best_sol) {
+        // new best
+        (Some((_, best_sol)), Ordering::Greater) => {
+            let improvement = solution.score.0 - best_sol.score;
+            println!(
+                "!!! WE ARE WINNING SON !!!, improvement of {}! previous best: {}",
+                improvement, best_sol.score
+            );
+        }
+        // likely the same solver
+        (Some((_, _)), Ordering::Equal) => {
+            println!("ties current best");
+        }
+        // nothing special, no new best
+        (Some((_, best_sol)), Ordering::Less) => {
+            println!("worse than best: {}", best_sol.score);
+        }
+        // first solution ever
+        (None, _) => {
+            println!("!!! FIRST BLOOD !!!");
         }
     }
     Ok(())
 }
 
-fn solve(solvers: &[String], problem_paths: &[PathBuf], parallel: bool) -> std::io::Result<()> {
-    let base_solution_dir = PathBuf::from("./solutions/");
+fn solve(
+    solvers: &[String],
+    options: &RunOptions,
+    problem_paths: &[PathBuf],
+    parallel: bool,
+) -> std::io::Result<()> {
+    let base_solution_dir = PathBuf::from(BASE_SOLUTION_DIR);
 
-    let solvers: Vec<_> = solvers
+    let solvers = solvers
         .iter()
-        .map(|solver_name| create_solver(solver_name))
-        .collect();
+        .map(|pipeline| Ok((pipeline.clone(), create_solver(pipeline)?)))
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|e| {
+            eprintln!("{e}");
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid solver pipeline")
+        })?;
 
     if parallel {
         problem_paths
             .iter()
             .par_bridge()
-            .map(|problem_path| solve_problem(&solvers, &base_solution_dir, problem_path))
+            .map(|problem_path| solve_problem(&solvers, options, &base_solution_dir, problem_path))
             .collect::<std::io::Result<()>>()
     } else {
         #[allow(clippy::map_collect_result_unit)]
         problem_paths
             .iter()
-            .map(|problem_path| solve_problem(&solvers, &base_solution_dir, problem_path))
+            .map(|problem_path| solve_problem(&solvers, options, &base_solution_dir, problem_path))
             .collect::<std::io::Result<()>>()
     }
 }
@@ -114,6 +250,7 @@ fn solve(solvers: &[String], problem_paths: &[PathBuf], parallel: bool) -> std::
 pub fn default_command(
     problem_paths: &[PathBuf],
     solvers: Option<Vec<String>>,
+    options: &RunOptions,
     gui: bool,
     parallel: bool,
 ) -> Result<(), std::io::Error> {
@@ -131,7 +268,7 @@ pub fn default_command(
             gui_main(&std::path::PathBuf::from(problem_path), solver);
             Ok(())
         }
-        (paths, Some(solvers), false) => solve(&solvers, paths, parallel),
+        (paths, Some(solvers), false) => solve(&solvers, options, paths, parallel),
         (_, Some(_), true) => panic!("GUI mode is not supported with multiple solvers"),
         (_, None, _) => panic!("No problem paths and solvers provided"),
     }
@@ -1,6 +1,10 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand};
 
+pub mod breakdown;
 pub mod default;
+pub mod solvers;
 pub mod stats;
 
 #[derive(Parser, Debug)]
@@ -20,10 +24,55 @@ pub struct Args {
     pub gui: bool,
     #[clap(long)]
     pub parallel: bool,
+    /// Time budget of every run, in seconds
+    #[clap(long)]
+    pub budget: Option<u64>,
+    /// Time budget for one problem, as `<problem>=<seconds>`, overrides --budget
+    #[clap(long, value_parser = parse_problem_budget)]
+    pub problem_budget: Vec<(String, u64)>,
+    /// Seconds between two checkpoints of a run, none when 0. Worth it for long runs only,
+    /// they go to `solutions/checkpoints/`.
+    #[clap(long, default_value_t = 0)]
+    pub checkpoint_interval: u64,
+    /// Seed of every run, random otherwise. Either way it is saved in the solution metadata,
+    /// runs without a time budget then give the same solution again.
+    #[clap(long)]
+    pub seed: Option<u64>,
+    /// Resume a run from its checkpoint file, with a new time budget
+    #[clap(long)]
+    pub resume: Option<PathBuf>,
+}
+
+fn parse_problem_budget(s: &str) -> Result<(String, u64), String> {
+    let (problem, seconds) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <problem>=<seconds>, got {s}"))?;
+    let seconds = seconds
+        .parse()
+        .map_err(|e| format!("invalid number of seconds {seconds}: {e}"))?;
+    Ok((problem.to_owned(), seconds))
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Stats,
-    Score { problem: String, solution: String },
+    /// List every solver and its parameters
+    Solvers,
+    Score {
+        problem: String,
+        solution: String,
+    },
+    Breakdown {
+        problem: String,
+        solution: String,
+        /// Print the whole breakdown as JSON instead of tables
+        #[clap(long)]
+        json: bool,
+        /// Include the score of every attendee/musician pair (JSON only)
+        #[clap(long)]
+        pairs: bool,
+        /// How many of the best and worst musicians to show
+        #[clap(long, default_value_t = 10)]
+        top: usize,
+    },
 }
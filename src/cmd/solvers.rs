@@ -0,0 +1,31 @@
+use crate::solvers::{all_combinators, all_solvers, ParameterSpec};
+
+pub fn list_solvers() -> std::io::Result<()> {
+    for (name, solver) in all_solvers() {
+        print_solver(name, solver.parameters());
+    }
+    for (name, parameters) in all_combinators() {
+        print_solver(&format!("{name}(<pipeline>)"), parameters);
+    }
+    Ok(())
+}
+
+fn print_solver(name: &str, parameters: &[ParameterSpec]) {
+    println!("{name}");
+    if parameters.is_empty() {
+        println!("    (no parameters)");
+    }
+    for spec in parameters {
+        let kind = match spec.min {
+            Some(min) => format!("{} >= {}", spec.kind, min),
+            None => spec.kind.to_string(),
+        };
+        println!(
+            "    {:12} {:18} {:8} {}",
+            spec.name,
+            kind,
+            spec.default.unwrap_or("-"),
+            spec.description
+        );
+    }
+}
@@ -1,11 +1,26 @@
-use std::{fs, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-use crate::dto::SolutionMetaDto;
+use rayon::prelude::*;
+
+use crate::{dto::SolutionMetaDto, scoring::upper_bound, solvers::Problem};
 
 pub fn stats(problems_n: &[String], solvers: &[String]) -> Result<(), std::io::Error> {
     let mut sum_best = 0;
+    let mut sum_upper_bound = 0;
+
+    let upper_bounds = problems_n
+        .par_iter()
+        .map(|n| {
+            let problem = Problem::load(&PathBuf::from(format!("./problems/{n}.json")))?;
+            upper_bound::load_or_compute(&problem)
+        })
+        .collect::<Result<Vec<_>, std::io::Error>>()?;
 
-    for n in problems_n {
+    for (n, upper_bound) in problems_n.iter().zip(upper_bounds) {
+        sum_upper_bound += upper_bound;
         let best_fname = format!("./solutions/best/{n}_meta.json");
         let best_path = Path::new(&best_fname);
 
@@ -35,6 +50,11 @@ pub fn stats(problems_n: &[String], solvers: &[String]) -> Result<(), std::io::E
         println!("Problem {n}");
         println!("------------------------------------");
         println!("best: {} score={}", best.solver_name, best.score);
+        println!(
+            "upper bound: {} gap={:.1}%",
+            upper_bound,
+            gap(best.score, upper_bound)
+        );
         current_solved
             .iter()
             .for_each(|x| println!("{} score={}", x.solver_name, x.score));
@@ -42,6 +62,15 @@ pub fn stats(problems_n: &[String], solvers: &[String]) -> Result<(), std::io::E
     }
     println!("------------------------------------");
     println!("Sum of all best: {sum_best}");
+    println!(
+        "Sum of all upper bounds: {sum_upper_bound} gap={:.1}%",
+        gap(sum_best, sum_upper_bound)
+    );
 
     Ok(())
 }
+
+/// How far the score is from the upper bound, in percent of the bound
+fn gap(score: i64, upper_bound: i64) -> f64 {
+    (upper_bound - score) as f64 * 100.0 / upper_bound.max(1) as f64
+}
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use crate::dto::{Point2D, ProblemDto};
 use nalgebra::{Point2, Vector2};
 use parry2d::{
@@ -5,14 +7,44 @@ use parry2d::{
     partitioning::{Qbvh, QbvhUpdateWorkspace},
     query::{visitors::RayIntersectionsVisitor, Ray},
 };
+use rayon::prelude::*;
 
+/// Broad phase for the obstacles (musicians and pillars) between attendees and musicians.
+///
+/// Either borrows a solution for a one-off score (`new`), or owns it (`new_owned`) so that
+/// iterative solvers can keep it across steps and only refit the musicians that moved.
 pub struct Collider<'sol, 'pro> {
-    placements: &'sol [Point2D],
-    problem: &'pro ProblemDto,
+    placements: Cow<'sol, [Point2D]>,
+    problem: Cow<'pro, ProblemDto>,
     qbvh: Qbvh<usize>,
     workspace: QbvhUpdateWorkspace,
 }
 
+pub type OwnedCollider = Collider<'static, 'static>;
+
+impl Default for Collider<'_, '_> {
+    fn default() -> Self {
+        Collider {
+            placements: Cow::Owned(vec![]),
+            problem: Cow::Owned(ProblemDto::default()),
+            qbvh: Qbvh::new(),
+            workspace: QbvhUpdateWorkspace::default(),
+        }
+    }
+}
+
+impl Clone for Collider<'_, '_> {
+    fn clone(&self) -> Self {
+        Collider {
+            placements: self.placements.clone(),
+            problem: self.problem.clone(),
+            qbvh: self.qbvh.clone(),
+            // only a scratch space for the updates
+            workspace: QbvhUpdateWorkspace::default(),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum Obstacle {
     Musician(usize),
@@ -51,14 +83,26 @@ impl<'sol, 'pro> Collider<'sol, 'pro> {
         let pillar_count = self.pillar_count();
         self.qbvh.refit(margin, &mut self.workspace, |index| {
             let obstacle = lookup_obstacle(pillar_count, *index);
-            let (center, radius) = get_circle(self.placements, self.problem, obstacle);
+            let (center, radius) = get_circle(&self.placements, &self.problem, obstacle);
             circle_bounds(&center, radius)
         });
         self.qbvh.rebalance(margin, &mut self.workspace);
     }
 
     pub fn new(problem: &'pro ProblemDto, placements: &'sol [Point2D]) -> Collider<'sol, 'pro> {
-        let node_count = problem.musicians.len() + problem.pillars.len();
+        Self::build(Cow::Borrowed(problem), Cow::Borrowed(placements))
+    }
+
+    pub fn new_owned(problem: ProblemDto, placements: Vec<Point2D>) -> OwnedCollider {
+        Collider::build(Cow::Owned(problem), Cow::Owned(placements))
+    }
+
+    fn build(
+        problem: Cow<'pro, ProblemDto>,
+        placements: Cow<'sol, [Point2D]>,
+    ) -> Collider<'sol, 'pro> {
+        // the placements may be only some of the musicians, the only ones in the way then
+        let node_count = placements.len() + problem.pillars.len();
 
         let mut qbvh: Qbvh<usize> = Qbvh::new();
         let workspace = QbvhUpdateWorkspace::default();
@@ -77,6 +121,51 @@ impl<'sol, 'pro> Collider<'sol, 'pro> {
         collider
     }
 
+    pub fn placements(&self) -> &[Point2D] {
+        &self.placements
+    }
+
+    pub fn problem(&self) -> &ProblemDto {
+        &self.problem
+    }
+
+    pub fn move_musician(&mut self, musician_i: usize, location: Point2D) {
+        self.move_musicians(&[(musician_i, location)]);
+    }
+
+    /// Moves every musician in `moves` at once, with a single refit
+    pub fn move_musicians(&mut self, moves: &[(usize, Point2D)]) {
+        let pillar_count = self.pillar_count();
+        for (musician_i, location) in moves {
+            self.placements.to_mut()[*musician_i] = *location;
+            self.qbvh.pre_update_or_insert(pillar_count + musician_i);
+        }
+        self.refit();
+    }
+
+    /// Whether musician `musician_i` is on the way from the attendee to musician `other_i`
+    pub fn blocks(&self, musician_i: usize, attendee_i: usize, other_i: usize) -> bool {
+        musician_i != other_i
+            && crate::geometry::line_circle_intersection(
+                &self.problem.attendees[attendee_i],
+                &self.placements[other_i],
+                &self.placements[musician_i],
+                5.0,
+            )
+    }
+
+    /// Every `(attendee, musician)` pair that musician `musician_i` is on the way of
+    pub fn blocked_by(&self, musician_i: usize) -> Vec<(usize, usize)> {
+        (0..self.problem.attendees.len())
+            .into_par_iter()
+            .flat_map_iter(|attendee_i| {
+                (0..self.placements.len())
+                    .filter(move |other_i| self.blocks(musician_i, attendee_i, *other_i))
+                    .map(move |other_i| (attendee_i, other_i))
+            })
+            .collect()
+    }
+
     pub fn is_hidden(&self, attendee_i: usize, musician_i: usize) -> bool {
         let attendee_location = &self.problem.attendees[attendee_i].as_vec();
         let musician_location = self.placements[musician_i].as_vec();
@@ -91,7 +180,7 @@ impl<'sol, 'pro> Collider<'sol, 'pro> {
                 return true;
             }
 
-            let (center, radius) = get_circle(self.placements, self.problem, obstacle);
+            let (center, radius) = get_circle(&self.placements, &self.problem, obstacle);
             !crate::geometry::line_circle_intersection(
                 attendee_location,
                 &musician_location,
@@ -100,10 +189,86 @@ impl<'sol, 'pro> Collider<'sol, 'pro> {
             )
         };
 
-        let mut visitor = RayIntersectionsVisitor::new(&ray, std::f32::INFINITY, &mut callback);
+        let mut visitor = RayIntersectionsVisitor::new(&ray, f32::INFINITY, &mut callback);
 
         !self.qbvh.traverse_depth_first(&mut visitor)
     }
+
+    /// Whether an obstacle is on the way from the attendee to `location`, where no musician is
+    pub fn is_location_hidden(&self, attendee_i: usize, location: &Point2D) -> bool {
+        let attendee_location = &self.problem.attendees[attendee_i].as_vec();
+        let location = location.as_vec();
+        let dir = (location - attendee_location).normalize();
+        let ray = Ray::new(Point2::new(attendee_location.x, attendee_location.y), dir);
+
+        let mut callback = |node_index: &usize| {
+            let obstacle = lookup_obstacle(self.pillar_count(), *node_index);
+            let (center, radius) = get_circle(&self.placements, &self.problem, obstacle);
+            !crate::geometry::line_circle_intersection(
+                attendee_location,
+                &location,
+                &center,
+                radius,
+            )
+        };
+
+        // nothing behind the location matters
+        let mut visitor = RayIntersectionsVisitor::new(
+            &ray,
+            (location - attendee_location).norm(),
+            &mut callback,
+        );
+
+        !self.qbvh.traverse_depth_first(&mut visitor)
+    }
+
+    /// Calls `callback` with every obstacle whose bounding box is on the way from the attendee
+    /// to the musician, until it returns false. Only a broad phase, the exact check is up to the caller.
+    pub fn for_each_candidate_blocker(
+        &self,
+        attendee_i: usize,
+        musician_i: usize,
+        mut callback: impl FnMut(Obstacle) -> bool,
+    ) {
+        let attendee_location = &self.problem.attendees[attendee_i].as_vec();
+        let musician_location = self.placements[musician_i].as_vec();
+        let dir = (musician_location - attendee_location).normalize();
+        let ray = Ray::new(Point2::new(attendee_location.x, attendee_location.y), dir);
+
+        let mut callback = |node_index: &usize| {
+            let obstacle = lookup_obstacle(self.pillar_count(), *node_index);
+            if obstacle == Obstacle::Musician(musician_i) {
+                return true;
+            }
+            callback(obstacle)
+        };
+
+        let mut visitor = RayIntersectionsVisitor::new(&ray, f32::INFINITY, &mut callback);
+        self.qbvh.traverse_depth_first(&mut visitor);
+    }
+
+    /// Counts the obstacles (musicians and pillars) on the way from the attendee to the musician
+    pub fn count_blockers(&self, attendee_i: usize, musician_i: usize) -> u16 {
+        let attendee_location = &self.problem.attendees[attendee_i].as_vec();
+        let musician_location = self.placements[musician_i].as_vec();
+
+        let mut count = 0;
+        self.for_each_candidate_blocker(attendee_i, musician_i, |obstacle| {
+            let (center, radius) = get_circle(&self.placements, &self.problem, obstacle);
+            if crate::geometry::line_circle_intersection(
+                attendee_location,
+                &musician_location,
+                &center,
+                radius,
+            ) {
+                count += 1;
+            }
+            // never stop, we need to see all of them
+            true
+        });
+
+        count
+    }
 }
 
 fn circle_bounds(position: &Vector2<f32>, radius: f32) -> Aabb {
@@ -111,3 +276,57 @@ fn circle_bounds(position: &Vector2<f32>, radius: f32) -> Aabb {
     let max = Point2::new(position.x + radius, position.y + radius);
     Aabb::new(min, max)
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::dto::{Attendee, Instrument, PillarDto};
+
+    #[test]
+    fn test_moves_match_fresh() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut location = || Point2D {
+            x: rng.gen_range(10.0..190.0),
+            y: rng.gen_range(10.0..120.0),
+        };
+        let mut placements: Vec<Point2D> = (0..30).map(|_| location()).collect();
+        let problem = ProblemDto {
+            musicians: vec![Instrument(0); placements.len()],
+            attendees: (0..20)
+                .map(|i| Attendee {
+                    x: i as f32 * 10.0,
+                    y: 180.0,
+                    tastes: vec![1.0],
+                })
+                .collect(),
+            pillars: vec![PillarDto {
+                center: (100.0, 140.0),
+                radius: 10.0,
+            }],
+            ..Default::default()
+        };
+
+        let mut collider = Collider::new_owned(problem.clone(), placements.clone());
+        for step in 0..20 {
+            let moves: Vec<_> = (0..step % 3 + 1)
+                .map(|k| ((step * 7 + k * 11) % placements.len(), location()))
+                .collect();
+            for (musician_i, p) in &moves {
+                placements[*musician_i] = *p;
+            }
+            collider.move_musicians(&moves);
+
+            let fresh = Collider::new(&problem, &placements);
+            for attendee_i in 0..problem.attendees.len() {
+                for musician_i in 0..placements.len() {
+                    assert_eq!(
+                        collider.is_hidden(attendee_i, musician_i),
+                        fresh.is_hidden(attendee_i, musician_i)
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 
 use log::debug;
 use rand::Rng;
@@ -145,13 +145,14 @@ impl Grid {
     }
 }
 
-pub fn calculate_invalid_positions(positions: &[Point2D], problem: &ProblemDto) -> HashSet<usize> {
+/// In order, so that seeded runs repair them the same way every time
+pub fn calculate_invalid_positions(positions: &[Point2D], problem: &ProblemDto) -> BTreeSet<usize> {
     let min_x = problem.stage_bottom_left.x() + 10.0;
     let min_y = problem.stage_bottom_left.y() + 10.0;
     let max_x = problem.stage_bottom_left.x() + problem.stage_width - 10.0;
     let max_y = problem.stage_bottom_left.y() + problem.stage_height - 10.0;
 
-    let mut result = HashSet::new();
+    let mut result = BTreeSet::new();
     for i in 0..positions.len() {
         let pos0 = &positions[i];
         if pos0.x.is_nan() {
@@ -175,8 +176,12 @@ pub fn calculate_invalid_positions(positions: &[Point2D], problem: &ProblemDto)
     result
 }
 
-pub fn generate_random_placement(problem: &ProblemDto, placements: &[Point2D]) -> Point2D {
-    let mut placement = get_random_coords(problem);
+pub fn generate_random_placement(
+    rng: &mut impl Rng,
+    problem: &ProblemDto,
+    placements: &[Point2D],
+) -> Point2D {
+    let mut placement = get_random_coords(rng, problem);
     let mut correct_placed = false;
 
     while !correct_placed {
@@ -184,7 +189,7 @@ pub fn generate_random_placement(problem: &ProblemDto, placements: &[Point2D]) -
 
         for other_placement in placements {
             if placement.distance(other_placement) < 10.0 {
-                placement = get_random_coords(problem);
+                placement = get_random_coords(rng, problem);
                 correct_placed = false;
                 break;
             }
@@ -193,9 +198,7 @@ pub fn generate_random_placement(problem: &ProblemDto, placements: &[Point2D]) -
     placement
 }
 
-pub fn get_random_coords(problem: &ProblemDto) -> Point2D {
-    let mut rng = rand::thread_rng();
-
+pub fn get_random_coords(rng: &mut impl Rng, problem: &ProblemDto) -> Point2D {
     Point2D {
         x: rng.gen_range(
             (problem.stage_bottom_left.0 + 10.0)
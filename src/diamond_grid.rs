@@ -1,6 +1,9 @@
 use std::ops::{Index, IndexMut};
 
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::dto::ProblemDto;
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct GridSize {
@@ -141,6 +144,25 @@ pub fn fit_circles_grid(
     fit_grid(min_x, min_y, width, height, min_coarseness)
 }
 
+/// Grid of circles of `radius` covering the part of the stage where musicians can stand
+pub fn fit_stage(problem: &ProblemDto, radius: f32) -> (GridSize, GridTransform) {
+    let stage_width = problem.stage_width;
+    let stage_height = problem.stage_height;
+    assert!(stage_width >= 20.);
+    assert!(stage_height >= 20.);
+    let padding_x = if stage_width < 20.00002 { 5. } else { 5.002 };
+    let padding_y = if stage_height < 20.00002 { 5. } else { 5.002 };
+    let (corner_x, corner_y) = problem.stage_bottom_left;
+    let width = stage_width - padding_x * 2.;
+    let height = stage_height - padding_y * 2.;
+    fit_circles_grid(
+        (corner_x + padding_x, corner_y + padding_y),
+        width.max(0.),
+        height.max(0.),
+        radius,
+    )
+}
+
 pub fn fit_grid(
     x_min: f32,
     y_min: f32,
@@ -189,7 +211,8 @@ mod tests {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Serializable for the checkpoints, which only save coordinates generated here
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GridCoord {
     pub x: isize,
     pub y: isize,
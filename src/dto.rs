@@ -73,6 +73,15 @@ pub struct ProblemDto {
     pub pillars: Vec<PillarDto>,
 }
 
+impl ProblemDto {
+    /// The problem as given, without the pruning done by `Problem::load`
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct SolutionDto {
     pub placements: Vec<Point2D>,
@@ -167,6 +176,9 @@ impl From<(f32, f32)> for Point2D {
 pub struct SolutionMetaDto {
     pub solver_name: String,
     pub score: i64,
+    /// Of the run which found it, if it was seeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
 }
 
 impl SolutionMetaDto {
@@ -174,6 +186,7 @@ impl SolutionMetaDto {
         SolutionMetaDto {
             solver_name: "err_not_solved".to_string(),
             score: 0,
+            seed: None,
         }
     }
 }
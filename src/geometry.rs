@@ -29,19 +29,24 @@ pub fn distance2(c0: &impl Coords2D, c1: &impl Coords2D) -> f32 {
     x * x + y * y
 }
 
+/// Whether the segment goes through the circle, computed in f64: the coordinates are in the
+/// thousands, where f32 rounding lets a segment tangent to a musician slip by (the judge has it
+/// blocked)
 pub fn line_circle_intersection(
     line_start: &impl Coords2D,
     line_end: &impl Coords2D,
     circle_center: &impl Coords2D,
     radius: f32,
 ) -> bool {
+    let (start_x, start_y) = (line_start.x() as f64, line_start.y() as f64);
+
     // Create vector from the start of the line to the center of the circle
-    let start_to_center_x = circle_center.x() - line_start.x();
-    let start_to_center_y = circle_center.y() - line_start.y();
+    let start_to_center_x = circle_center.x() as f64 - start_x;
+    let start_to_center_y = circle_center.y() as f64 - start_y;
 
     // Create the vector that represents the line
-    let line_vector_x = line_end.x() - line_start.x();
-    let line_vector_y = line_end.y() - line_start.y();
+    let line_vector_x = line_end.x() as f64 - start_x;
+    let line_vector_y = line_end.y() as f64 - start_y;
 
     // Calculate the squared length of the line
     let line_len_sq = line_vector_x * line_vector_x + line_vector_y * line_vector_y;
@@ -57,19 +62,16 @@ pub fn line_circle_intersection(
         return false;
     }
 
-    // Calculate the coordinates of the closest Placement
-    let closest_point_x = line_start.x() + t * line_vector_x;
-    let closest_point_y = line_start.y() + t * line_vector_y;
-
-    // Calculate the vector from the closest Placement to the center of the circle
-    let closest_to_center_x = circle_center.x() - closest_point_x;
-    let closest_to_center_y = circle_center.y() - closest_point_y;
+    // The vector from the closest Placement to the center of the circle
+    let closest_to_center_x = start_to_center_x - t * line_vector_x;
+    let closest_to_center_y = start_to_center_y - t * line_vector_y;
 
     // Calculate the squared length of the vector
     let closest_to_center_len_sq =
         closest_to_center_x * closest_to_center_x + closest_to_center_y * closest_to_center_y;
 
     // If the squared length is less than r squared, the line intersects the circle
+    let radius = radius as f64;
     closest_to_center_len_sq <= radius * radius
 }
 
@@ -90,6 +92,14 @@ mod tests {
             // circle slightly behind the line
             ((-2.42, -3.58), (14.76, 6.64), (17.56, 11.7), 5., false),
             ((1100., 800.), (1100., 150.), (1100., 100.), 5., false),
+            // 24.99992 away squared, f32 had it at 25.00…
+            (
+                (2107., 1554.),
+                (2036.7095, 1573.),
+                (2053.0962, 1573.75),
+                5.,
+                true,
+            ),
         ];
 
         for (a, b, c, r, int) in tests {
@@ -369,7 +369,7 @@ pub fn gui_main(problem_path: &std::path::Path, solver_name: &str) {
 
         if do_step && !state.done {
             if state.solver.is_none() {
-                let mut solver = create_solver(solver_name);
+                let mut solver = create_solver(solver_name).unwrap_or_else(|e| panic!("{}", e));
                 solver.initialize(&state.problem, SolutionDto::default());
                 state.solver = Some(solver);
             }
@@ -0,0 +1,125 @@
+/// Cheapest assignment of every row to its own column, with the Hungarian algorithm
+/// (shortest augmenting paths with potentials), in O(rows² · columns).
+///
+/// Returns the column of every row. There must be at least as many columns as rows.
+pub fn assign(rows: usize, columns: usize, cost: impl Fn(usize, usize) -> i64) -> Vec<usize> {
+    assert!(
+        rows <= columns,
+        "hungarian: {} rows for {} columns",
+        rows,
+        columns
+    );
+    const INF: i64 = i64::MAX;
+    // 1-based, row and column 0 are the start of the augmenting paths
+    let mut u = vec![0i64; rows + 1];
+    let mut v = vec![0i64; columns + 1];
+    // the row assigned to each column, 0 when free
+    let mut row_of = vec![0usize; columns + 1];
+    // the previous column on the path to each column
+    let mut way = vec![0usize; columns + 1];
+
+    for row in 1..=rows {
+        row_of[0] = row;
+        let mut j0 = 0;
+        let mut min_v = vec![INF; columns + 1];
+        let mut used = vec![false; columns + 1];
+        loop {
+            used[j0] = true;
+            let i0 = row_of[j0];
+            let mut delta = INF;
+            let mut j1 = 0;
+            for j in 1..=columns {
+                if used[j] {
+                    continue;
+                }
+                let reduced = cost(i0 - 1, j - 1) - u[i0] - v[j];
+                if reduced < min_v[j] {
+                    min_v[j] = reduced;
+                    way[j] = j0;
+                }
+                if min_v[j] < delta {
+                    delta = min_v[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=columns {
+                if used[j] {
+                    u[row_of[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_v[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if row_of[j0] == 0 {
+                break;
+            }
+        }
+        // flip the path
+        while j0 != 0 {
+            let j1 = way[j0];
+            row_of[j0] = row_of[j1];
+            j0 = j1;
+        }
+    }
+
+    let mut column_of = vec![0; rows];
+    for j in 1..=columns {
+        if row_of[j] != 0 {
+            column_of[row_of[j] - 1] = j - 1;
+        }
+    }
+    column_of
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COSTS: [[i64; 6]; 4] = [
+        [7, 53, 183, 439, 863, 497],
+        [497, 383, 563, 79, 973, 287],
+        [287, 63, 343, 169, 583, 0],
+        [627, 343, 773, 959, 943, 767],
+    ];
+
+    /// Totals of every assignment of the rows from `row` on, to the columns not `taken`
+    fn all_totals(row: usize, taken: &mut Vec<usize>, totals: &mut Vec<i64>) {
+        if row == COSTS.len() {
+            totals.push(total(taken));
+            return;
+        }
+        for column in 0..COSTS[0].len() {
+            if !taken.contains(&column) {
+                taken.push(column);
+                all_totals(row + 1, taken, totals);
+                taken.pop();
+            }
+        }
+    }
+
+    fn total(columns: &[usize]) -> i64 {
+        columns
+            .iter()
+            .enumerate()
+            .map(|(row, &column)| COSTS[row][column])
+            .sum()
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        let mut totals = vec![];
+        all_totals(0, &mut vec![], &mut totals);
+
+        let columns = assign(4, 6, |row, column| COSTS[row][column]);
+        let mut unique = columns.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 4);
+        assert_eq!(total(&columns), *totals.iter().min().unwrap());
+
+        // negative costs, as for values to maximize
+        let columns = assign(4, 6, |row, column| -COSTS[row][column]);
+        assert_eq!(total(&columns), *totals.iter().max().unwrap());
+    }
+}
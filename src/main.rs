@@ -1,13 +1,15 @@
 use std::path::Path;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{ffi::OsString, fs::DirEntry, path::PathBuf};
 
 use clap::Parser;
+use cmd::breakdown::*;
 use cmd::default::*;
+use cmd::solvers::*;
 use cmd::stats::*;
 use cmd::Args;
 use cmd::Commands;
-use dto::SolutionDto;
+use dto::{ProblemDto, SolutionDto};
 use env_logger::Env;
 use helpers::*;
 use solvers::Problem;
@@ -23,6 +25,7 @@ mod dto;
 mod geometry;
 mod gui;
 mod helpers;
+mod hungarian;
 mod scoring;
 mod solvers;
 
@@ -97,6 +100,19 @@ fn main() -> std::io::Result<()> {
     let gui = args.gui;
     // This is not the default because every solver is already parallel
     let parallel = args.parallel;
+    let options = RunOptions {
+        budgets: Budgets {
+            default: args.budget.map(Duration::from_secs),
+            per_problem: args
+                .problem_budget
+                .iter()
+                .map(|(problem, seconds)| (problem.clone(), Duration::from_secs(*seconds)))
+                .collect(),
+        },
+        checkpoint_interval: Some(Duration::from_secs(args.checkpoint_interval))
+            .filter(|d| !d.is_zero()),
+        seed: args.seed,
+    };
 
     env_logger::Builder::from_env(Env::default().default_filter_or(log_level)).init();
 
@@ -112,7 +128,10 @@ fn main() -> std::io::Result<()> {
             problems.sort_by_key(|x| x.parse::<u8>().unwrap());
             stats(&problems, &solvers.unwrap_or_else(list_current_solvers))
         }
+        Some(Commands::Solvers) => list_solvers(),
         Some(Commands::Score { problem, solution }) => {
+            // The judge sees all the attendees and pillars, not what is left after pruning
+            let raw_problem = ProblemDto::load(Path::new(problem))?;
             let problem = Problem::load(Path::new(problem))?;
             let solution = SolutionDto::load(Path::new(solution))?;
             let before_score = Instant::now();
@@ -130,21 +149,56 @@ fn main() -> std::io::Result<()> {
                 solution.volumes.as_ref(),
             );
             let fast_score_time = before_fast_score.elapsed();
+
+            let before_reference_score = Instant::now();
+            let reference_score = scoring::reference_scorer::ReferenceScorer.score(
+                &raw_problem,
+                &solution.placements,
+                solution.volumes.as_ref(),
+            );
+            let reference_score_time = before_reference_score.elapsed();
+
+            let judge_mark = |s: i64| {
+                if s == reference_score.0 {
+                    "matches judge".to_owned()
+                } else {
+                    format!("off by {:+}", s - reference_score.0)
+                }
+            };
             println!(
-                "     score: {:>10} {:>10}us",
+                "     score: {:>10} {:>10}us  {}",
                 score.0,
-                score_time.as_micros()
+                score_time.as_micros(),
+                judge_mark(score.0)
             );
             println!(
-                "fast score: {:>10} {:>10}us",
+                "fast score: {:>10} {:>10}us  {}",
                 fast_score.0,
-                fast_score_time.as_micros()
+                fast_score_time.as_micros(),
+                judge_mark(fast_score.0)
+            );
+            println!(
+                "judge score: {:>9} {:>10}us",
+                reference_score.0,
+                reference_score_time.as_micros()
             );
             Ok(())
         }
-        _ => {
-            let problem_paths = get_problem_paths(&args, false)?;
-            default_command(&problem_paths, solvers, gui, parallel)
-        }
+        Some(Commands::Breakdown {
+            problem,
+            solution,
+            json,
+            pairs,
+            top,
+        }) => breakdown(Path::new(problem), Path::new(solution), *json, *pairs, *top),
+        _ => match &args.resume {
+            Some(checkpoint_path) => {
+                resume(checkpoint_path, &options, Path::new(BASE_SOLUTION_DIR))
+            }
+            None => {
+                let problem_paths = get_problem_paths(&args, false)?;
+                default_command(&problem_paths, solvers, &options, gui, parallel)
+            }
+        },
     }
 }
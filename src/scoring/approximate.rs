@@ -1,13 +1,32 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+
 use crate::{
-    dto::{Attendee, ProblemDto},
+    dto::{Attendee, Point2D, ProblemDto},
     solvers::Problem,
 };
 
-use super::Scorer;
+use super::{breakdown::ScoreBreakdown, new_scorer::NewScorer, Scorer};
+
+/// Largest estimated error (as a share of the absolute score) when the cluster count is automatic
+const TARGET_ERROR: f64 = 0.05;
+/// Cluster count to start from when the cluster count is automatic, doubled until good enough
+const INITIAL_CLUSTERS: usize = 16;
+/// Distance the whole range of tastes is worth when clustering
+const TASTE_WEIGHT: f32 = 10.0;
+const KMEANS_ITERATIONS: usize = 25;
+/// Random solutions to estimate the error on when there is no solution yet
+const ERROR_SAMPLES: usize = 3;
 
+/// Scores against a smaller audience: the attendees are clustered by position (and a bit by taste),
+/// and each cluster is replaced by one attendee at its center with the sum of the tastes.
+///
+/// The impact is linear in the taste, so the only error comes from the distance (and blockage)
+/// of the members being different from the one of the center.
 #[derive(Clone)]
 pub struct ApproximateScorer {
     approximate_problem: ProblemDto,
+    estimated_error: f64,
 }
 
 impl Scorer for ApproximateScorer {
@@ -17,39 +36,241 @@ impl Scorer for ApproximateScorer {
         placements: &[crate::dto::Point2D],
         volumes: Option<&Vec<f32>>,
     ) -> crate::solvers::Score {
-        // NOTE: Same as the existing scorer, but less data (at least for now)
+        // NOTE: Same as the existing scorer, but less data
         super::new_scorer::NewScorer.score(&self.approximate_problem, placements, volumes)
     }
 }
 
 impl ApproximateScorer {
-    pub fn new(problem: &Problem) -> Self {
-        // let mut unified_attendees = vec![];
-        // let mut unified_attendee = problem.data.attendees[0]
-        let mut unified_attendee = problem
-            .data
-            .attendees
+    /// Clusters the attendees into `clusters` groups, or as few as needed to stay under
+    /// `TARGET_ERROR` when `None`. The error is estimated on `placements`, or on random
+    /// placements when there aren't any yet.
+    pub fn new(
+        problem: &Problem,
+        placements: &[Point2D],
+        clusters: Option<usize>,
+        seed: u64,
+    ) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let samples = if placements.is_empty() {
+            (0..ERROR_SAMPLES)
+                .map(|_| random_placements(&problem.data, &mut rng))
+                .collect()
+        } else {
+            vec![placements.to_vec()]
+        };
+        let exact = samples
             .iter()
-            .cloned()
-            .reduce(|a, b| Attendee {
-                x: a.x + b.x,
-                y: a.y + b.y,
-                tastes: a
-                    .tastes
-                    .iter()
-                    .zip(b.tastes.iter())
-                    .map(|(a, b)| a + b)
-                    .collect(),
-            })
-            .unwrap();
-        unified_attendee.x /= problem.data.attendees.len() as f32;
-        unified_attendee.y /= problem.data.attendees.len() as f32;
+            .map(|placements| ScoreBreakdown::new(&problem.data, placements, None, false))
+            .collect::<Vec<_>>();
 
-        ApproximateScorer {
-            approximate_problem: ProblemDto {
-                attendees: vec![unified_attendee],
+        let features = features(&problem.data.attendees);
+        let mut k = clusters.unwrap_or(INITIAL_CLUSTERS);
+        loop {
+            let attendees = if k >= features.len() {
+                problem.data.attendees.clone()
+            } else {
+                let assignment = kmeans(&features, k, &mut rng);
+                merge_clusters(&problem.data.attendees, &assignment, k)
+            };
+            let approximate_problem = ProblemDto {
+                attendees,
                 ..problem.data.clone()
-            },
+            };
+            let estimated_error = estimate_error(&approximate_problem, &samples, &exact);
+
+            let done = clusters.is_some()
+                || estimated_error <= TARGET_ERROR
+                || approximate_problem.attendees.len() == problem.data.attendees.len();
+            if done {
+                return ApproximateScorer {
+                    approximate_problem,
+                    estimated_error,
+                };
+            }
+            k *= 2;
+        }
+    }
+
+    pub fn clusters(&self) -> usize {
+        self.approximate_problem.attendees.len()
+    }
+
+    /// Largest difference with `NewScorer` seen, as a share of the sum of the absolute pair scores
+    pub fn estimated_error(&self) -> f64 {
+        self.estimated_error
+    }
+}
+
+fn random_placements(problem: &ProblemDto, rng: &mut StdRng) -> Vec<Point2D> {
+    let (left, bottom) = problem.stage_bottom_left;
+    problem
+        .musicians
+        .iter()
+        .map(|_| Point2D {
+            x: rng.gen_range(left + 10.0..=left + problem.stage_width - 10.0),
+            y: rng.gen_range(bottom + 10.0..=bottom + problem.stage_height - 10.0),
+        })
+        .collect()
+}
+
+/// Position, then the tastes scaled so that their whole range is worth `TASTE_WEIGHT`
+fn features(attendees: &[Attendee]) -> Vec<Vec<f32>> {
+    let (min, max) = attendees
+        .iter()
+        .flat_map(|a| a.tastes.iter())
+        .fold((f32::MAX, f32::MIN), |(min, max), t| {
+            (min.min(*t), max.max(*t))
+        });
+    let taste_scale = if max > min {
+        TASTE_WEIGHT / (max - min)
+    } else {
+        0.0
+    };
+    attendees
+        .iter()
+        .map(|a| {
+            let mut feature = vec![a.x, a.y];
+            feature.extend(a.tastes.iter().map(|t| t * taste_scale));
+            feature
+        })
+        .collect()
+}
+
+fn distance2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+fn nearest(centers: &[Vec<f32>], point: &[f32]) -> usize {
+    centers
+        .iter()
+        .map(|c| distance2(c, point))
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap()
+        .0
+}
+
+/// Lloyd's algorithm with k-means++ seeding, returns the cluster of every point
+fn kmeans(points: &[Vec<f32>], k: usize, rng: &mut StdRng) -> Vec<usize> {
+    let mut centers = vec![points[rng.gen_range(0..points.len())].clone()];
+    let mut closest = points
+        .iter()
+        .map(|p| distance2(p, &centers[0]))
+        .collect::<Vec<_>>();
+    while centers.len() < k {
+        let total: f32 = closest.iter().sum();
+        let next = if total > 0.0 {
+            let mut target = rng.gen_range(0.0..total);
+            closest
+                .iter()
+                .position(|d| {
+                    target -= d;
+                    target < 0.0
+                })
+                .unwrap_or(points.len() - 1)
+        } else {
+            // everybody is already a center
+            break;
+        };
+        centers.push(points[next].clone());
+        for (d, p) in closest.iter_mut().zip(points) {
+            *d = d.min(distance2(p, &points[next]));
+        }
+    }
+
+    let mut assignment = vec![usize::MAX; points.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let new_assignment = points
+            .par_iter()
+            .map(|p| nearest(&centers, p))
+            .collect::<Vec<_>>();
+        if new_assignment == assignment {
+            break;
+        }
+        assignment = new_assignment;
+
+        let mut sums = vec![vec![0f32; points[0].len()]; centers.len()];
+        let mut counts = vec![0usize; centers.len()];
+        for (p, cluster) in points.iter().zip(&assignment) {
+            counts[*cluster] += 1;
+            for (s, x) in sums[*cluster].iter_mut().zip(p) {
+                *s += x;
+            }
+        }
+        for ((center, sum), count) in centers.iter_mut().zip(sums).zip(counts) {
+            // an empty cluster keeps its center
+            if count > 0 {
+                *center = sum.into_iter().map(|s| s / count as f32).collect();
+            }
+        }
+    }
+    assignment
+}
+
+/// One attendee per non-empty cluster: at the center of its members, with the sum of their tastes
+fn merge_clusters(attendees: &[Attendee], assignment: &[usize], k: usize) -> Vec<Attendee> {
+    let taste_count = attendees[0].tastes.len();
+    let mut merged = vec![(0usize, 0f32, 0f32, vec![0f32; taste_count]); k];
+    for (attendee, cluster) in attendees.iter().zip(assignment) {
+        let (count, x, y, tastes) = &mut merged[*cluster];
+        *count += 1;
+        *x += attendee.x;
+        *y += attendee.y;
+        for (sum, taste) in tastes.iter_mut().zip(&attendee.tastes) {
+            *sum += taste;
+        }
+    }
+    merged
+        .into_iter()
+        .filter(|(count, ..)| *count > 0)
+        .map(|(count, x, y, tastes)| Attendee {
+            x: x / count as f32,
+            y: y / count as f32,
+            tastes,
+        })
+        .collect()
+}
+
+fn estimate_error(
+    approximate_problem: &ProblemDto,
+    samples: &[Vec<Point2D>],
+    exact: &[ScoreBreakdown],
+) -> f64 {
+    samples
+        .iter()
+        .zip(exact)
+        .map(|(placements, exact)| {
+            let approximate = NewScorer.score(approximate_problem, placements, None).0;
+            let scale: i64 = exact
+                .musicians
+                .iter()
+                .map(|m| m.positive - m.negative)
+                .sum();
+            (approximate - exact.total).abs() as f64 / scale.max(1) as f64
+        })
+        .fold(0.0, f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kmeans_separates_groups() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let points = (0..30)
+            .map(|i| {
+                let offset = (i / 10) as f32 * 100.0;
+                vec![offset + rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)]
+            })
+            .collect::<Vec<_>>();
+        let assignment = kmeans(&points, 3, &mut rng);
+        for group in assignment.chunks(10) {
+            assert!(group.iter().all(|c| *c == group[0]));
         }
+        assert_ne!(assignment[0], assignment[10]);
+        assert_ne!(assignment[10], assignment[20]);
+        assert_ne!(assignment[0], assignment[20]);
     }
 }
@@ -0,0 +1,62 @@
+use rayon::prelude::*;
+
+/// Dense matrix of bits, stored row by row so that rows can be updated in parallel
+#[derive(Default, Clone)]
+pub struct BitMatrix {
+    words_per_row: usize,
+    words: Vec<u64>,
+}
+
+impl BitMatrix {
+    /// All bits cleared
+    pub fn new(rows: usize, cols: usize) -> Self {
+        let words_per_row = cols.div_ceil(64);
+        BitMatrix {
+            words_per_row,
+            words: vec![0; rows * words_per_row],
+        }
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> bool {
+        get_bit(self.row(row), col)
+    }
+
+    pub fn row(&self, row: usize) -> &[u64] {
+        let start = row * self.words_per_row;
+        &self.words[start..start + self.words_per_row]
+    }
+
+    pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = &mut [u64]> {
+        self.words.par_chunks_mut(self.words_per_row.max(1))
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// Raw words, row by row, each row padded to a multiple of 64 bits
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Inverse of `words`, `None` if there aren't as many words as `rows` × `cols` needs
+    pub fn from_words(rows: usize, cols: usize, words: Vec<u64>) -> Option<Self> {
+        let words_per_row = cols.div_ceil(64);
+        (words.len() == rows * words_per_row).then_some(BitMatrix {
+            words_per_row,
+            words,
+        })
+    }
+}
+
+pub fn get_bit(row: &[u64], col: usize) -> bool {
+    row[col / 64] & (1 << (col % 64)) != 0
+}
+
+pub fn set_bit(row: &mut [u64], col: usize, value: bool) {
+    if value {
+        row[col / 64] |= 1 << (col % 64);
+    } else {
+        row[col / 64] &= !(1 << (col % 64));
+    }
+}
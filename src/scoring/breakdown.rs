@@ -0,0 +1,223 @@
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    collider::Collider,
+    dto::{Point2D, ProblemDto},
+};
+
+use super::new_scorer::{compute_closeness, contribution, impact};
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct MusicianBreakdown {
+    pub instrument: u32,
+    pub volume: f32,
+    pub closeness: f32,
+    pub score: i64,
+    pub positive: i64,
+    pub negative: i64,
+    pub blocked_attendees: usize,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct AttendeeBreakdown {
+    pub score: i64,
+    pub blocked_musicians: usize,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct InstrumentBreakdown {
+    pub musicians: usize,
+    pub score: i64,
+}
+
+/// Score of one attendee/musician pair, only the non-zero ones are listed
+#[derive(Serialize, Debug, Clone)]
+pub struct PairScore {
+    pub attendee: usize,
+    pub musician: usize,
+    pub score: i64,
+}
+
+/// Where the points of a solution come from, with the same numbers as `NewScorer`
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct ScoreBreakdown {
+    pub total: i64,
+    pub musicians: Vec<MusicianBreakdown>,
+    pub attendees: Vec<AttendeeBreakdown>,
+    pub instruments: Vec<InstrumentBreakdown>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pairs: Option<Vec<PairScore>>,
+}
+
+impl ScoreBreakdown {
+    pub fn new(
+        problem: &ProblemDto,
+        placements: &[Point2D],
+        volumes: Option<&Vec<f32>>,
+        with_pairs: bool,
+    ) -> Self {
+        let collider = Collider::new(problem, placements);
+        let has_pillars = !problem.pillars.is_empty();
+        let closeness = if has_pillars {
+            compute_closeness(problem, placements)
+        } else {
+            vec![1.0; problem.musicians.len()]
+        };
+        let volume = |musician_i: usize| volumes.map(|vs| vs[musician_i]).unwrap_or(1.0);
+
+        // (score, blocked) of every pair, row by row
+        let rows: Vec<Vec<Option<i64>>> = problem
+            .attendees
+            .par_iter()
+            .enumerate()
+            .map(|(attendee_i, attendee)| {
+                problem
+                    .musicians
+                    .iter()
+                    .enumerate()
+                    .map(|(musician_i, instrument)| {
+                        if collider.is_hidden(attendee_i, musician_i) {
+                            return None;
+                        }
+                        let impact = impact(attendee, instrument, &placements[musician_i]);
+                        Some(contribution(
+                            impact,
+                            closeness[musician_i],
+                            volume(musician_i),
+                            has_pillars,
+                        ))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut musicians: Vec<_> = problem
+            .musicians
+            .iter()
+            .enumerate()
+            .map(|(musician_i, instrument)| MusicianBreakdown {
+                instrument: instrument.0,
+                volume: volume(musician_i),
+                closeness: closeness[musician_i],
+                ..Default::default()
+            })
+            .collect();
+        let instrument_count = problem.musicians.iter().map(|i| i.0).max().unwrap() as usize + 1;
+        let mut instruments = vec![InstrumentBreakdown::default(); instrument_count];
+        let mut attendees = vec![];
+        let mut pairs = vec![];
+
+        for (attendee_i, row) in rows.iter().enumerate() {
+            let mut attendee = AttendeeBreakdown::default();
+            for (musician_i, pair) in row.iter().enumerate() {
+                let musician = &mut musicians[musician_i];
+                match pair {
+                    None => {
+                        attendee.blocked_musicians += 1;
+                        musician.blocked_attendees += 1;
+                    }
+                    Some(score) => {
+                        attendee.score += score;
+                        musician.score += score;
+                        if *score > 0 {
+                            musician.positive += score;
+                        } else {
+                            musician.negative += score;
+                        }
+                        if with_pairs && *score != 0 {
+                            pairs.push(PairScore {
+                                attendee: attendee_i,
+                                musician: musician_i,
+                                score: *score,
+                            });
+                        }
+                    }
+                }
+            }
+            attendees.push(attendee);
+        }
+
+        for musician in &musicians {
+            let instrument = &mut instruments[musician.instrument as usize];
+            instrument.musicians += 1;
+            instrument.score += musician.score;
+        }
+
+        ScoreBreakdown {
+            total: musicians.iter().map(|m| m.score).sum(),
+            musicians,
+            attendees,
+            instruments,
+            pairs: with_pairs.then_some(pairs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dto::{Attendee, Instrument, PillarDto},
+        scoring::{new_scorer::NewScorer, Scorer},
+    };
+
+    #[test]
+    fn test_totals_add_up() {
+        let mut problem = ProblemDto {
+            room_width: 300.0,
+            room_height: 300.0,
+            stage_width: 100.0,
+            stage_height: 100.0,
+            stage_bottom_left: (100.0, 100.0),
+            musicians: vec![Instrument(0), Instrument(1), Instrument(0)],
+            attendees: vec![
+                Attendee {
+                    x: 150.0,
+                    y: 250.0,
+                    tastes: vec![1000.0, -500.0],
+                },
+                Attendee {
+                    x: 20.0,
+                    y: 130.0,
+                    tastes: vec![-200.0, 3000.0],
+                },
+            ],
+            pillars: vec![],
+        };
+        let placements = vec![
+            Point2D { x: 140.0, y: 180.0 },
+            Point2D { x: 120.0, y: 150.0 },
+            Point2D { x: 160.0, y: 180.0 },
+        ];
+        let volumes = vec![10.0, 3.0, 7.5];
+        let no_pillars = problem.clone();
+        // in the way of the first attendee and the first musician, and closeness counts
+        problem.pillars.push(PillarDto {
+            center: (142.0, 200.0),
+            radius: 4.0,
+        });
+
+        for problem in [no_pillars, problem] {
+            let breakdown = ScoreBreakdown::new(&problem, &placements, Some(&volumes), true);
+            let score = NewScorer.score(&problem, &placements, Some(&volumes));
+            let blocked = usize::from(!problem.pillars.is_empty());
+            assert_eq!(breakdown.attendees[0].blocked_musicians, blocked);
+            assert_eq!(breakdown.total, score.0);
+            assert_eq!(
+                breakdown.musicians.iter().map(|m| m.score).sum::<i64>(),
+                score.0
+            );
+            assert_eq!(
+                breakdown.attendees.iter().map(|a| a.score).sum::<i64>(),
+                score.0
+            );
+            assert_eq!(
+                breakdown.instruments.iter().map(|i| i.score).sum::<i64>(),
+                score.0
+            );
+            let pairs = breakdown.pairs.unwrap();
+            assert_eq!(pairs.iter().map(|p| p.score).sum::<i64>(), score.0);
+        }
+    }
+}
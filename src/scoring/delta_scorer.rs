@@ -0,0 +1,464 @@
+use rayon::prelude::*;
+
+use crate::{
+    collider::{Collider, OwnedCollider},
+    dto::{Attendee, Point2D, ProblemDto},
+    solvers::Score,
+};
+
+use super::{
+    new_scorer::{compute_instrument_closeness, contribution, impact, musicians_per_instrument},
+    scorer::is_sound_blocked,
+};
+
+/// Stateful scorer which answers "what if" questions about small changes to a solution
+/// without scoring the whole thing again. The numbers are the same as the ones of `NewScorer`.
+///
+/// Evaluating a change is O(attendees × musicians) instead of O(attendees × musicians × obstacles).
+#[derive(Default, Clone)]
+pub struct DeltaScorer {
+    problem: ProblemDto,
+    placements: Vec<Point2D>,
+    volumes: Vec<f32>,
+    has_pillars: bool,
+    musicians_per_ins: Vec<Vec<usize>>,
+    closeness: Vec<f32>,
+    // Indexed by [attendee * musicians + musician]
+    impacts: Vec<f32>,
+    // Number of musicians and pillars between the attendee and the musician
+    blockers: Vec<u16>,
+    // Kept in sync with `placements`, to count the blockers of the musicians that moved
+    collider: OwnedCollider,
+    score: i64,
+}
+
+impl DeltaScorer {
+    pub fn new(problem: &ProblemDto, placements: &[Point2D], volumes: Option<&Vec<f32>>) -> Self {
+        assert!(
+            problem.musicians.len() + problem.pillars.len() < u16::MAX as usize,
+            "too many obstacles for the blockers counter"
+        );
+        let musician_count = problem.musicians.len();
+        let has_pillars = !problem.pillars.is_empty();
+        let musicians_per_ins = musicians_per_instrument(problem);
+
+        let mut closeness = vec![1f32; musician_count];
+        if has_pillars {
+            for ins_musicians in &musicians_per_ins {
+                let ins_closeness = compute_instrument_closeness(placements, ins_musicians);
+                for (musician_i, c) in ins_musicians.iter().zip(ins_closeness) {
+                    closeness[*musician_i] = c;
+                }
+            }
+        }
+
+        let collider = Collider::new_owned(problem.clone(), placements.to_vec());
+        let (impacts, blockers): (Vec<Vec<f32>>, Vec<Vec<u16>>) = problem
+            .attendees
+            .par_iter()
+            .enumerate()
+            .map(|(attendee_i, attendee)| {
+                let impacts = problem
+                    .musicians
+                    .iter()
+                    .zip(placements)
+                    .map(|(instrument, placement)| impact(attendee, instrument, placement))
+                    .collect();
+                let blockers = (0..musician_count)
+                    .map(|musician_i| collider.count_blockers(attendee_i, musician_i))
+                    .collect();
+                (impacts, blockers)
+            })
+            .unzip();
+
+        let mut scorer = DeltaScorer {
+            problem: problem.clone(),
+            placements: placements.to_vec(),
+            volumes: volumes
+                .cloned()
+                .unwrap_or_else(|| vec![1.0; musician_count]),
+            has_pillars,
+            musicians_per_ins,
+            closeness,
+            impacts: impacts.concat(),
+            blockers: blockers.concat(),
+            collider,
+            score: 0,
+        };
+        scorer.score = scorer.full_score();
+        scorer
+    }
+
+    pub fn score(&self) -> Score {
+        Score(self.score)
+    }
+
+    /// Score change if `musician` moves to `location`
+    pub fn move_delta(&self, musician: usize, location: Point2D) -> i64 {
+        self.delta(&[(musician, location)])
+    }
+
+    /// Score change if musicians `musician_a` and `musician_b` swap places
+    pub fn swap_delta(&self, musician_a: usize, musician_b: usize) -> i64 {
+        if self.is_noop_swap(musician_a, musician_b) {
+            return 0;
+        }
+        self.delta(&self.swap_changes(musician_a, musician_b))
+    }
+
+    /// Moves `musician` to `location`, returns the score change
+    pub fn apply_move(&mut self, musician: usize, location: Point2D) -> i64 {
+        self.apply(&[(musician, location)])
+    }
+
+    /// Swaps the places of `musician_a` and `musician_b`, returns the score change
+    pub fn apply_swap(&mut self, musician_a: usize, musician_b: usize) -> i64 {
+        if self.is_noop_swap(musician_a, musician_b) {
+            return 0;
+        }
+        self.apply(&self.swap_changes(musician_a, musician_b))
+    }
+
+    /// Sets the volume of `musician`, returns the score change
+    pub fn set_volume(&mut self, musician: usize, volume: f32) -> i64 {
+        let musician_count = self.placements.len();
+        let column_score = |this: &Self| -> i64 {
+            (0..this.problem.attendees.len())
+                .map(|attendee_i| {
+                    this.pair_score(
+                        attendee_i * musician_count + musician,
+                        this.closeness[musician],
+                    )
+                })
+                .sum()
+        };
+        let old = column_score(self);
+        self.volumes[musician] = volume;
+        let delta = column_score(self) - old;
+        self.score += delta;
+        delta
+    }
+
+    /// What every musician brings to the score
+    pub fn musician_scores(&self) -> Vec<i64> {
+        let musician_count = self.placements.len();
+        let mut scores = vec![0; musician_count];
+        for idx in 0..self.impacts.len() {
+            let musician_i = idx % musician_count;
+            scores[musician_i] += self.pair_score(idx, self.closeness[musician_i]);
+        }
+        scores
+    }
+
+    /// Whether nothing is between the attendee and the musician
+    pub fn is_audible(&self, attendee_i: usize, musician_i: usize) -> bool {
+        self.blockers[attendee_i * self.placements.len() + musician_i] == 0
+    }
+
+    /// Score change if every musician in `changes` is moved to the given location at once
+    pub fn delta(&self, changes: &[(usize, Point2D)]) -> i64 {
+        let closeness = self.closeness_after(changes);
+        let musician_count = self.placements.len();
+
+        self.problem
+            .attendees
+            .par_iter()
+            .enumerate()
+            .map(|(attendee_i, attendee)| {
+                let row = attendee_i * musician_count;
+                let mut delta = 0;
+                #[allow(clippy::needless_range_loop)]
+                for musician_i in 0..musician_count {
+                    let old = self.pair_score(row + musician_i, self.closeness[musician_i]);
+                    let new = match changes.iter().find(|(k, _)| *k == musician_i) {
+                        Some((_, location)) => {
+                            if self.count_blockers_after(attendee, musician_i, location, changes)
+                                == 0
+                            {
+                                let impact =
+                                    impact(attendee, &self.problem.musicians[musician_i], location);
+                                self.contribution(impact, closeness[musician_i], musician_i)
+                            } else {
+                                0
+                            }
+                        }
+                        None => {
+                            let blockers = self.blockers_after(attendee, row, musician_i, changes);
+                            if blockers == 0 {
+                                self.contribution(
+                                    self.impacts[row + musician_i],
+                                    closeness[musician_i],
+                                    musician_i,
+                                )
+                            } else {
+                                0
+                            }
+                        }
+                    };
+                    delta += new - old;
+                }
+                delta
+            })
+            .sum()
+    }
+
+    /// Moves every musician in `changes` to the given location at once, returns the score change
+    pub fn apply(&mut self, changes: &[(usize, Point2D)]) -> i64 {
+        let delta = self.delta(changes);
+        let closeness = self.closeness_after(changes);
+        let musician_count = self.placements.len();
+        self.collider.move_musicians(changes);
+
+        let this = &*self;
+        let (impacts, blockers): (Vec<Vec<f32>>, Vec<Vec<u16>>) = this
+            .problem
+            .attendees
+            .par_iter()
+            .enumerate()
+            .map(|(attendee_i, attendee)| {
+                let row = attendee_i * musician_count;
+                let mut impacts = this.impacts[row..row + musician_count].to_vec();
+                let mut blockers = this.blockers[row..row + musician_count].to_vec();
+                for musician_i in 0..musician_count {
+                    match changes.iter().find(|(k, _)| *k == musician_i) {
+                        Some((_, location)) => {
+                            impacts[musician_i] =
+                                impact(attendee, &this.problem.musicians[musician_i], location);
+                            blockers[musician_i] =
+                                this.collider.count_blockers(attendee_i, musician_i);
+                        }
+                        None => {
+                            blockers[musician_i] =
+                                this.blockers_after(attendee, row, musician_i, changes);
+                        }
+                    }
+                }
+                (impacts, blockers)
+            })
+            .unzip();
+
+        self.impacts = impacts.concat();
+        self.blockers = blockers.concat();
+        self.closeness = closeness;
+        for (musician_i, location) in changes {
+            self.placements[*musician_i] = *location;
+        }
+        self.score += delta;
+        debug_assert_eq!(self.score, self.full_score());
+        delta
+    }
+
+    fn full_score(&self) -> i64 {
+        (0..self.impacts.len())
+            .into_par_iter()
+            .map(|idx| {
+                let musician_i = idx % self.placements.len();
+                self.pair_score(idx, self.closeness[musician_i])
+            })
+            .sum()
+    }
+
+    fn contribution(&self, impact: f32, closeness: f32, musician_i: usize) -> i64 {
+        contribution(
+            impact,
+            closeness,
+            self.volumes[musician_i],
+            self.has_pillars,
+        )
+    }
+
+    /// Current score of the pair at `idx` with the given closeness
+    fn pair_score(&self, idx: usize, closeness: f32) -> i64 {
+        if self.blockers[idx] != 0 {
+            return 0;
+        }
+        let musician_i = idx % self.placements.len();
+        self.contribution(self.impacts[idx], closeness, musician_i)
+    }
+
+    /// Blockers between the attendee and an unmoved musician once `changes` are applied
+    fn blockers_after(
+        &self,
+        attendee: &Attendee,
+        row: usize,
+        musician_i: usize,
+        changes: &[(usize, Point2D)],
+    ) -> u16 {
+        let musician_location = &self.placements[musician_i];
+        let mut blockers = self.blockers[row + musician_i];
+        for (moved_i, location) in changes {
+            if is_sound_blocked(musician_location, &self.placements[*moved_i], 5.0, attendee) {
+                blockers -= 1;
+            }
+            if is_sound_blocked(musician_location, location, 5.0, attendee) {
+                blockers += 1;
+            }
+        }
+        blockers
+    }
+
+    /// Blockers between the attendee and a moved musician once `changes` are applied
+    fn count_blockers_after(
+        &self,
+        attendee: &Attendee,
+        musician_i: usize,
+        musician_location: &Point2D,
+        changes: &[(usize, Point2D)],
+    ) -> u16 {
+        let mut blockers = 0;
+        for (other_i, other_location) in self.placements.iter().enumerate() {
+            if other_i == musician_i {
+                continue;
+            }
+            let other_location = changes
+                .iter()
+                .find(|(k, _)| *k == other_i)
+                .map(|(_, l)| l)
+                .unwrap_or(other_location);
+            if is_sound_blocked(musician_location, other_location, 5.0, attendee) {
+                blockers += 1;
+            }
+        }
+        for pillar in &self.problem.pillars {
+            if is_sound_blocked(musician_location, &pillar.center, pillar.radius, attendee) {
+                blockers += 1;
+            }
+        }
+        blockers
+    }
+
+    fn closeness_after(&self, changes: &[(usize, Point2D)]) -> Vec<f32> {
+        let mut closeness = self.closeness.clone();
+        if !self.has_pillars {
+            return closeness;
+        }
+
+        let mut placements = self.placements.clone();
+        for (musician_i, location) in changes {
+            placements[*musician_i] = *location;
+        }
+        let mut instruments = changes
+            .iter()
+            .map(|(musician_i, _)| self.problem.musicians[*musician_i].0 as usize)
+            .collect::<Vec<_>>();
+        instruments.sort_unstable();
+        instruments.dedup();
+        for instrument in instruments {
+            let ins_musicians = &self.musicians_per_ins[instrument];
+            let ins_closeness = compute_instrument_closeness(&placements, ins_musicians);
+            for (musician_i, c) in ins_musicians.iter().zip(ins_closeness) {
+                closeness[*musician_i] = c;
+            }
+        }
+        closeness
+    }
+
+    fn swap_changes(&self, musician_a: usize, musician_b: usize) -> [(usize, Point2D); 2] {
+        [
+            (musician_a, self.placements[musician_b]),
+            (musician_b, self.placements[musician_a]),
+        ]
+    }
+
+    /// Swapping two musicians with the same instrument and volume changes nothing
+    fn is_noop_swap(&self, musician_a: usize, musician_b: usize) -> bool {
+        musician_a == musician_b
+            || (self.problem.musicians[musician_a] == self.problem.musicians[musician_b]
+                && self.volumes[musician_a] == self.volumes[musician_b])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        dto::{Instrument, PillarDto},
+        scoring::{new_scorer::NewScorer, Scorer},
+    };
+
+    fn random_problem(rng: &mut StdRng, with_pillars: bool) -> ProblemDto {
+        let attendees = (0..40)
+            .map(|_| Attendee {
+                x: rng.gen_range(0.0..200.0),
+                y: rng.gen_range(150.0..200.0),
+                tastes: (0..3).map(|_| rng.gen_range(-1000.0..1000.0)).collect(),
+            })
+            .collect();
+        let pillars = if with_pillars {
+            vec![PillarDto {
+                center: (100.0, 140.0),
+                radius: 5.0,
+            }]
+        } else {
+            vec![]
+        };
+        ProblemDto {
+            room_width: 200.0,
+            room_height: 200.0,
+            stage_width: 200.0,
+            stage_height: 130.0,
+            stage_bottom_left: (0.0, 0.0),
+            musicians: (0..12).map(|i| Instrument(i % 3)).collect(),
+            attendees,
+            pillars,
+        }
+    }
+
+    fn random_location(rng: &mut StdRng) -> Point2D {
+        Point2D {
+            x: rng.gen_range(10.0..190.0),
+            y: rng.gen_range(10.0..120.0),
+        }
+    }
+
+    #[test]
+    fn test_matches_new_scorer() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for with_pillars in [false, true] {
+            let problem = random_problem(&mut rng, with_pillars);
+            let mut placements = (0..problem.musicians.len())
+                .map(|_| random_location(&mut rng))
+                .collect::<Vec<_>>();
+            let volumes = (0..problem.musicians.len())
+                .map(|_| rng.gen_range(0.0..10.0))
+                .collect::<Vec<_>>();
+            let mut scorer = DeltaScorer::new(&problem, &placements, Some(&volumes));
+            let mut score = NewScorer.score(&problem, &placements, Some(&volumes)).0;
+            assert_eq!(scorer.score().0, score);
+
+            for step in 0..50 {
+                let musician_a = rng.gen_range(0..placements.len());
+                let (delta, applied) = if step % 2 == 0 {
+                    let location = random_location(&mut rng);
+                    placements[musician_a] = location;
+                    (
+                        scorer.move_delta(musician_a, location),
+                        scorer.apply_move(musician_a, location),
+                    )
+                } else {
+                    let musician_b = rng.gen_range(0..placements.len());
+                    placements.swap(musician_a, musician_b);
+                    (
+                        scorer.swap_delta(musician_a, musician_b),
+                        scorer.apply_swap(musician_a, musician_b),
+                    )
+                };
+                let new_score = NewScorer.score(&problem, &placements, Some(&volumes)).0;
+                assert_eq!(delta, new_score - score, "step {}", step);
+                assert_eq!(applied, delta, "step {}", step);
+                assert_eq!(scorer.score().0, new_score, "step {}", step);
+                score = new_score;
+            }
+
+            let mut volumes = volumes;
+            volumes[3] = 0.0;
+            let delta = scorer.set_volume(3, 0.0);
+            let new_score = NewScorer.score(&problem, &placements, Some(&volumes)).0;
+            assert_eq!(delta, new_score - score);
+            assert_eq!(scorer.score().0, new_score);
+            assert_eq!(scorer.musician_scores().iter().sum::<i64>(), new_score);
+        }
+    }
+}
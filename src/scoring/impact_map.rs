@@ -1,19 +1,30 @@
-use std::{collections::HashSet, iter::repeat};
+use std::{collections::HashSet, fs};
 
+use log::debug;
 use rayon::prelude::*;
 
 use crate::{
     common::Grid,
     dto::{Attendee, Instrument, PillarDto, Point2D},
-    solvers::Score,
+    geometry::distance2,
+    solvers::{Problem, Score},
 };
 
-use super::scorer::{calculate_impact, is_sound_blocked};
+use super::{
+    bit_matrix::{set_bit, BitMatrix},
+    scorer::{calculate_impact, is_sound_blocked},
+};
 
 #[derive(Clone)]
 pub struct ImpactMap {
+    /// Impact of the attendees who can still hear each position
     pub scores: Vec<Score>,
+    // Only with pillars: Σ 1/d to the musicians of the instrument already placed
+    closeness: Vec<f64>,
+    // Only with pillars: what the musicians already placed would gain from their closeness
+    peer_bonus: Vec<f64>,
     pub best_score_pos_idx: usize,
+    /// Best value, including the closeness to the musicians already placed
     pub best_score: Score,
 }
 
@@ -31,13 +42,15 @@ impl ImpactMap {
             scores.push(score);
         }
 
-        let (best_score_pos_idx, best_score) = Self::get_best_score(&scores, grid);
-
-        ImpactMap {
+        let mut impact_map = ImpactMap {
+            closeness: vec![0.0; scores.len()],
+            peer_bonus: vec![0.0; scores.len()],
             scores,
-            best_score_pos_idx,
-            best_score,
-        }
+            best_score_pos_idx: 0,
+            best_score: Score(0),
+        };
+        impact_map.update_best_score(grid);
+        impact_map
     }
 
     fn score_instrument(
@@ -58,37 +71,43 @@ impl ImpactMap {
         Score(score)
     }
 
-    fn get_best_score(scores: &[Score], grid: &Grid) -> (usize, Score) {
-        let best = scores
-            .iter()
-            .zip(&grid.positions)
-            .enumerate()
-            .filter(|(_idx, (_s, p))| !p.taken)
-            .max_by_key(|(_idx, (s, _p))| s.0)
-            .unwrap();
-        (best.0, *best.1 .0)
+    /// What placing a musician at the position is worth, with the closeness of the pillar problems
+    fn value(&self, idx: usize) -> i64 {
+        (self.scores[idx].0 as f64 * (1.0 + self.closeness[idx]) + self.peer_bonus[idx]) as i64
     }
 
-    pub fn calculate_blocked_positions(
-        new_pos: &Point2D,
-        attendees: &[Attendee],
-        grid: &Grid,
-    ) -> Vec<(usize, usize)> {
-        grid.positions
-            .par_iter()
-            .enumerate()
-            // We don't care for those anymore, so can keep them invalid
-            .filter(|(_idx, pos)| !pos.taken)
-            .flat_map(|(idx, pos)| {
-                let mut result = vec![];
-                for (idx_attendee, attendee) in attendees.iter().enumerate() {
-                    if is_sound_blocked(&pos.p, new_pos, 5.0, attendee) {
-                        result.push((idx, idx_attendee));
-                    }
-                }
-                result
-            })
-            .collect()
+    /// `best_score` is `i64::MIN` when every position is taken
+    fn update_best_score(&mut self, grid: &Grid) {
+        let (best_score_pos_idx, best_score) = (0..self.scores.len())
+            .filter(|idx| !grid.positions[*idx].taken)
+            .map(|idx| (idx, self.value(idx)))
+            .max_by_key(|(_idx, value)| *value)
+            .unwrap_or((usize::MAX, i64::MIN));
+        self.best_score_pos_idx = best_score_pos_idx;
+        self.best_score = Score(best_score);
+    }
+
+    /// A musician of this instrument was placed at `idx_pos`: every other position gets closer
+    /// to it, and placing one there would also raise the value of the one at `idx_pos`.
+    pub fn add_peer(&mut self, grid: &Grid, idx_pos: usize) {
+        let peer = grid.positions[idx_pos].p;
+        let peer_score = self.scores[idx_pos].0 as f64;
+        self.add_peer_at(grid, &peer, peer_score);
+    }
+
+    /// Same as `add_peer` for a musician anywhere, worth `peer_score` on its own
+    pub fn add_peer_at(&mut self, grid: &Grid, peer: &Point2D, peer_score: f64) {
+        self.closeness
+            .par_iter_mut()
+            .zip(&mut self.peer_bonus)
+            .zip(&grid.positions)
+            .filter(|(_, pos)| !pos.taken)
+            .for_each(|((closeness, peer_bonus), pos)| {
+                let inv_distance = 1.0 / (distance2(pos, peer) as f64).sqrt();
+                *closeness += inv_distance;
+                *peer_bonus += peer_score * inv_distance;
+            });
+        self.update_best_score(grid);
     }
 
     pub fn update(
@@ -97,14 +116,10 @@ impl ImpactMap {
         attendees: &[Attendee],
         grid: &Grid,
         new_taken_positions: &HashSet<usize>,
-        blocked_positions: &[(usize, usize)],
-        pillar_blockage: &PillarBlockageMap,
+        newly_hidden: &[(usize, usize)],
     ) {
         let mut needs_best_score_update = new_taken_positions.contains(&self.best_score_pos_idx);
-        for (idx, idx_attendee) in blocked_positions {
-            if pillar_blockage.is_sound_blocked(*idx, *idx_attendee) {
-                continue;
-            }
+        for (idx, idx_attendee) in newly_hidden {
             let pos = &grid.positions[*idx];
             let attendee = &attendees[*idx_attendee];
             self.scores[*idx].0 -= calculate_impact(attendee, instrument, &pos.p);
@@ -113,16 +128,16 @@ impl ImpactMap {
             }
         }
         if needs_best_score_update {
-            let (best_score_pos_idx, best_score) = Self::get_best_score(&self.scores, grid);
-            self.best_score_pos_idx = best_score_pos_idx;
-            self.best_score = best_score;
+            self.update_best_score(grid);
         }
     }
 }
 
+/// Which attendees can't hear the grid positions because of a pillar: one row of bits per position
 #[derive(Default, Clone)]
 pub struct PillarBlockageMap {
-    pub blocked_positions: HashSet<(usize, usize)>,
+    // None when there are no pillars
+    blocked: Option<BitMatrix>,
 }
 
 impl PillarBlockageMap {
@@ -130,25 +145,234 @@ impl PillarBlockageMap {
         if pillars.is_empty() {
             return PillarBlockageMap::default();
         }
-        let blocked_positions = (0..grid.positions.len())
-            .flat_map(|idx_pos| repeat(idx_pos).zip(0..attendees.len()))
-            .par_bridge()
-            .filter(|(idx_pos, idx_attendee)| {
-                pillars.iter().any(|p| {
-                    is_sound_blocked(
-                        &grid.positions[*idx_pos].p,
-                        &p.center,
-                        p.radius,
-                        &attendees[*idx_attendee],
-                    )
+        let mut blocked = BitMatrix::new(grid.positions.len(), attendees.len());
+        blocked
+            .par_rows_mut()
+            .zip(&grid.positions)
+            .for_each(|(row, pos)| {
+                for (idx_attendee, attendee) in attendees.iter().enumerate() {
+                    if pillars
+                        .iter()
+                        .any(|p| is_sound_blocked(&pos.p, &p.center, p.radius, attendee))
+                    {
+                        set_bit(row, idx_attendee, true);
+                    }
+                }
+            });
+
+        PillarBlockageMap {
+            blocked: Some(blocked),
+        }
+    }
+
+    /// Same as `new`, cached in `problems_extra/`. The file is named after the shape of the grid
+    /// and a fingerprint of the grid, pillars and attendees, so that two grids of the same shape
+    /// (the `N×1` rows of cells of assign) have their own. It starts with a version and the
+    /// fingerprint, and is built again when they don't match.
+    pub fn load_or_new(problem: &Problem, grid: &Grid) -> Self {
+        let data = &problem.data;
+        let fingerprint = fingerprint(grid, &data.pillars, &data.attendees);
+        let path = problem.extra_data_path(&format!(
+            "pillar_blockage_{}x{}_{:016x}.bin",
+            grid.width, grid.height, fingerprint
+        ));
+        let Some(path) = path.filter(|_| !data.pillars.is_empty()) else {
+            return Self::new(grid, &data.pillars, &data.attendees);
+        };
+
+        let rows = grid.positions.len();
+        let cols = data.attendees.len();
+        let header = [CACHE_VERSION, fingerprint];
+        let cached = fs::read(&path).ok().map(|bytes| from_bytes(&bytes));
+        match cached {
+            Some(words) if words.starts_with(&header) => {
+                if let Some(blocked) =
+                    BitMatrix::from_words(rows, cols, words[header.len()..].to_vec())
+                {
+                    debug!("pillar_blockage({}): loaded from cache", problem.id);
+                    return PillarBlockageMap {
+                        blocked: Some(blocked),
+                    };
+                }
+            }
+            Some(_) => debug!(
+                "pillar_blockage({}): cached by another version, building it again",
+                problem.id
+            ),
+            None => {}
+        }
+
+        let map = Self::new(grid, &data.pillars, &data.attendees);
+        let words = map.blocked.as_ref().unwrap().words();
+        let bytes = [to_bytes(&header), to_bytes(words)].concat();
+        if let Err(e) = fs::write(&path, bytes) {
+            debug!("pillar_blockage({}): can't cache: {}", problem.id, e);
+        }
+        map
+    }
+
+    pub fn is_sound_blocked(&self, idx_pos: usize, idx_attendee: usize) -> bool {
+        self.blocked
+            .as_ref()
+            .is_some_and(|b| b.get(idx_pos, idx_attendee))
+    }
+
+    pub fn blocked_pairs(&self) -> usize {
+        self.blocked.as_ref().map_or(0, |b| b.count_ones())
+    }
+}
+
+/// First word of the cache files, bumped when their layout changes
+const CACHE_VERSION: u64 = 1;
+
+/// FNV-1a of everything the blockage depends on, stable across runs unlike `DefaultHasher`:
+/// the cache files of other grids with the same shape, or of other attendees (pruned or not),
+/// don't match it
+fn fingerprint(grid: &Grid, pillars: &[PillarDto], attendees: &[Attendee]) -> u64 {
+    let counts = [grid.positions.len(), pillars.len(), attendees.len()];
+    let positions = grid.positions.iter().flat_map(|pos| [pos.p.x, pos.p.y]);
+    let pillars = pillars
+        .iter()
+        .flat_map(|p| [p.center.0, p.center.1, p.radius]);
+    let attendees = attendees.iter().flat_map(|a| [a.x, a.y]);
+    let values = positions.chain(pillars).chain(attendees);
+    counts
+        .iter()
+        .map(|&c| c as u64)
+        .chain(values.map(|v| v.to_bits() as u64))
+        .fold(0xcbf29ce484222325, |hash, word| {
+            (hash ^ word).wrapping_mul(0x100000001b3)
+        })
+}
+
+fn to_bytes(words: &[u64]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_le_bytes()).collect()
+}
+
+fn from_bytes(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks_exact(8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{common::Position, dto::ProblemDto};
+
+    fn row_grid(y: f32) -> Grid {
+        Grid {
+            positions: [120.0, 150.0, 180.0]
+                .iter()
+                .map(|&x| Position {
+                    p: Point2D { x, y },
+                    taken: false,
                 })
+                .collect(),
+            width: 3,
+            height: 1,
+        }
+    }
+
+    fn cache_files(dir: &Path) -> Vec<std::path::PathBuf> {
+        let mut files: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.contains("pillar_blockage")
             })
             .collect();
+        files.sort();
+        files
+    }
 
-        PillarBlockageMap { blocked_positions }
+    fn bits(map: &PillarBlockageMap, grid: &Grid, attendees: usize) -> Vec<bool> {
+        (0..grid.positions.len())
+            .flat_map(|pos| (0..attendees).map(move |a| map.is_sound_blocked(pos, a)))
+            .collect()
     }
 
-    pub fn is_sound_blocked(&self, idx_pos: usize, idx_attendee: usize) -> bool {
-        self.blocked_positions.contains(&(idx_pos, idx_attendee))
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join(format!("pillar_blockage_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("problems")).unwrap();
+        fs::create_dir_all(dir.join("problems_extra")).unwrap();
+        let data = ProblemDto {
+            room_width: 300.0,
+            room_height: 300.0,
+            stage_width: 100.0,
+            stage_height: 100.0,
+            stage_bottom_left: (100.0, 100.0),
+            musicians: vec![Instrument(0)],
+            attendees: vec![
+                Attendee {
+                    x: 150.0,
+                    y: 250.0,
+                    tastes: vec![1000.0],
+                },
+                Attendee {
+                    x: 20.0,
+                    y: 150.0,
+                    tastes: vec![1000.0],
+                },
+            ],
+            pillars: vec![PillarDto {
+                center: (150.0, 220.0),
+                radius: 5.0,
+            }],
+        };
+        let problem_path = dir.join("problems").join("1.json");
+        fs::write(&problem_path, serde_json::to_string(&data).unwrap()).unwrap();
+        let problem = Problem::load(&problem_path).unwrap();
+        let attendees = problem.data.attendees.len();
+        let extra = dir.join("problems_extra");
+
+        let grid = row_grid(150.0);
+        let fresh = PillarBlockageMap::new(&grid, &problem.data.pillars, &problem.data.attendees);
+        assert!(fresh.blocked_pairs() > 0);
+        let built = PillarBlockageMap::load_or_new(&problem, &grid);
+        assert_eq!(
+            bits(&built, &grid, attendees),
+            bits(&fresh, &grid, attendees)
+        );
+        let files = cache_files(&extra);
+        assert_eq!(files.len(), 1);
+
+        // the next run reads the file: flip a bit of it to tell
+        let mut words = from_bytes(&fs::read(&files[0]).unwrap());
+        words[2] ^= 1;
+        fs::write(&files[0], to_bytes(&words)).unwrap();
+        let loaded = PillarBlockageMap::load_or_new(&problem, &grid);
+        assert_ne!(
+            bits(&loaded, &grid, attendees),
+            bits(&fresh, &grid, attendees)
+        );
+
+        // another version is built again, and written over
+        words[0] = CACHE_VERSION + 1;
+        fs::write(&files[0], to_bytes(&words)).unwrap();
+        let rebuilt = PillarBlockageMap::load_or_new(&problem, &grid);
+        assert_eq!(
+            bits(&rebuilt, &grid, attendees),
+            bits(&fresh, &grid, attendees)
+        );
+        assert_eq!(from_bytes(&fs::read(&files[0]).unwrap())[0], CACHE_VERSION);
+
+        // a grid of the same shape has its own file
+        let other_grid = row_grid(190.0);
+        PillarBlockageMap::load_or_new(&problem, &other_grid);
+        assert_eq!(cache_files(&extra).len(), 2);
+        let header = [
+            CACHE_VERSION,
+            fingerprint(&grid, &problem.data.pillars, &problem.data.attendees),
+        ];
+        assert!(from_bytes(&fs::read(&files[0]).unwrap()).starts_with(&header));
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -5,12 +5,18 @@ use crate::{
     solvers::Score,
 };
 
-use self::new_scorer::NewScorer;
+use self::{new_scorer::NewScorer, visibility::VisibilityMatrix};
 
 pub mod approximate;
+pub mod bit_matrix;
+pub mod breakdown;
+pub mod delta_scorer;
 pub mod impact_map;
 pub mod new_scorer;
+pub mod reference_scorer;
 pub mod scorer;
+pub mod upper_bound;
+pub mod visibility;
 
 pub trait Scorer: DynClone + Sync + Send {
     fn score(
@@ -19,6 +25,17 @@ pub trait Scorer: DynClone + Sync + Send {
         placements: &[Point2D],
         volumes: Option<&Vec<f32>>,
     ) -> Score;
+
+    /// Same as `score`, for the scorers that can make use of the already known visibility
+    fn score_with_visibility(
+        &self,
+        problem: &ProblemDto,
+        placements: &[Point2D],
+        volumes: Option<&Vec<f32>>,
+        _visibility: &VisibilityMatrix,
+    ) -> Score {
+        self.score(problem, placements, volumes)
+    }
 }
 
 impl Default for Box<dyn Scorer> {
@@ -1,10 +1,10 @@
 use rayon::prelude::*;
 
 use crate::collider::Collider;
-use crate::dto::Point2D;
+use crate::dto::{Attendee, Instrument, Point2D};
 use crate::{dto::ProblemDto, solvers::Score};
 
-use super::Scorer;
+use super::{visibility::VisibilityMatrix, Scorer};
 
 #[derive(Default, Clone)]
 pub struct NewScorer;
@@ -18,34 +18,58 @@ impl Scorer for NewScorer {
     ) -> Score {
         self::new_score(problem, placements, volumes)
     }
+
+    fn score_with_visibility(
+        &self,
+        problem: &ProblemDto,
+        placements: &[Point2D],
+        volumes: Option<&Vec<f32>>,
+        visibility: &VisibilityMatrix,
+    ) -> Score {
+        self::visible_score(problem, placements, volumes, visibility)
+    }
 }
 
-fn compute_closeness(problem: &ProblemDto, placements: &[Point2D]) -> Vec<f32> {
-    // sort musicians by instrument
+pub(super) fn musicians_per_instrument(problem: &ProblemDto) -> Vec<Vec<usize>> {
     let instrument_count: u32 = problem.musicians.iter().map(|ins| ins.0).max().unwrap() + 1;
     let mut musicians_per_ins: Vec<Vec<usize>> = (0..instrument_count).map(|_| vec![]).collect();
     for (musician_i, instrument) in problem.musicians.iter().enumerate() {
         musicians_per_ins[instrument.0 as usize].push(musician_i);
     }
+    musicians_per_ins
+}
+
+/// Closeness of every musician in `ins_musicians` (all using the same instrument), in the same order
+pub(super) fn compute_instrument_closeness(
+    placements: &[Point2D],
+    ins_musicians: &[usize],
+) -> Vec<f32> {
+    let mut ins_musicians_scores = vec![1f32; ins_musicians.len()];
+    // compute all the pairwise distances between musicians using the instrument, and update their scores
+    for (musician_ins_i, musician_i) in ins_musicians.iter().enumerate() {
+        let musician_pos = placements[*musician_i].as_vec();
+        for other_musician_ins_i in (musician_ins_i + 1)..ins_musicians.len() {
+            let other_musician_i = ins_musicians[other_musician_ins_i];
+            let other_musician_pos = placements[other_musician_i].as_vec();
+            let distance = (musician_pos - other_musician_pos).norm();
+
+            // add the result to the score of both musicians
+            let closeness = 1f32 / distance;
+            ins_musicians_scores[musician_ins_i] += closeness;
+            ins_musicians_scores[other_musician_ins_i] += closeness;
+        }
+    }
+    ins_musicians_scores
+}
+
+pub(super) fn compute_closeness(problem: &ProblemDto, placements: &[Point2D]) -> Vec<f32> {
+    // sort musicians by instrument
+    let musicians_per_ins = musicians_per_instrument(problem);
 
     let mut musicians_closeness: Vec<f32> = vec![0f32; problem.musicians.len()];
     // for each music instrument
     for ins_musicians in musicians_per_ins.into_iter() {
-        let mut ins_musicians_scores = vec![1f32; ins_musicians.len()];
-        // compute all the pairwise distances between musicians using the instrument, and update their scores
-        for (musician_ins_i, musician_i) in ins_musicians.iter().enumerate() {
-            let musician_pos = placements[*musician_i].as_vec();
-            for other_musician_ins_i in (musician_ins_i + 1)..ins_musicians.len() {
-                let other_musician_i = ins_musicians[other_musician_ins_i];
-                let other_musician_pos = placements[other_musician_i].as_vec();
-                let distance = (musician_pos - other_musician_pos).norm();
-
-                // add the result to the score of both musicians
-                let closeness = 1f32 / distance;
-                ins_musicians_scores[musician_ins_i] += closeness;
-                ins_musicians_scores[other_musician_ins_i] += closeness;
-            }
-        }
+        let ins_musicians_scores = compute_instrument_closeness(placements, &ins_musicians);
 
         // store back the closeness of all musicians using the instrument into the global array
         for (musician_i, closeness) in ins_musicians
@@ -59,8 +83,46 @@ fn compute_closeness(problem: &ProblemDto, placements: &[Point2D]) -> Vec<f32> {
     musicians_closeness
 }
 
+pub(super) fn impact(attendee: &Attendee, instrument: &Instrument, placement: &Point2D) -> f32 {
+    let taste: f32 = attendee.tastes[instrument.0 as usize];
+    let distance_sq = (attendee.x - placement.x).powi(2) + (attendee.y - placement.y).powi(2);
+    ((1_000_000f32 * taste) / distance_sq).ceil()
+}
+
+/// The score of one attendee/musician pair, `closeness` is ignored without pillars
+pub(super) fn contribution(impact: f32, closeness: f32, volume: f32, has_pillars: bool) -> i64 {
+    if has_pillars {
+        (impact * closeness * volume).ceil() as i64
+    } else {
+        (impact * volume) as i64
+    }
+}
+
 fn new_score(problem: &ProblemDto, placements: &[Point2D], volumes: Option<&Vec<f32>>) -> Score {
     let collider = Collider::new(problem, placements);
+    score_pairs(problem, placements, volumes, |attendee_i, musician_i| {
+        collider.is_hidden(attendee_i, musician_i)
+    })
+}
+
+/// Same as `new_score`, without any ray casting since the visibility is already known
+fn visible_score(
+    problem: &ProblemDto,
+    placements: &[Point2D],
+    volumes: Option<&Vec<f32>>,
+    visibility: &VisibilityMatrix,
+) -> Score {
+    score_pairs(problem, placements, volumes, |attendee_i, musician_i| {
+        !visibility.is_audible(attendee_i, musician_i)
+    })
+}
+
+fn score_pairs(
+    problem: &ProblemDto,
+    placements: &[Point2D],
+    volumes: Option<&Vec<f32>>,
+    is_hidden: impl Fn(usize, usize) -> bool + Sync,
+) -> Score {
     let has_pillars = !problem.pillars.is_empty();
 
     // compute the closeness score per musician
@@ -78,23 +140,17 @@ fn new_score(problem: &ProblemDto, placements: &[Point2D], volumes: Option<&Vec<
             .map(|(attendee_i, attendee)| {
                 let mut attendee_score = 0i64;
                 for (musician_i, instrument_i) in problem.musicians.iter().enumerate() {
-                    if collider.is_hidden(attendee_i, musician_i) {
+                    if is_hidden(attendee_i, musician_i) {
                         continue;
                     }
-                    let taste: f32 = attendee.tastes[instrument_i.0 as usize];
-                    let musician_location = placements[musician_i];
-                    let distance_sq = (attendee.x - musician_location.x).powi(2)
-                        + (attendee.y - musician_location.y).powi(2);
-                    let impact = ((1_000_000f32 * taste) / distance_sq).ceil();
+                    let impact = impact(attendee, instrument_i, &placements[musician_i]);
                     let volume = volumes.map(|vs| vs[musician_i]).unwrap_or(1.0);
-
-                    let score = if has_pillars {
-                        let closeness = musicians_closeness[musician_i];
-                        (impact * closeness * volume).ceil() as i64
+                    let closeness = if has_pillars {
+                        musicians_closeness[musician_i]
                     } else {
-                        (impact * volume) as i64
+                        1.0
                     };
-                    attendee_score += score
+                    attendee_score += contribution(impact, closeness, volume, has_pillars);
                 }
                 attendee_score
             })
@@ -0,0 +1,256 @@
+use rayon::prelude::*;
+
+use crate::{
+    collider::{Collider, Obstacle},
+    dto::{Point2D, ProblemDto},
+    solvers::Score,
+};
+
+use super::Scorer;
+
+/// Scorer that follows the official rules to the bit, to know which number the judge will show.
+///
+/// Everything is computed in f64 from the decimal values which end up in the JSON files
+/// (the judge never sees our f32s), and every pair is rounded with `ceil`:
+///   - impact = ⌈1 000 000 · taste / d²⌉
+///   - pair score = ⌈closeness · volume · impact⌉, closeness = 1 + Σ 1/d to the same instrument
+///     (only for the problems with pillars, i.e. spec v2)
+///   - a pair is blocked when the segment touches another musician (r = 5) or a pillar
+///
+/// It is slower than `NewScorer`, the Qbvh is only used as a broad phase for the blockers.
+#[derive(Default, Clone)]
+pub struct ReferenceScorer;
+
+impl Scorer for ReferenceScorer {
+    fn score(
+        &self,
+        problem: &ProblemDto,
+        placements: &[Point2D],
+        volumes: Option<&Vec<f32>>,
+    ) -> Score {
+        self::reference_score(problem, placements, volumes)
+    }
+}
+
+/// The value the judge parses from the JSON we produce (or the problem it gave us)
+fn judge_value(value: f32) -> f64 {
+    // f32 Display is the shortest representation, which is also what serde_json writes
+    value.to_string().parse().unwrap()
+}
+
+fn judge_point(x: f32, y: f32) -> (f64, f64) {
+    (judge_value(x), judge_value(y))
+}
+
+fn distance2(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - b.0) * (a.0 - b.0) + (a.1 - b.1) * (a.1 - b.1)
+}
+
+/// Whether the segment from `start` to `end` touches the circle
+fn segment_touches_circle(start: (f64, f64), end: (f64, f64), center: (f64, f64), r: f64) -> bool {
+    let line = (end.0 - start.0, end.1 - start.1);
+    let to_center = (center.0 - start.0, center.1 - start.1);
+    let t = (to_center.0 * line.0 + to_center.1 * line.1) / (line.0 * line.0 + line.1 * line.1);
+    let closest = if t <= 0.0 {
+        start
+    } else if t >= 1.0 {
+        end
+    } else {
+        (start.0 + t * line.0, start.1 + t * line.1)
+    };
+    distance2(closest, center) <= r * r
+}
+
+fn reference_closeness(problem: &ProblemDto, musicians: &[(f64, f64)]) -> Vec<f64> {
+    if problem.pillars.is_empty() {
+        return vec![1.0; musicians.len()];
+    }
+    (0..musicians.len())
+        .map(|k| {
+            let mut closeness = 1.0;
+            for other_k in 0..musicians.len() {
+                if other_k != k && problem.musicians[other_k] == problem.musicians[k] {
+                    closeness += 1.0 / distance2(musicians[k], musicians[other_k]).sqrt();
+                }
+            }
+            closeness
+        })
+        .collect()
+}
+
+fn reference_score(
+    problem: &ProblemDto,
+    placements: &[Point2D],
+    volumes: Option<&Vec<f32>>,
+) -> Score {
+    let musicians: Vec<_> = placements.iter().map(|p| judge_point(p.x, p.y)).collect();
+    let pillars: Vec<_> = problem
+        .pillars
+        .iter()
+        .map(|p| (judge_point(p.center.0, p.center.1), judge_value(p.radius)))
+        .collect();
+    let volumes: Vec<f64> = match volumes {
+        Some(volumes) => volumes.iter().map(|v| judge_value(*v)).collect(),
+        None => vec![1.0; musicians.len()],
+    };
+    let closeness = reference_closeness(problem, &musicians);
+    let collider = Collider::new(problem, placements);
+
+    Score(
+        problem
+            .attendees
+            .par_iter()
+            .enumerate()
+            .map(|(attendee_i, attendee)| {
+                let attendee_location = judge_point(attendee.x, attendee.y);
+                let mut happiness = 0i64;
+                for (k, musician) in musicians.iter().enumerate() {
+                    let mut blocked = false;
+                    collider.for_each_candidate_blocker(attendee_i, k, |obstacle| {
+                        let (center, radius) = match obstacle {
+                            Obstacle::Musician(other_k) => (musicians[other_k], 5.0),
+                            Obstacle::Pillar(pillar_i) => pillars[pillar_i],
+                        };
+                        blocked =
+                            segment_touches_circle(attendee_location, *musician, center, radius);
+                        !blocked
+                    });
+                    if blocked {
+                        continue;
+                    }
+
+                    let taste = judge_value(attendee.tastes[problem.musicians[k].0 as usize]);
+                    let impact =
+                        (1_000_000.0 * taste / distance2(attendee_location, *musician)).ceil();
+                    happiness += (closeness[k] * volumes[k] * impact).ceil() as i64;
+                }
+                happiness
+            })
+            .sum(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{
+        dto::{Attendee, Instrument, SolutionDto, SolutionMetaDto},
+        scoring::{new_scorer::NewScorer, scorer::LegacyScorer},
+    };
+
+    #[test]
+    fn test_spec_example() {
+        let problem = ProblemDto {
+            room_width: 2000.0,
+            room_height: 5000.0,
+            stage_width: 1000.0,
+            stage_height: 200.0,
+            stage_bottom_left: (500.0, 0.0),
+            musicians: vec![Instrument(0), Instrument(1), Instrument(0)],
+            attendees: vec![
+                Attendee {
+                    x: 100.0,
+                    y: 500.0,
+                    tastes: vec![1000.0, -1000.0],
+                },
+                Attendee {
+                    x: 200.0,
+                    y: 1000.0,
+                    tastes: vec![200.0, 200.0],
+                },
+                Attendee {
+                    x: 1100.0,
+                    y: 800.0,
+                    tastes: vec![800.0, 1500.0],
+                },
+            ],
+            pillars: vec![],
+        };
+        let placements = vec![
+            Point2D { x: 590.0, y: 10.0 },
+            Point2D {
+                x: 1100.0,
+                y: 100.0,
+            },
+            Point2D {
+                x: 1100.0,
+                y: 150.0,
+            },
+        ];
+        assert_eq!(ReferenceScorer.score(&problem, &placements, None).0, 5343);
+    }
+
+    /// Runs every scorer on the best solutions and reports how far each one is from the reference.
+    /// Slow, run with `cargo test --release conformance -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn test_conformance() {
+        // LegacyScorer is O(attendees × musicians²), skip it on the big problems
+        const LEGACY_MAX_WORK: usize = 5_000_000_000;
+
+        let mut ids = std::fs::read_dir("./problems")
+            .unwrap()
+            .filter_map(|e| {
+                let path = e.unwrap().path();
+                path.file_stem()?.to_str()?.parse::<u32>().ok()
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+
+        println!(
+            "{:>4} {:>14} {:>14} {:>10} {:>10} {:>10}",
+            "id", "reference", "meta", "new", "legacy", "pairs"
+        );
+        let mut failures = vec![];
+        for id in ids {
+            let best = Path::new("./solutions/best");
+            let Ok(solution) = SolutionDto::load(&best.join(format!("{id}_solution.json"))) else {
+                continue;
+            };
+            let meta: SolutionMetaDto = serde_json::from_str(
+                &std::fs::read_to_string(best.join(format!("{id}_meta.json"))).unwrap(),
+            )
+            .unwrap();
+            let problem = ProblemDto::load(Path::new(&format!("./problems/{id}.json"))).unwrap();
+            let volumes = solution.volumes.as_ref();
+
+            let reference = ReferenceScorer
+                .score(&problem, &solution.placements, volumes)
+                .0;
+            let new = NewScorer.score(&problem, &solution.placements, volumes).0;
+            let musicians = problem.musicians.len();
+            let legacy = if problem.attendees.len() * musicians * musicians <= LEGACY_MAX_WORK {
+                Some(
+                    LegacyScorer
+                        .score(&problem, &solution.placements, volumes)
+                        .0,
+                )
+            } else {
+                None
+            };
+
+            // Rounding differences are at most one point per pair, anything above is a logic bug
+            let pairs = (problem.attendees.len() * musicians) as i64;
+            println!(
+                "{:>4} {:>14} {:>14} {:>+10} {:>10} {:>10}",
+                id,
+                reference,
+                meta.score,
+                new - reference,
+                legacy.map_or("-".to_owned(), |l| format!("{:+}", l - reference)),
+                pairs
+            );
+            if (new - reference).abs() > pairs {
+                failures.push(format!("{id}: new {new} vs reference {reference}"));
+            }
+            if let Some(legacy) = legacy {
+                if (legacy - reference).abs() > pairs {
+                    failures.push(format!("{id}: legacy {legacy} vs reference {reference}"));
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{:#?}", failures);
+    }
+}
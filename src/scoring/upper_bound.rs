@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+};
+
+use log::debug;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dto::{Instrument, ProblemDto},
+    solvers::Problem,
+};
+
+const VOLUME_MAX: f64 = 10.0;
+/// Of the bound below, so that the cached ones are computed again when it changes
+const VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct UpperBoundData {
+    /// 0 for the files from before there was one
+    #[serde(default)]
+    version: u32,
+    upper_bound: i64,
+}
+
+/// Upper bound of the score for the problem, cached in `problems_extra/`
+pub fn load_or_compute(problem: &Problem) -> std::io::Result<i64> {
+    let Some(path) = problem.extra_data_path("upper_bound.json") else {
+        return Ok(upper_bound(problem));
+    };
+    if path.exists() {
+        let data: UpperBoundData = serde_json::from_reader(BufReader::new(File::open(&path)?))?;
+        if data.version == VERSION {
+            return Ok(data.upper_bound);
+        }
+        debug!(
+            "upper_bound({}): cached with version {}, computing it again",
+            problem.id, data.version
+        );
+    }
+
+    let upper_bound = upper_bound(problem);
+    serde_json::to_writer(
+        BufWriter::new(File::create(path)?),
+        &UpperBoundData {
+            version: VERSION,
+            upper_bound,
+        },
+    )?;
+    Ok(upper_bound)
+}
+
+/// Every musician at full volume, with the best closeness its instrument could have, heard by
+/// every attendee who likes its instrument from the point of the stage closest to them where a
+/// musician can stand. No pair of a placement scores more, so no placement does.
+pub fn upper_bound(problem: &Problem) -> i64 {
+    let upper_bound = bound(&problem.data);
+    debug!("upper_bound({}): {}", problem.id, upper_bound);
+    upper_bound
+}
+
+fn bound(data: &ProblemDto) -> i64 {
+    let (left, bottom) = data.stage_bottom_left;
+    let (min_x, min_y) = (left + 10.0, bottom + 10.0);
+    let max_x = left + data.stage_width - 10.0;
+    let max_y = bottom + data.stage_height - 10.0;
+    let closest_distance2: Vec<f64> = data
+        .attendees
+        .iter()
+        .map(|a| {
+            let dx = (min_x - a.x).max(a.x - max_x).max(0.0) as f64;
+            let dy = (min_y - a.y).max(a.y - max_y).max(0.0) as f64;
+            dx * dx + dy * dy
+        })
+        .collect();
+
+    let mut players: HashMap<Instrument, usize> = HashMap::new();
+    for instrument in &data.musicians {
+        *players.entry(*instrument).or_default() += 1;
+    }
+
+    let has_pillars = !data.pillars.is_empty();
+    players
+        .par_iter()
+        .map(|(instrument, count)| {
+            let closeness = if has_pillars {
+                closeness_bound(*count)
+            } else {
+                1.0
+            };
+            // rounded up like the judge does, which only goes up with the impact
+            let musician: i64 = data
+                .attendees
+                .iter()
+                .zip(&closest_distance2)
+                .map(|(attendee, distance2)| {
+                    let taste = attendee.tastes[instrument.0 as usize] as f64;
+                    if taste <= 0.0 {
+                        return 0;
+                    }
+                    let impact = (1_000_000.0 * taste / distance2).ceil();
+                    (closeness * VOLUME_MAX * impact).ceil() as i64
+                })
+                .sum();
+            *count as i64 * musician
+        })
+        .sum()
+}
+
+/// Largest closeness of a musician with `count - 1` others of the same instrument around.
+///
+/// Musicians are disks of radius 5 that can't overlap, so at most ((r + 5) / 5)² - 1 others
+/// are within distance r: the j-th closest one is at least max(10, 5 (√(j + 1) - 1)) away.
+fn closeness_bound(count: usize) -> f64 {
+    1.0 + (1..count)
+        .map(|j| 1.0 / f64::max(10.0, 5.0 * (((j + 1) as f64).sqrt() - 1.0)))
+        .sum::<f64>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dto::{Attendee, Point2D},
+        scoring::{new_scorer::NewScorer, Scorer},
+    };
+
+    #[test]
+    fn test_reached_at_the_closest_point() {
+        let problem = ProblemDto {
+            stage_width: 100.0,
+            stage_height: 100.0,
+            musicians: vec![Instrument(0)],
+            attendees: vec![
+                Attendee {
+                    x: 50.0,
+                    y: 200.0,
+                    tastes: vec![1000.0],
+                },
+                Attendee {
+                    x: 200.0,
+                    y: 50.0,
+                    tastes: vec![-1000.0],
+                },
+            ],
+            ..Default::default()
+        };
+        // on the edge in front of the fan
+        let best = NewScorer.score(&problem, &[Point2D { x: 50.0, y: 90.0 }], Some(&vec![10.0]));
+        let upper_bound = bound(&problem);
+        assert!(upper_bound >= best.0);
+        // all of the fan, nothing of the other one
+        assert_eq!(
+            upper_bound,
+            10 * (1_000_000_000f64 / (110.0 * 110.0)).ceil() as i64
+        );
+    }
+
+    #[test]
+    fn test_closeness_bound() {
+        assert_eq!(closeness_bound(1), 1.0);
+        assert_eq!(closeness_bound(2), 1.1);
+        // the first 8 neighbours can all be touching
+        assert!((closeness_bound(9) - 1.8).abs() < 1e-9);
+        assert!(closeness_bound(10) < 1.9);
+    }
+}
@@ -0,0 +1,246 @@
+use rayon::prelude::*;
+
+use crate::{
+    collider::Collider,
+    common::Grid,
+    dto::{Attendee, Point2D},
+    geometry::Coords2D,
+};
+
+use super::{
+    bit_matrix::{get_bit, set_bit, BitMatrix},
+    impact_map::PillarBlockageMap,
+    scorer::is_sound_blocked,
+};
+
+/// Which sources each attendee can hear: one row of bits per attendee.
+///
+/// The sources are either the musicians themselves (they block each other, see `new`),
+/// or grid positions where musicians could go (see `for_positions`).
+#[derive(Default, Clone)]
+pub struct VisibilityMatrix {
+    audible: BitMatrix,
+}
+
+impl VisibilityMatrix {
+    /// Visibility of every musician, blocked by pillars and the other musicians
+    pub fn new(collider: &Collider) -> Self {
+        let placements = collider.placements();
+        let mut audible = BitMatrix::new(collider.problem().attendees.len(), placements.len());
+        audible
+            .par_rows_mut()
+            .enumerate()
+            .for_each(|(attendee_i, row)| {
+                for musician_i in 0..placements.len() {
+                    set_bit(row, musician_i, !collider.is_hidden(attendee_i, musician_i));
+                }
+            });
+        VisibilityMatrix { audible }
+    }
+
+    /// Visibility of every grid position, blocked by pillars only for now, see `add_obstacle`
+    pub fn for_positions(
+        grid: &Grid,
+        attendees: &[Attendee],
+        pillar_blockage: &PillarBlockageMap,
+    ) -> Self {
+        let mut audible = BitMatrix::new(attendees.len(), grid.positions.len());
+        audible
+            .par_rows_mut()
+            .enumerate()
+            .for_each(|(attendee_i, row)| {
+                for idx_pos in 0..grid.positions.len() {
+                    set_bit(
+                        row,
+                        idx_pos,
+                        !pillar_blockage.is_sound_blocked(idx_pos, attendee_i),
+                    );
+                }
+            });
+        VisibilityMatrix { audible }
+    }
+
+    pub fn is_audible(&self, attendee_i: usize, source_i: usize) -> bool {
+        self.audible.get(attendee_i, source_i)
+    }
+
+    pub fn audible_pairs(&self) -> usize {
+        self.audible.count_ones()
+    }
+
+    /// Updates the pairs affected by `musician_i` moving from `from` to where the collider
+    /// has it now: the rays to it, and the rays it used to block or now blocks.
+    /// Returns how many pairs changed.
+    pub fn move_musician(
+        &mut self,
+        collider: &Collider,
+        musician_i: usize,
+        from: &Point2D,
+    ) -> usize {
+        let placements = collider.placements();
+        self.audible
+            .par_rows_mut()
+            .zip(&collider.problem().attendees)
+            .enumerate()
+            .map(|(attendee_i, (row, attendee))| {
+                let mut changed = 0;
+                for (other_i, other) in placements.iter().enumerate() {
+                    let was_audible = get_bit(row, other_i);
+                    let audible = if other_i == musician_i {
+                        !collider.is_hidden(attendee_i, musician_i)
+                    } else if collider.blocks(musician_i, attendee_i, other_i) {
+                        false
+                    } else if !was_audible && is_sound_blocked(other, from, 5.0, attendee) {
+                        // we were (one of) the blockers, someone else may still be
+                        !collider.is_hidden(attendee_i, other_i)
+                    } else {
+                        was_audible
+                    };
+                    if audible != was_audible {
+                        set_bit(row, other_i, audible);
+                        changed += 1;
+                    }
+                }
+                changed
+            })
+            .sum()
+    }
+
+    /// Hides every source (skipping the ones `skip` says) behind the obstacles of `collider`
+    /// from the attendees, at once. Returns the newly hidden pairs as `(source, attendee)`.
+    pub fn add_obstacles(
+        &mut self,
+        sources: &[impl Coords2D + Sync],
+        collider: &Collider,
+        skip: impl Fn(usize) -> bool + Sync,
+    ) -> Vec<(usize, usize)> {
+        self.audible
+            .par_rows_mut()
+            .enumerate()
+            .flat_map_iter(|(attendee_i, row)| {
+                let mut hidden = vec![];
+                for (source_i, source) in sources.iter().enumerate() {
+                    if skip(source_i) || !get_bit(row, source_i) {
+                        continue;
+                    }
+                    let location = Point2D {
+                        x: source.x(),
+                        y: source.y(),
+                    };
+                    if collider.is_location_hidden(attendee_i, &location) {
+                        set_bit(row, source_i, false);
+                        hidden.push((source_i, attendee_i));
+                    }
+                }
+                hidden
+            })
+            .collect()
+    }
+
+    /// Hides every source (skipping the ones `skip` says) behind a new obstacle from the attendees.
+    /// Returns the newly hidden pairs as `(source, attendee)`.
+    pub fn add_obstacle(
+        &mut self,
+        sources: &[impl Coords2D + Sync],
+        attendees: &[Attendee],
+        center: &Point2D,
+        radius: f32,
+        skip: impl Fn(usize) -> bool + Sync,
+    ) -> Vec<(usize, usize)> {
+        self.audible
+            .par_rows_mut()
+            .zip(attendees)
+            .enumerate()
+            .flat_map_iter(|(attendee_i, (row, attendee))| {
+                let mut hidden = vec![];
+                for (source_i, source) in sources.iter().enumerate() {
+                    if skip(source_i) || !get_bit(row, source_i) {
+                        continue;
+                    }
+                    if is_sound_blocked(source, center, radius, attendee) {
+                        set_bit(row, source_i, false);
+                        hidden.push((source_i, attendee_i));
+                    }
+                }
+                hidden
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        dto::{Instrument, PillarDto, ProblemDto},
+        scoring::{new_scorer::NewScorer, Scorer},
+    };
+
+    fn random_problem(rng: &mut StdRng) -> ProblemDto {
+        let attendees = (0..40)
+            .map(|_| Attendee {
+                x: rng.gen_range(0.0..200.0),
+                y: rng.gen_range(150.0..200.0),
+                tastes: (0..3).map(|_| rng.gen_range(-1000.0..1000.0)).collect(),
+            })
+            .collect();
+        ProblemDto {
+            room_width: 200.0,
+            room_height: 200.0,
+            stage_width: 200.0,
+            stage_height: 130.0,
+            stage_bottom_left: (0.0, 0.0),
+            musicians: (0..20).map(|i| Instrument(i % 3)).collect(),
+            attendees,
+            pillars: vec![PillarDto {
+                center: (100.0, 140.0),
+                radius: 5.0,
+            }],
+        }
+    }
+
+    fn random_location(rng: &mut StdRng) -> Point2D {
+        Point2D {
+            x: rng.gen_range(10.0..190.0),
+            y: rng.gen_range(10.0..120.0),
+        }
+    }
+
+    #[test]
+    fn test_move_matches_fresh() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let problem = random_problem(&mut rng);
+        let mut placements = (0..problem.musicians.len())
+            .map(|_| random_location(&mut rng))
+            .collect::<Vec<_>>();
+        let mut collider = Collider::new_owned(problem.clone(), placements.clone());
+        let mut visibility = VisibilityMatrix::new(&collider);
+
+        for _ in 0..100 {
+            let musician_i = rng.gen_range(0..placements.len());
+            let from = placements[musician_i];
+            placements[musician_i] = random_location(&mut rng);
+            collider.move_musician(musician_i, placements[musician_i]);
+            visibility.move_musician(&collider, musician_i, &from);
+
+            let fresh = VisibilityMatrix::new(&Collider::new(&problem, &placements));
+            for attendee_i in 0..problem.attendees.len() {
+                for musician_i in 0..placements.len() {
+                    assert_eq!(
+                        visibility.is_audible(attendee_i, musician_i),
+                        fresh.is_audible(attendee_i, musician_i),
+                        "attendee {attendee_i}, musician {musician_i}"
+                    );
+                }
+            }
+            assert_eq!(
+                NewScorer
+                    .score_with_visibility(&problem, &placements, None, &visibility)
+                    .0,
+                NewScorer.score(&problem, &placements, None).0
+            );
+        }
+    }
+}
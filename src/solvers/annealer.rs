@@ -1,18 +1,29 @@
 // TODO: Remove this
 #![allow(dead_code, unused_variables)]
 
-use std::time::Instant;
+use std::{collections::HashMap, f32::consts::TAU, time::Duration};
 
 use log::debug;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    diamond_grid::{fit_circles_grid, DiamondGrid, GridCoord, GridSize, GridTransform},
-    dto::SolutionDto,
+    diamond_grid::{fit_stage, DiamondGrid, GridCoord, GridSize, GridTransform},
+    dto::{Point2D, SolutionDto},
+    scoring::delta_scorer::DeltaScorer,
 };
 
 use rand::{seq::SliceRandom, Rng};
 
-use super::{Problem, Score, Solver};
+use super::{
+    exact_score, parameters::name_suffix, Deadline, Parameter, ParameterKind, ParameterSpec,
+    Problem, Score, Solver, SolverRng,
+};
+
+/// Of the circles of the grid, a bit more than half the distance between musicians
+const RADIUS: f32 = 5.002;
+/// Of the temperatures of the cooling cycle, when starting from a prior solution: hot enough
+/// to leave a local optimum, not to walk away from it
+const POLISH_HEAT: f32 = 0.2;
 
 #[derive(Default, Clone)]
 pub struct Annealer {
@@ -20,13 +31,56 @@ pub struct Annealer {
     grid_size: GridSize,
     grid_transform: GridTransform,
     grid: DiamondGrid<Option<usize>>,
+    /// Empty when moving in continuous space
     placements: Vec<GridCoord>,
+    /// Where the musicians are, on the grid or not
+    positions: Vec<Point2D>,
+    volumes: Vec<f32>,
+    scorer: DeltaScorer,
     score: Score,
+    /// The best solution seen, the cooling moves away from it at times
+    best: SolutionDto,
+    best_score: Score,
+    /// Share of the temperatures of the cooling cycle, lower to only polish a prior solution
+    heat: f32,
 
     pub temperature_scale: f32,
     pub max_steps: usize,
+    /// The parameters given, for the name
+    parameters_suffix: String,
+    steps_per_musician: Option<usize>,
+    temperature: Option<f32>,
+    timeout: Option<Duration>,
+    continuous: bool,
+    seed: Option<u64>,
     pub step_i: usize,
-    pub start_time: Option<Instant>,
+    /// Progress of the cooling before a restore, the time budget covers the rest
+    progress_offset: f32,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+    rng: SolverRng,
+}
+
+/// What `checkpoint` saves, the rest comes back from the problem and the parameters
+#[derive(Serialize, Deserialize)]
+struct AnnealerState {
+    placements: Vec<GridCoord>,
+    positions: Vec<Point2D>,
+    volumes: Vec<f32>,
+    step_i: usize,
+    /// Sets the temperature, between 0 and 1
+    progress: f32,
+    /// 1 in the checkpoints from before there was one
+    #[serde(default = "full_heat")]
+    heat: f32,
+    /// The best solution seen, which can be the prior solution, not in the older checkpoints
+    #[serde(default)]
+    best: Option<(Score, SolutionDto)>,
+    rng: SolverRng,
+}
+
+fn full_heat() -> f32 {
+    1.0
 }
 
 #[derive(Clone)]
@@ -39,15 +93,27 @@ enum MusicianChange {
         musician: usize,
         location: GridCoord,
     },
+    /// Off the grid, in continuous space
+    Shift { musician: usize, position: Point2D },
 }
 
 impl MusicianChange {
-    fn apply(&self, placements: &mut [GridCoord], grid: &mut DiamondGrid<Option<usize>>) -> Self {
+    fn apply(
+        &self,
+        placements: &mut [GridCoord],
+        positions: &mut [Point2D],
+        grid: &mut DiamondGrid<Option<usize>>,
+        grid_transform: &GridTransform,
+    ) -> Self {
         match self {
             MusicianChange::Swap {
                 musician_a,
                 musician_b,
             } => {
+                positions.swap(*musician_a, *musician_b);
+                if placements.is_empty() {
+                    return self.clone();
+                }
                 let loc_a = placements[*musician_a];
                 let loc_b = placements[*musician_b];
                 placements[*musician_b] = loc_a;
@@ -59,6 +125,7 @@ impl MusicianChange {
             MusicianChange::Move { musician, location } => {
                 let old_location = placements[*musician];
                 placements[*musician] = *location;
+                positions[*musician] = grid_transform.apply(location).into();
                 grid[&old_location] = None;
                 grid[location] = Some(*musician);
                 MusicianChange::Move {
@@ -66,40 +133,264 @@ impl MusicianChange {
                     location: old_location,
                 }
             }
+            MusicianChange::Shift { musician, position } => {
+                let old_position = positions[*musician];
+                positions[*musician] = *position;
+                MusicianChange::Shift {
+                    musician: *musician,
+                    position: old_position,
+                }
+            }
+        }
+    }
+
+    fn score_delta(&self, scorer: &DeltaScorer, grid_transform: &GridTransform) -> i64 {
+        match self {
+            MusicianChange::Swap {
+                musician_a,
+                musician_b,
+            } => scorer.swap_delta(*musician_a, *musician_b),
+            MusicianChange::Move { musician, location } => {
+                scorer.move_delta(*musician, grid_transform.apply(location).into())
+            }
+            MusicianChange::Shift { musician, position } => scorer.move_delta(*musician, *position),
+        }
+    }
+
+    fn apply_to_scorer(&self, scorer: &mut DeltaScorer, grid_transform: &GridTransform) -> i64 {
+        match self {
+            MusicianChange::Swap {
+                musician_a,
+                musician_b,
+            } => scorer.apply_swap(*musician_a, *musician_b),
+            MusicianChange::Move { musician, location } => {
+                scorer.apply_move(*musician, grid_transform.apply(location).into())
+            }
+            MusicianChange::Shift { musician, position } => scorer.apply_move(*musician, *position),
         }
     }
 }
 
 impl Annealer {
-    fn serialize(&self) -> SolutionDto {
-        let mut res = vec![];
-        for coord in &self.placements {
-            res.push(self.grid_transform.apply(coord).into());
-        }
-        assert!(!res.is_empty());
+    pub(super) fn serialize(&self) -> SolutionDto {
+        assert!(!self.positions.is_empty());
         SolutionDto {
-            placements: res,
-            volumes: Some(vec![10.0; self.placements.len()]),
+            placements: self.positions.clone(),
+            volumes: Some(self.volumes.clone()),
         }
     }
 
-    fn compute_score(&self, solution: &SolutionDto) -> Score {
-        self.problem
-            .score(&solution.placements, solution.volumes.as_ref())
+    fn accept(&mut self, change: &MusicianChange) {
+        let score_delta = change.apply_to_scorer(&mut self.scorer, &self.grid_transform);
+        change.apply(
+            &mut self.placements,
+            &mut self.positions,
+            &mut self.grid,
+            &self.grid_transform,
+        );
+        self.score.0 += score_delta;
+    }
+
+    /// Every musician goes to the free cell closest to where it is, in order
+    fn snap(&self, positions: &[Point2D]) -> Vec<GridCoord> {
+        let cells = self.grid_size.all_grid_coordinates();
+        assert!(
+            cells.len() >= positions.len(),
+            "annealer: {} cells for {} musicians",
+            cells.len(),
+            positions.len()
+        );
+        let centers: Vec<Point2D> = cells
+            .iter()
+            .map(|c| self.grid_transform.apply(c).into())
+            .collect();
+        let mut free = vec![true; cells.len()];
+        positions
+            .iter()
+            .map(|position| {
+                let closest = (0..cells.len())
+                    .filter(|&i| free[i])
+                    .min_by(|&a, &b| {
+                        position
+                            .distance(&centers[a])
+                            .total_cmp(&position.distance(&centers[b]))
+                    })
+                    .unwrap();
+                free[closest] = false;
+                cells[closest]
+            })
+            .collect()
+    }
+
+    pub(super) fn score(&self) -> Score {
+        self.score
+    }
+
+    /// The best solution seen by `solve_step`, or the prior solution when it scored better
+    /// before being snapped to the grid
+    pub(super) fn best(&self) -> (Score, &SolutionDto) {
+        (self.best_score, &self.best)
+    }
+
+    /// One move, taken if it scores better or with a chance falling with `raw_temperature`
+    pub(super) fn step_at(&mut self, raw_temperature: f32) {
+        // generate a neighbor mutation
+        let musician_i = self.rng.gen_range(0..self.problem.data.musicians.len());
+        // distance_mean is the mean of the distance distribution, essentially the peak
+        let distance_mean = raw_temperature * self.temperature_scale;
+        // the less raw_temperature is, the more likely distribution is to be close to distance_mean
+        let distance = pareto(
+            &mut self.rng,
+            (1.0 + raw_temperature as f64).exp(),
+            distance_mean as f64,
+        )
+        .ceil() as usize;
+        let neighbor = if self.continuous {
+            shift(
+                &mut self.rng,
+                &self.problem,
+                &self.positions,
+                musician_i,
+                distance.max(1),
+            )
+        } else {
+            neighbor(
+                &mut self.rng,
+                &self.problem,
+                &self.grid,
+                &self.placements,
+                musician_i,
+                distance.max(1),
+            )
+        };
+
+        let score_delta = neighbor.score_delta(&self.scorer, &self.grid_transform);
+
+        let decision_stats = if score_delta > 0 {
+            self.accept(&neighbor);
+            None
+        } else {
+            let probability = acceptance_probability(score_delta, raw_temperature);
+            let take_the_loss = self.rng.gen_bool(probability as f64);
+            // debug!("loss of {score_delta} taken {take_the_loss} prob {probability:.4}");
+            if take_the_loss {
+                self.accept(&neighbor);
+            }
+            Some((probability, take_the_loss))
+        };
+
+        debug!(
+            "annealer({}): step {:>5}   raw_temperature={:<5.3}   distance_mean={:<5.2}   distance={:<5.2}  score_delta={:<10} {:?}",
+            self.problem.id, self.step_i, raw_temperature, distance_mean, distance, score_delta, decision_stats
+        );
+    }
+
+    /// From 0 at the start to 1 when fully cooled down
+    fn progress(&self) -> f32 {
+        // with a budget, cool down over the whole budget instead of a fixed number of steps
+        match self.time_budget {
+            Some(_) => {
+                let progress = self.deadline.progress().unwrap().min(1.0);
+                self.progress_offset + (1.0 - self.progress_offset) * progress
+            }
+            // a single step is the last one
+            None => self.step_i as f32 / self.max_steps.saturating_sub(1).max(1) as f32,
+        }
+    }
+
+    fn fit_grid(&mut self, problem: &Problem) {
+        self.problem = problem.clone();
+        (self.grid_size, self.grid_transform) = fit_stage(&problem.data, RADIUS);
+        self.grid = DiamondGrid::new(self.grid_size, |_| None);
+        self.placements = vec![];
+        self.positions = vec![];
+    }
+
+    /// Once the musicians are placed, on the grid or at their positions in continuous space
+    fn start(&mut self) {
+        if !self.placements.is_empty() {
+            for (i, placement) in self.placements.iter().enumerate() {
+                self.grid[placement] = Some(i);
+            }
+            self.positions = self
+                .placements
+                .iter()
+                .map(|c| self.grid_transform.apply(c).into())
+                .collect();
+        }
+
+        // compute the score
+        let solution = self.serialize();
+        self.scorer = DeltaScorer::new(
+            &self.problem.data,
+            &solution.placements,
+            solution.volumes.as_ref(),
+        );
+        self.score = self.scorer.score();
+        self.best = solution;
+        self.best_score = self.score;
+
+        // figure out the initial temperature
+        let grid_width = self.grid_size.width();
+        let grid_height: usize = self.grid_size.height();
+        self.temperature_scale = ((grid_width.pow(2) + grid_width.pow(2)) as f32).sqrt() / 3.
+            * self.temperature.unwrap_or(1.);
+        self.max_steps = self.problem.data.musicians.len() * self.steps_per_musician.unwrap_or(500);
+        self.deadline = Deadline::start(Some(
+            self.time_budget
+                .unwrap_or(self.timeout.unwrap_or(Duration::from_secs(60 * 20))),
+        ));
+    }
+}
+
+/// A random position at most `distance` cells away, or a swap with the musician in the way
+fn shift(
+    rng: &mut impl Rng,
+    problem: &Problem,
+    positions: &[Point2D],
+    musician_i: usize,
+    distance: usize,
+) -> MusicianChange {
+    let position = positions[musician_i];
+    let radius = distance as f32 * 2. * RADIUS * rng.gen::<f32>().sqrt();
+    let angle = rng.gen_range(0.0..TAU);
+    let (left, bottom) = problem.data.stage_bottom_left;
+    let new_position = Point2D {
+        x: (position.x + radius * angle.cos())
+            .clamp(left + 10., left + problem.data.stage_width - 10.),
+        y: (position.y + radius * angle.sin())
+            .clamp(bottom + 10., bottom + problem.data.stage_height - 10.),
+    };
+
+    let existing_musician = positions
+        .iter()
+        .enumerate()
+        .position(|(i, p)| i != musician_i && p.distance(&new_position) < 10.);
+
+    if let Some(musician_b) = existing_musician {
+        return MusicianChange::Swap {
+            musician_a: musician_i,
+            musician_b,
+        };
+    }
+
+    MusicianChange::Shift {
+        musician: musician_i,
+        position: new_position,
     }
 }
 
 fn neighbor(
+    rng: &mut impl Rng,
     problem: &Problem,
     grid: &DiamondGrid<Option<usize>>,
     placements: &[GridCoord],
     musician_i: usize,
     distance: usize,
 ) -> MusicianChange {
-    let mut rng = rand::thread_rng();
     let musician = &placements[musician_i];
 
-    let displacement = musician.random_displacement(&mut rng, distance);
+    let displacement = musician.random_displacement(rng, distance);
     let new_location = grid.size.displace(musician, displacement);
 
     let existing_musician = placements.iter().position(|p| *p == new_location);
@@ -117,14 +408,14 @@ fn neighbor(
     }
 }
 // pareto distribution is really biased towards mean
-fn pareto(alpha: f64, xmin: f64) -> f64 {
-    let u: f64 = rand::random::<f64>();
+fn pareto(rng: &mut impl Rng, alpha: f64, xmin: f64) -> f64 {
+    let u: f64 = rng.gen::<f64>();
     xmin * (1.0 / u).powf(1.0 / alpha)
 }
 
 // cauchy distribution can generate negative numbers and 0, so use with abs() and max(1)
-fn _cauchy(loc: f64, scale: f64) -> f64 {
-    let u: f64 = rand::random::<f64>();
+fn _cauchy(rng: &mut impl Rng, loc: f64, scale: f64) -> f64 {
+    let u: f64 = rng.gen::<f64>();
     loc + scale * (u - 0.5).tan()
 }
 
@@ -142,7 +433,7 @@ fn cooling_cycle(x: f32) -> f32 {
     }
 }
 
-fn acceptance_probability(score_delta: i64, raw_temperature: f32) -> f32 {
+pub(super) fn acceptance_probability(score_delta: i64, raw_temperature: f32) -> f32 {
     let score_scale = 1. / 1_000_000f32;
     if raw_temperature == 0. {
         return 0.;
@@ -152,112 +443,277 @@ fn acceptance_probability(score_delta: i64, raw_temperature: f32) -> f32 {
 
 impl Solver for Annealer {
     fn name(&self) -> String {
-        "annealer".to_owned()
+        format!("annealer{}", self.parameters_suffix)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[
+            ParameterSpec {
+                name: "steps",
+                kind: ParameterKind::Int,
+                default: Some("500"),
+                min: Some(1.0),
+                description: "steps per musician, when there is no time budget",
+            },
+            ParameterSpec {
+                name: "temperature",
+                kind: ParameterKind::Float,
+                default: Some("1"),
+                min: Some(0.0),
+                description: "scale of the initial move distance (a third of the grid diagonal)",
+            },
+            ParameterSpec {
+                name: "timeout",
+                kind: ParameterKind::Duration,
+                default: Some("20m"),
+                min: None,
+                description: "longest run when there is no time budget",
+            },
+            ParameterSpec {
+                name: "space",
+                kind: ParameterKind::Enum(&["grid", "continuous"]),
+                default: Some("grid"),
+                min: None,
+                description: "where the musicians move, a prior solution is snapped to the grid",
+            },
+        ]
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("steps", Parameter::Int(v)) => self.steps_per_musician = Some(v as usize),
+                ("temperature", Parameter::Float(v)) => self.temperature = Some(v as f32),
+                ("timeout", Parameter::Duration(v)) => self.timeout = Some(v),
+                ("space", Parameter::Enum(v)) => self.continuous = v == "continuous",
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
     }
 
     fn get_problem(&self) -> &Problem {
         &self.problem
     }
 
-    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
-        // NOTE: This can be changed
-        assert!(
-            solution.placements.is_empty(),
-            "annealer: must be the start of the chain"
-        );
-        self.problem = problem.clone();
-        let musician_count = problem.data.musicians.len();
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
 
-        let stage_width = problem.data.stage_width;
-        let stage_height = problem.data.stage_height;
-        assert!(stage_width >= 20.);
-        assert!(stage_height >= 20.);
-        let padding_x = if stage_width < 20.00002 { 5. } else { 5.002 };
-        let padding_y = if stage_height < 20.00002 { 5. } else { 5.002 };
-        let (corner_x, corner_y) = problem.data.stage_bottom_left;
-        let width = stage_width - padding_x * 2.;
-        let height = stage_height - padding_y * 2.;
-        (self.grid_size, self.grid_transform) = fit_circles_grid(
-            (corner_x + padding_x, corner_y + padding_y),
-            width.max(0.),
-            height.max(0.),
-            5.002,
-        );
-        self.grid = DiamondGrid::new(self.grid_size, |_| None);
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
 
-        // figure out an initial placement for musicians
-        let mut placement = self.grid_size.all_grid_coordinates();
-        let (random_placement, _) =
-            placement[..].partial_shuffle(&mut rand::thread_rng(), musician_count);
-        for (i, placement) in random_placement.iter().enumerate() {
-            self.placements.push(*placement);
-            self.grid[placement] = Some(i);
+    /// Starts from `solution` if there is one, cooler to only polish it, and gives it back if
+    /// nothing beats it
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        let musician_count = problem.data.musicians.len();
+        self.fit_grid(problem);
+        self.step_i = 0;
+        self.progress_offset = 0.0;
+        self.heat = if solution.placements.is_empty() {
+            1.0
+        } else {
+            POLISH_HEAT
+        };
+        self.rng = SolverRng::new(self.seed);
+        self.volumes = solution
+            .volumes
+            .clone()
+            .unwrap_or_else(|| vec![10.0; musician_count]);
+        // the prior solution before snapping it, which may score better than on the grid
+        let mut snapped = None;
+
+        if solution.placements.is_empty() {
+            // figure out an initial placement for musicians
+            let mut placement = self.grid_size.all_grid_coordinates();
+            let (random_placement, _) =
+                placement[..].partial_shuffle(&mut self.rng, musician_count);
+            self.placements = random_placement.to_vec();
+        } else if self.continuous {
+            self.positions = solution.placements;
+        } else {
+            self.placements = self.snap(&solution.placements);
+            snapped = Some(solution);
+        }
+        if self.continuous && self.positions.is_empty() {
+            // a random start is on the grid all the same
+            self.positions = std::mem::take(&mut self.placements)
+                .iter()
+                .map(|c| self.grid_transform.apply(c).into())
+                .collect();
+        }
+        self.start();
+        if let Some(prior) = snapped {
+            let prior_score = exact_score(problem, &prior);
+            if prior_score.0 > self.best_score.0 {
+                self.best = prior;
+                self.best_score = prior_score;
+            }
         }
-
-        // compute the score
-        self.score = self.compute_score(&self.serialize());
-
-        // figure out the initial temperature
-        let grid_width = self.grid_size.width();
-        let grid_height: usize = self.grid_size.height();
-        self.temperature_scale = ((grid_width.pow(2) + grid_width.pow(2)) as f32).sqrt() / 3.;
-        self.max_steps = musician_count * 500;
-        self.start_time = Some(Instant::now());
         debug!(
             "annealer({}): initialized for {}",
             self.problem.id, self.max_steps
         );
     }
 
-    fn solve_step(&mut self) -> (SolutionDto, bool) {
-        let mut rng = rand::thread_rng();
-        let progress = self.step_i as f32 / (self.max_steps - 1) as f32;
-        let raw_temperature = cooling_cycle(progress);
-
-        // generate a neighbor mutation
-        let musician_i = rand::thread_rng().gen_range(0..self.problem.data.musicians.len());
-        // distance_mean is the mean of the distance distribution, essentially the peak
-        let distance_mean = raw_temperature * self.temperature_scale;
-        // the less raw_temperature is, the more likely distribution is to be close to distance_mean
-        let distance =
-            pareto((1.0 + raw_temperature as f64).exp(), distance_mean as f64).ceil() as usize;
-        let neighbor = neighbor(
-            &self.problem,
-            &self.grid,
-            &self.placements,
-            musician_i,
-            distance.max(1),
-        );
-
-        let reverse_change = neighbor.apply(&mut self.placements, &mut self.grid);
-        let new_solution = self.serialize();
-        let new_score = self.compute_score(&new_solution);
-        let score_delta = new_score.0 - self.score.0;
+    fn checkpoint(&self) -> Option<serde_json::Value> {
+        let state = AnnealerState {
+            placements: self.placements.clone(),
+            positions: self.positions.clone(),
+            volumes: self.volumes.clone(),
+            step_i: self.step_i,
+            progress: self.progress(),
+            heat: self.heat,
+            best: Some((self.best_score, self.best.clone())),
+            rng: self.rng.clone(),
+        };
+        Some(serde_json::to_value(state).unwrap())
+    }
 
-        let decision_stats = if score_delta > 0 {
-            self.score = new_score;
-            None
+    /// With a time budget, the cooling starts again over what is left of it
+    fn restore(&mut self, problem: &Problem, state: serde_json::Value) -> Result<(), String> {
+        let state: AnnealerState = serde_json::from_value(state).map_err(|e| e.to_string())?;
+        let musician_count = problem.data.musicians.len();
+        let placed = if self.continuous {
+            state.positions.len()
         } else {
-            let probability = acceptance_probability(score_delta, raw_temperature);
-            let take_the_loss = rng.gen_bool(probability as f64);
-            // debug!("loss of {score_delta} taken {take_the_loss} prob {probability:.4}");
-            if take_the_loss {
-                self.score = new_score;
-            } else {
-                reverse_change.apply(&mut self.placements, &mut self.grid);
-            }
-            Some((probability, take_the_loss))
+            state.placements.len()
         };
-
+        if placed != musician_count || state.volumes.len() != musician_count {
+            return Err(format!(
+                "annealer: {} placements for {} musicians",
+                placed, musician_count
+            ));
+        }
+        self.fit_grid(problem);
+        if let Some(p) = state.placements.iter().find(|p| !self.grid_size.check(p)) {
+            return Err(format!("annealer: {:?} is not on the grid", p));
+        }
+        if self.continuous {
+            self.positions = state.positions;
+        } else {
+            self.placements = state.placements;
+        }
+        self.volumes = state.volumes;
+        self.step_i = state.step_i;
+        self.progress_offset = state.progress;
+        self.heat = state.heat;
+        self.rng = state.rng;
+        self.start();
+        if let Some((best_score, best)) = state.best {
+            if best_score.0 > self.best_score.0 {
+                self.best = best;
+                self.best_score = best_score;
+            }
+        }
         debug!(
-            "annealer({}): step {:>5}   raw_temperature={:<5.3}   distance_mean={:<5.2}   distance={:<5.2}  score_delta={:<10} {:?}",
-            self.problem.id, self.step_i, raw_temperature, distance_mean, distance, score_delta, decision_stats
+            "annealer({}): restored at step {}/{}",
+            self.problem.id, self.step_i, self.max_steps
         );
+        Ok(())
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        self.step_at(self.heat * cooling_cycle(self.progress()));
+        if self.score.0 > self.best_score.0 {
+            self.best = self.serialize();
+            self.best_score = self.score;
+        }
 
         self.step_i += 1;
-        let done_steps = self.step_i >= self.max_steps;
-        let elapsed = self.start_time.unwrap().elapsed();
-        let timeout = elapsed.as_secs() > 60 * 20;
-        (self.serialize(), done_steps || timeout)
+        let done_steps = self.time_budget.is_none() && self.step_i >= self.max_steps;
+        (self.best.clone(), done_steps || self.deadline.passed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common::calculate_invalid_positions, solvers::test_problem};
+
+    fn prior() -> SolutionDto {
+        SolutionDto {
+            placements: vec![
+                Point2D { x: 20.0, y: 20.0 },
+                Point2D { x: 33.0, y: 20.0 },
+                Point2D { x: 60.0, y: 71.5 },
+            ],
+            volumes: Some(vec![1.0, 2.0, 3.0]),
+        }
+    }
+
+    #[test]
+    fn test_snaps_prior_solution() {
+        let mut solver = Annealer::default();
+        solver.initialize(&test_problem(), prior());
+        let solution = solver.serialize();
+        assert_eq!(solution.volumes, prior().volumes);
+        for (snapped, placement) in solution.placements.iter().zip(prior().placements.iter()) {
+            assert!(snapped.distance(placement) < 2. * RADIUS);
+        }
+        assert!(calculate_invalid_positions(&solution.placements, &test_problem().data).is_empty());
+
+        let prior_score = exact_score(&test_problem(), &prior());
+        let solution = solver.finish(&test_problem(), None);
+        assert!(solution.score.0 >= prior_score.0);
+    }
+
+    #[test]
+    fn test_checkpoint_keeps_the_prior_solution() {
+        let problem = test_problem();
+        // off the grid, next to the attendee: snapping it loses score
+        let prior = SolutionDto {
+            placements: vec![
+                Point2D { x: 45.0, y: 90.0 },
+                Point2D { x: 90.0, y: 10.0 },
+                Point2D { x: 55.0, y: 90.0 },
+            ],
+            volumes: Some(vec![10.0, 0.0, 10.0]),
+        };
+        let mut solver = Annealer::default();
+        solver.initialize(&problem, prior.clone());
+        let (best_score, _) = solver.best();
+        assert_eq!(best_score.0, exact_score(&problem, &prior).0);
+
+        let mut resumed = Annealer::default();
+        resumed
+            .restore(&problem, solver.checkpoint().unwrap())
+            .unwrap();
+        let (resumed_score, resumed_best) = resumed.best();
+        assert_eq!(resumed_score.0, best_score.0);
+        assert_eq!(resumed_best.placements, prior.placements);
+    }
+
+    #[test]
+    fn test_progress_of_a_single_step() {
+        let mut solver = Annealer::default();
+        solver.initialize(&test_problem(), SolutionDto::default());
+        solver.max_steps = 1;
+        assert_eq!(solver.progress(), 0.0);
+        solver.solve_step();
+        assert_eq!(solver.progress(), 1.0);
+    }
+
+    #[test]
+    fn test_continuous_space() {
+        let mut solver = Annealer::default();
+        solver.set_parameters(HashMap::from([
+            ("space".to_owned(), Parameter::Enum("continuous".to_owned())),
+            ("steps".to_owned(), Parameter::Int(200)),
+        ]));
+        solver.set_seed(3);
+        let solution = solver.solve(&test_problem());
+        assert_eq!(solution.data.volumes, Some(vec![10.0; 3]));
+
+        solver.initialize(&test_problem(), prior());
+        assert_eq!(solver.serialize().placements, prior().placements);
+        let prior_score = exact_score(&test_problem(), &prior());
+        let solution = solver.finish(&test_problem(), None);
+        assert!(solution.score.0 >= prior_score.0);
+        assert_eq!(solution.data.volumes, prior().volumes);
+        assert!(
+            calculate_invalid_positions(&solution.data.placements, &test_problem().data).is_empty()
+        );
     }
 }
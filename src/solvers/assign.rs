@@ -0,0 +1,293 @@
+use std::collections::{BTreeSet, HashMap};
+
+use log::debug;
+use rayon::prelude::*;
+
+use crate::{
+    collider::Collider,
+    common::{Grid, Position},
+    diamond_grid::fit_stage,
+    dto::{Point2D, ProblemDto, SolutionDto},
+    geometry::distance2,
+    hungarian,
+    scoring::{impact_map::PillarBlockageMap, visibility::VisibilityMatrix},
+};
+
+use super::{parameters::name_suffix, Parameter, ParameterKind, ParameterSpec, Problem, Solver};
+
+/// Just apart enough for any musicians to stand on all the slots at once
+const RADIUS: f32 = 5.002;
+
+/// Best assignment of the musicians to a set of slots, with the Hungarian algorithm.
+///
+/// The value of a musician on a slot is what its instrument brings there at full volume,
+/// or nothing when muted. At the start of a pipeline, the slots are the best cells of a
+/// `DiamondGrid` for each instrument, and the other musicians are ignored. After another
+/// solver, the slots are the positions it found: a musician hides the others whichever its
+/// instrument, so the assignment is only off by the closeness bonus.
+#[derive(Clone)]
+pub struct Assign {
+    // Parameters
+    parameters_suffix: String,
+    spread: f32,
+    // Data
+    problem: Problem,
+    slots: Vec<Point2D>,
+    /// Of every instrument on every slot, at volume 1: `values[slot][instrument]`
+    values: Vec<Vec<i64>>,
+}
+
+impl Default for Assign {
+    fn default() -> Self {
+        Self {
+            parameters_suffix: String::new(),
+            spread: 2.0,
+            problem: Problem::default(),
+            slots: vec![],
+            values: vec![],
+        }
+    }
+}
+
+impl Solver for Assign {
+    fn name(&self) -> String {
+        format!("assign{}", self.parameters_suffix)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[ParameterSpec {
+            name: "spread",
+            kind: ParameterKind::Float,
+            default: Some("2"),
+            min: Some(1.0),
+            description: "candidate slots per musician of each instrument, at the start",
+        }]
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("spread", Parameter::Float(v)) => self.spread = v as f32,
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
+    }
+
+    fn get_problem(&self) -> &Problem {
+        &self.problem
+    }
+
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        self.problem = problem.clone();
+        let data = &problem.data;
+        if solution.placements.is_empty() {
+            let (grid_size, grid_transform) = fit_stage(data, RADIUS);
+            let cells: Vec<Point2D> = grid_size
+                .all_grid_coordinates()
+                .iter()
+                .map(|coord| grid_transform.apply(coord).into())
+                .collect();
+            assert!(
+                cells.len() >= data.musicians.len(),
+                "assign({}): {} cells for {} musicians",
+                problem.id,
+                cells.len(),
+                data.musicians.len()
+            );
+            debug!(
+                "assign({}): computing the values of {} cells",
+                problem.id,
+                cells.len()
+            );
+            // only the pillars hide the cells, cached on disk as for the greedy
+            let grid = Grid {
+                width: cells.len(),
+                height: 1,
+                positions: cells
+                    .iter()
+                    .map(|&p| Position { p, taken: false })
+                    .collect(),
+            };
+            let pillar_blockage_map = PillarBlockageMap::load_or_new(problem, &grid);
+            let values = slot_values(data, &cells, |attendee_i, cell_i| {
+                !pillar_blockage_map.is_sound_blocked(cell_i, attendee_i)
+            });
+            let chosen = self.candidates(&values);
+            self.slots = chosen.iter().map(|&i| cells[i]).collect();
+            self.values = chosen.iter().map(|&i| values[i].clone()).collect();
+        } else {
+            let collider = Collider::new(data, &solution.placements);
+            let visibility = VisibilityMatrix::new(&collider);
+            self.slots = solution.placements;
+            self.values = slot_values(data, &self.slots, |attendee_i, slot_i| {
+                visibility.is_audible(attendee_i, slot_i)
+            });
+        }
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        let musicians = &self.problem.data.musicians;
+        let value =
+            |musician: usize, slot: usize| self.values[slot][musicians[musician].0 as usize].max(0);
+        let assignment = hungarian::assign(musicians.len(), self.slots.len(), |musician, slot| {
+            -value(musician, slot)
+        });
+        let total: i64 = assignment
+            .iter()
+            .enumerate()
+            .map(|(musician, &slot)| value(musician, slot))
+            .sum();
+        debug!(
+            "assign({}): {} musicians on {} slots, value {} at full volume",
+            self.problem.id,
+            musicians.len(),
+            self.slots.len(),
+            total * 10
+        );
+
+        let placements = assignment.iter().map(|&slot| self.slots[slot]).collect();
+        let volumes = assignment
+            .iter()
+            .enumerate()
+            .map(|(musician, &slot)| if value(musician, slot) > 0 { 10.0 } else { 0.0 })
+            .collect();
+        (
+            SolutionDto {
+                placements,
+                volumes: Some(volumes),
+            },
+            true,
+        )
+    }
+}
+
+impl Assign {
+    /// The best cells of every instrument, `spread` for each of its musicians, and then the
+    /// best cells of any instrument until there is one per musician
+    fn candidates(&self, values: &[Vec<i64>]) -> BTreeSet<usize> {
+        let musicians = &self.problem.data.musicians;
+        let mut counts = vec![0usize; values[0].len()];
+        for instrument in musicians {
+            counts[instrument.0 as usize] += 1;
+        }
+
+        let mut chosen = BTreeSet::new();
+        let mut cells: Vec<usize> = (0..values.len()).collect();
+        for (instrument, &count) in counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let wanted = ((count as f32 * self.spread).ceil() as usize).min(cells.len());
+            cells.sort_by_key(|&cell| -values[cell][instrument]);
+            chosen.extend(&cells[..wanted]);
+        }
+        cells.sort_by_key(|&cell| -values[cell].iter().max().unwrap());
+        for cell in cells {
+            if chosen.len() >= musicians.len() {
+                break;
+            }
+            chosen.insert(cell);
+        }
+        debug!(
+            "assign({}): {} candidate slots for {} musicians",
+            self.problem.id,
+            chosen.len(),
+            musicians.len()
+        );
+        chosen
+    }
+}
+
+/// Value of every instrument on every slot at volume 1, from the attendees who hear it
+fn slot_values(
+    problem: &ProblemDto,
+    slots: &[Point2D],
+    is_audible: impl Fn(usize, usize) -> bool + Sync,
+) -> Vec<Vec<i64>> {
+    let instruments = problem.musicians.iter().map(|i| i.0).max().unwrap() as usize + 1;
+    slots
+        .par_iter()
+        .enumerate()
+        .map(|(slot_i, slot)| {
+            let mut values = vec![0; instruments];
+            for (attendee_i, attendee) in problem.attendees.iter().enumerate() {
+                if !is_audible(attendee_i, slot_i) {
+                    continue;
+                }
+                // as `calculate_impact`, with the distance once for every instrument
+                let distance_square = distance2(slot, attendee) as f64;
+                for (value, taste) in values.iter_mut().zip(&attendee.tastes) {
+                    *value += (1000000_f64 * *taste as f64 / distance_square).ceil() as i64;
+                }
+            }
+            values
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::{Attendee, Instrument};
+
+    fn problem() -> Problem {
+        Problem {
+            data: ProblemDto {
+                stage_width: 100.0,
+                stage_height: 20.0,
+                musicians: vec![Instrument(0), Instrument(1), Instrument(2)],
+                attendees: vec![
+                    Attendee {
+                        x: 0.0,
+                        y: 30.0,
+                        tastes: vec![1000.0, 0.0, -1000.0],
+                    },
+                    Attendee {
+                        x: 100.0,
+                        y: 30.0,
+                        tastes: vec![0.0, 1000.0, -1000.0],
+                    },
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reassigns_fixed_positions() {
+        let problem = problem();
+        // every musician at the wrong end
+        let left = Point2D { x: 10.0, y: 15.0 };
+        let middle = Point2D { x: 50.0, y: 15.0 };
+        let right = Point2D { x: 90.0, y: 15.0 };
+        let solution = SolutionDto {
+            placements: vec![right, middle, left],
+            volumes: None,
+        };
+
+        let mut solver = Assign::default();
+        solver.initialize(&problem, solution);
+        let (solution, done) = solver.solve_step();
+        assert!(done);
+        let mut placements = solution.placements.clone();
+        placements.sort_by(|a, b| a.x.total_cmp(&b.x));
+        assert_eq!(placements, vec![left, middle, right]);
+        assert_eq!(solution.placements[0], left);
+        assert_eq!(solution.placements[1], right);
+        assert_eq!(solution.volumes, Some(vec![10.0, 10.0, 0.0]));
+    }
+
+    #[test]
+    fn test_starts_from_nothing() {
+        let problem = problem();
+        let mut solver = Assign::default();
+        let solution = solver.solve(&problem);
+        let placements = &solution.data.placements;
+        assert_eq!(placements.len(), 3);
+        assert!(placements[0].x < placements[1].x);
+        assert_eq!(solution.data.volumes.as_ref().unwrap()[2], 0.0);
+        assert!(solution.score.0 > 0);
+    }
+}
@@ -1,4 +1,7 @@
+use std::time::Duration;
+
 use log::debug;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     common::Grid,
@@ -6,7 +9,13 @@ use crate::{
     scoring::impact_map::ImpactMap,
 };
 
-use super::{Problem, Solver};
+use super::{sub_seed, Deadline, Problem, Solver};
+
+#[derive(Serialize, Deserialize)]
+struct ChainState {
+    step0: bool,
+    stage: serde_json::Value,
+}
 
 #[derive(Clone)]
 pub struct Chain {
@@ -14,6 +23,8 @@ pub struct Chain {
     solver1: Box<dyn Solver>,
     step0: bool,
     problem: Problem,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
 }
 
 impl Solver for Chain {
@@ -33,10 +44,43 @@ impl Solver for Chain {
         self.get_solver().get_problem()
     }
 
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.solver0.set_seed(sub_seed(seed, 0));
+        self.solver1.set_seed(sub_seed(seed, 1));
+    }
+
+    fn stage_count(&self) -> usize {
+        self.solver0.stage_count() + self.solver1.stage_count()
+    }
+
     fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        self.start_stage0(problem);
         self.solver0.initialize(problem, solution);
-        self.step0 = true;
-        self.problem = problem.clone();
+    }
+
+    /// Only the running stage has a state, the first one can't be resumed once done
+    fn checkpoint(&self) -> Option<serde_json::Value> {
+        let state = ChainState {
+            step0: self.step0,
+            stage: self.get_solver().checkpoint()?,
+        };
+        Some(serde_json::to_value(state).unwrap())
+    }
+
+    fn restore(&mut self, problem: &Problem, state: serde_json::Value) -> Result<(), String> {
+        let state: ChainState = serde_json::from_value(state).map_err(|e| e.to_string())?;
+        self.start_stage0(problem);
+        if state.step0 {
+            self.solver0.restore(problem, state.stage)
+        } else {
+            self.step0 = false;
+            self.solver1.set_time_budget(self.deadline.remaining());
+            self.solver1.restore(problem, state.stage)
+        }
     }
 
     fn solve_step(&mut self) -> (SolutionDto, bool) {
@@ -50,6 +94,7 @@ impl Solver for Chain {
                     self.problem.id,
                     self.solver1.name()
                 );
+                self.solver1.set_time_budget(self.deadline.remaining());
                 self.solver1
                     .initialize(self.solver0.get_problem(), s.clone());
                 self.step0 = false;
@@ -66,9 +111,23 @@ impl Chain {
             solver1,
             step0: true,
             problem: Problem::default(),
+            time_budget: None,
+            deadline: Deadline::default(),
         }
     }
 
+    fn start_stage0(&mut self, problem: &Problem) {
+        // Every stage gets the same share, the time left by the first ones goes to the next ones
+        self.deadline = Deadline::start(self.time_budget);
+        self.solver0.set_time_budget(
+            self.time_budget.map(|budget| {
+                budget * self.solver0.stage_count() as u32 / self.stage_count() as u32
+            }),
+        );
+        self.step0 = true;
+        self.problem = problem.clone();
+    }
+
     #[allow(clippy::borrowed_box)]
     fn get_solver(&self) -> &Box<dyn Solver> {
         if self.step0 {
@@ -78,3 +137,92 @@ impl Chain {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::{dto::Point2D, solvers::test_problem};
+
+    /// The budget of every stage started, and when
+    type Starts = Arc<Mutex<Vec<(Option<Duration>, Instant)>>>;
+
+    /// Waits for its time budget, writing down the budget and when it started
+    #[derive(Clone, Default)]
+    struct Waiter {
+        problem: Problem,
+        budget: Option<Duration>,
+        deadline: Deadline,
+        starts: Starts,
+    }
+
+    impl Solver for Waiter {
+        fn name(&self) -> String {
+            "waiter".to_owned()
+        }
+
+        fn set_time_budget(&mut self, budget: Option<Duration>) {
+            self.budget = budget;
+        }
+
+        fn get_problem(&self) -> &Problem {
+            &self.problem
+        }
+
+        fn initialize(&mut self, problem: &Problem, _solution: SolutionDto) {
+            self.problem = problem.clone();
+            self.deadline = Deadline::start(self.budget);
+            self.starts
+                .lock()
+                .unwrap()
+                .push((self.budget, Instant::now()));
+        }
+
+        fn solve_step(&mut self) -> (SolutionDto, bool) {
+            thread::sleep(Duration::from_millis(5));
+            let placements = [20.0, 40.0, 60.0].map(|x| Point2D { x, y: 20.0 }).to_vec();
+            (
+                SolutionDto {
+                    placements,
+                    volumes: None,
+                },
+                self.deadline.passed(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_shares_the_budget() {
+        let waiter = Waiter::default();
+        let starts = waiter.starts.clone();
+        let budget = Duration::from_millis(200);
+        let mut chain = Chain::new(Box::new(waiter.clone()), Box::new(waiter));
+        chain.set_time_budget(Some(budget));
+
+        let start = Instant::now();
+        chain.solve(&test_problem());
+        let elapsed = start.elapsed();
+        // at most one step late
+        assert!(elapsed < budget + Duration::from_millis(20), "{elapsed:?}");
+
+        let starts = starts.lock().unwrap();
+        assert_eq!(starts.len(), 2);
+        assert_eq!(starts[0].0, Some(budget / 2));
+        // what the first one left
+        let left = budget.saturating_sub(starts[1].1 - start);
+        let second = starts[1].0.unwrap();
+        assert!(
+            second <= left + Duration::from_millis(5),
+            "{second:?} {left:?}"
+        );
+        assert!(
+            second + Duration::from_millis(5) >= left,
+            "{second:?} {left:?}"
+        );
+    }
+}
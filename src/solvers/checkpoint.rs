@@ -0,0 +1,98 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{Problem, Solver};
+
+/// State of a running solver, to resume it where it was if the process dies
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    /// As given on the command line, to create the solver again
+    pub pipeline: String,
+    pub solver: String,
+    /// Where the problem was loaded from
+    pub problem: PathBuf,
+    /// Of the run, for the stages which haven't started yet
+    #[serde(default)]
+    pub seed: Option<u64>,
+    pub state: serde_json::Value,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Through a temporary file, so that a crash while saving keeps the previous checkpoint
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        serde_json::to_writer(BufWriter::new(File::create(&tmp_path)?), self)?;
+        std::fs::rename(tmp_path, path)
+    }
+}
+
+/// Saves a checkpoint of a solver at most every `interval`
+pub struct Checkpointer {
+    pipeline: String,
+    seed: Option<u64>,
+    path: PathBuf,
+    interval: Duration,
+    last: Instant,
+}
+
+impl Checkpointer {
+    pub fn new(pipeline: &str, seed: Option<u64>, path: PathBuf, interval: Duration) -> Self {
+        Checkpointer {
+            pipeline: pipeline.to_owned(),
+            seed,
+            path,
+            interval,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Saves the solver state if the last checkpoint is old enough and the solver has one.
+    /// Failing to save is only logged, the run goes on.
+    pub fn tick<S: Solver + ?Sized>(&mut self, problem: &Problem, solver: &S) {
+        if self.last.elapsed() < self.interval {
+            return;
+        }
+        self.last = Instant::now();
+        let Some(state) = solver.checkpoint() else {
+            return;
+        };
+        let checkpoint = Checkpoint {
+            pipeline: self.pipeline.clone(),
+            solver: solver.name(),
+            problem: problem.path.clone(),
+            seed: self.seed,
+            state,
+        };
+        match checkpoint.save(&self.path) {
+            Ok(()) => debug!(
+                "checkpoint({}): saved to {}",
+                problem.id,
+                self.path.display()
+            ),
+            Err(e) => warn!(
+                "checkpoint({}): failed to save to {}: {}",
+                problem.id,
+                self.path.display(),
+                e
+            ),
+        }
+    }
+}
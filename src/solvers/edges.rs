@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use log::debug;
+
+use crate::{
+    common::{calculate_invalid_positions, Grid, Position},
+    dto::{Instrument, Point2D, ProblemDto, SolutionDto},
+    geometry::distance2,
+    scoring::impact_map::{ImpactMap, PillarBlockageMap},
+};
+
+use super::{
+    greedy::Greedy, parameters::name_suffix, Parameter, ParameterKind, ParameterSpec, Problem,
+    Solver,
+};
+
+/// Between two musicians of a row, 10 and a hair so that rounding never brings them closer
+const SPACING: f32 = 10.001;
+/// Between two rows, shifted by half the spacing: the musicians of neighbour rows are
+/// `SPACING` apart too
+const ROW_GAP: f32 = 8.662;
+
+/// Greedy on rows of positions along the edges of the stage, where the musicians closest to
+/// the audience hide nobody: a row on each edge and then rows further in, packed as tightly as
+/// the musicians can stand. The instruments are placed by the value of their `ImpactMap`.
+#[derive(Clone)]
+pub struct Edges {
+    // Parameters
+    parameters_suffix: String,
+    rows: usize,
+    // Data
+    greedy: Greedy,
+}
+
+impl Default for Edges {
+    fn default() -> Self {
+        Self {
+            parameters_suffix: String::new(),
+            rows: 3,
+            greedy: Greedy::default(),
+        }
+    }
+}
+
+impl Solver for Edges {
+    fn name(&self) -> String {
+        format!("edges{}", self.parameters_suffix)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[ParameterSpec {
+            name: "rows",
+            kind: ParameterKind::Int,
+            default: Some("3"),
+            min: Some(1.0),
+            description: "rows along each edge, more when the musicians don't fit",
+        }]
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("rows", Parameter::Int(v)) => self.rows = v as usize,
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
+    }
+
+    fn get_impact_map(&self, instrument: &Instrument) -> Option<&ImpactMap> {
+        self.greedy.get_impact_map(instrument)
+    }
+
+    fn get_grid(&self) -> Option<&Grid> {
+        self.greedy.get_grid()
+    }
+
+    fn get_problem(&self) -> &Problem {
+        self.greedy.get_problem()
+    }
+
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        assert!(
+            solution.placements.is_empty(),
+            "edges({}): must be the start of the chain",
+            problem.id
+        );
+        let data = &problem.data;
+        // the greedy wastes some room where the rows of two edges meet
+        let wanted = data.musicians.len() * 2;
+        let mut rows = self.rows;
+        let mut positions = edge_positions(data, rows);
+        while !fits(&positions, wanted) && rows < max_rows(data) {
+            rows += 1;
+            positions = edge_positions(data, rows);
+        }
+        debug!(
+            "edges({}): {} positions on {} rows per edge for {} musicians",
+            problem.id,
+            positions.len(),
+            rows,
+            data.musicians.len()
+        );
+
+        let grid = Grid {
+            width: positions.len(),
+            height: 1,
+            positions: positions
+                .into_iter()
+                .map(|p| Position { p, taken: false })
+                .collect(),
+        };
+        let pillar_blockage_map = PillarBlockageMap::new(&grid, &data.pillars, &data.attendees);
+        let placements = vec![
+            Point2D {
+                x: f32::NAN,
+                y: f32::NAN,
+            };
+            data.musicians.len()
+        ];
+        self.greedy
+            .start(problem, grid, pillar_blockage_map, placements);
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        let (solution, done) = self.greedy.solve_step();
+        if done {
+            assert!(
+                calculate_invalid_positions(&solution.placements, &self.get_problem().data)
+                    .is_empty(),
+                "edges({}): invalid placements",
+                self.get_problem().id
+            );
+        }
+        (solution, done)
+    }
+}
+
+/// Rows parallel to each edge of the stage, from the edge inwards, every other one shifted by
+/// half the spacing. The rows of different edges cross near the corners.
+fn edge_positions(problem: &ProblemDto, rows: usize) -> Vec<Point2D> {
+    let (left, bottom) = problem.stage_bottom_left;
+    let min_x = left + 10.0;
+    let min_y = bottom + 10.0;
+    let max_x = left + problem.stage_width - 10.0;
+    let max_y = bottom + problem.stage_height - 10.0;
+
+    let mut positions = vec![];
+    for row in 0..rows {
+        let depth = row as f32 * ROW_GAP;
+        let shift = if row % 2 == 0 { 0.0 } else { SPACING / 2.0 };
+        let along = |from: f32, until: f32, at: &mut dyn FnMut(f32)| {
+            let mut i = 0;
+            loop {
+                let v = from + shift + i as f32 * SPACING;
+                if v > until {
+                    break;
+                }
+                at(v);
+                i += 1;
+            }
+        };
+        for y in [min_y + depth, max_y - depth] {
+            if min_y <= y && y <= max_y {
+                along(min_x, max_x, &mut |x| positions.push(Point2D { x, y }));
+            }
+        }
+        for x in [min_x + depth, max_x - depth] {
+            if min_x <= x && x <= max_x {
+                along(min_y, max_y, &mut |y| positions.push(Point2D { x, y }));
+            }
+        }
+    }
+    positions
+}
+
+/// Rows from an edge to the middle of the stage
+fn max_rows(problem: &ProblemDto) -> usize {
+    let depth = (problem.stage_width.max(problem.stage_height) - 20.0) / 2.0;
+    (depth / ROW_GAP) as usize + 1
+}
+
+/// Whether `count` musicians fit on `positions`, taking them in order and skipping those too
+/// close to the ones taken
+fn fits(positions: &[Point2D], count: usize) -> bool {
+    let mut taken: Vec<Point2D> = vec![];
+    for position in positions {
+        if taken.len() >= count {
+            break;
+        }
+        if taken
+            .iter()
+            .all(|t| distance2(t, position) >= SPACING * SPACING)
+        {
+            taken.push(*position);
+        }
+    }
+    taken.len() >= count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::Attendee;
+
+    #[test]
+    fn test_hugs_the_edge_facing_the_fans() {
+        let problem = Problem {
+            data: ProblemDto {
+                stage_width: 100.0,
+                stage_height: 100.0,
+                musicians: vec![Instrument(0), Instrument(1), Instrument(0), Instrument(1)],
+                attendees: vec![
+                    Attendee {
+                        x: 50.0,
+                        y: 200.0,
+                        tastes: vec![1000.0, 10.0],
+                    },
+                    Attendee {
+                        x: -100.0,
+                        y: 50.0,
+                        tastes: vec![10.0, 1000.0],
+                    },
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut solver = Edges::default();
+        let solution = solver.solve(&problem);
+        let placements = &solution.data.placements;
+
+        assert!(calculate_invalid_positions(placements, &problem.data).is_empty());
+        // instrument 0 on the top edge, instrument 1 on the left one
+        assert_eq!(placements[0].y, 90.0);
+        assert_eq!(placements[2].y, 90.0);
+        assert_eq!(placements[1].x, 10.0);
+        assert_eq!(placements[3].x, 10.0);
+    }
+}
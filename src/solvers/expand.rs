@@ -1,16 +1,20 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use log::debug;
 use priority_queue::PriorityQueue;
-use rand::seq::SliceRandom;
+use rand::{seq::SliceRandom, Rng};
 
 use crate::{
+    collider::{Collider, OwnedCollider},
     common::Grid,
     dto::{Point2D, SolutionDto},
     geometry::distance2,
+    scoring::visibility::VisibilityMatrix,
 };
 
-use super::{Parameter, Problem, Score, Solver};
+use super::{
+    parameters::CYCLES_CAP, Deadline, Parameter, ParameterSpec, Problem, Score, Solver, SolverRng,
+};
 
 #[derive(Default, Clone)]
 pub struct Expand {
@@ -20,9 +24,15 @@ pub struct Expand {
     problem: Problem,
     grid: Grid,
     placements: Vec<Point2D>,
+    collider: OwnedCollider,
+    visibility: VisibilityMatrix,
     pq: PriorityQueue<usize, i64>,
     curr_score: Score,
     cycles_count: u32,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+    seed: Option<u64>,
+    rng: SolverRng,
 }
 
 impl Solver for Expand {
@@ -34,6 +44,10 @@ impl Solver for Expand {
         name
     }
 
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[CYCLES_CAP]
+    }
+
     fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
         for (k, v) in parameters.into_iter() {
             match (k.as_str(), v) {
@@ -51,6 +65,14 @@ impl Solver for Expand {
         &self.problem
     }
 
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
     fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
         // NOTE: This can be changed
         assert!(
@@ -59,6 +81,8 @@ impl Solver for Expand {
         );
 
         self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
+        self.rng = SolverRng::new(self.seed);
 
         self.grid = Grid::new(&self.problem);
 
@@ -125,7 +149,11 @@ impl Solver for Expand {
         }
 
         self.pq = pq;
-        self.curr_score = self.problem.score(&self.placements, None); // TODO volumes
+        self.collider = Collider::new_owned(self.problem.data.clone(), self.placements.clone());
+        self.visibility = VisibilityMatrix::new(&self.collider);
+        self.curr_score =
+            self.problem
+                .score_with_visibility(&self.placements, None, &self.visibility); // TODO volumes
         self.cycles_count = 0;
 
         debug!("expand({}): initialized", self.problem.id);
@@ -181,14 +209,24 @@ impl Solver for Expand {
 
         let volumes = None; // TODO volumes
         loop {
-            if rand::random::<u8>() % 10 > 3 {
+            if self.deadline.passed() {
+                debug!("expand({}): out of time", self.problem.id);
+                return (
+                    SolutionDto {
+                        placements: self.placements.clone(),
+                        ..Default::default()
+                    },
+                    true,
+                );
+            }
+            if self.rng.gen_range(0..10) > 3 {
                 // Try expand - move musicians to new positions
 
-                let group_size = rand::random::<usize>() % 3 + 1;
+                let group_size = self.rng.gen_range(1..=3);
 
                 let mut placement_indices = (0..self.placements.len()).collect::<Vec<_>>();
                 let (placement_indices_slice, _) =
-                    placement_indices.partial_shuffle(&mut rand::thread_rng(), group_size);
+                    placement_indices.partial_shuffle(&mut self.rng, group_size);
 
                 // Take out the musicians
                 let old_placements = self.placements.clone();
@@ -208,18 +246,21 @@ impl Solver for Expand {
                     .filter(|p| !p.taken)
                     .collect::<Vec<_>>();
                 let (not_taken_slice, _) =
-                    not_taken_positions.partial_shuffle(&mut rand::thread_rng(), group_size);
+                    not_taken_positions.partial_shuffle(&mut self.rng, group_size);
 
                 let mut new_placements = old_placements.clone();
                 for (idx, pos) in placement_indices_slice.iter().zip(not_taken_slice.iter()) {
                     new_placements[*idx] = pos.p;
                 }
 
-                let new_score = self.problem.score(&new_placements, volumes);
+                self.placements = old_placements.clone();
+                self.move_to(&new_placements);
+                let new_score =
+                    self.problem
+                        .score_with_visibility(&self.placements, volumes, &self.visibility);
                 let diff = new_score.0 - self.curr_score.0;
 
                 if diff > 0 {
-                    self.placements = new_placements;
                     self.curr_score = new_score;
 
                     self.grid.recalculate_taken(&self.placements);
@@ -232,34 +273,36 @@ impl Solver for Expand {
                     );
                     break;
                 } else {
-                    self.placements = old_placements;
+                    self.move_to(&old_placements);
 
                     self.grid.recalculate_taken(&self.placements);
                 }
             } else {
                 // Try shuffle - swap musician positions
 
-                let group_size = rand::random::<usize>() % 3 + 1;
+                let group_size = self.rng.gen_range(1..=3);
 
                 let mut placement_indices = (0..self.placements.len()).collect::<Vec<_>>();
                 let (placement_indices_slice, _) =
-                    placement_indices.partial_shuffle(&mut rand::thread_rng(), group_size * 2);
+                    placement_indices.partial_shuffle(&mut self.rng, group_size * 2);
 
                 let (group_0, group_1) = placement_indices_slice.split_at_mut(group_size);
 
-                let rng = &mut rand::thread_rng();
-                group_0.shuffle(rng);
+                group_0.shuffle(&mut self.rng);
 
                 let mut new_placements = self.placements.clone();
                 for (idx0, idx1) in group_0.iter().zip(group_1.iter()) {
                     new_placements.swap(*idx0, *idx1);
                 }
 
-                let new_score = self.problem.score(&new_placements, volumes);
+                let old_placements = self.placements.clone();
+                self.move_to(&new_placements);
+                let new_score =
+                    self.problem
+                        .score_with_visibility(&self.placements, volumes, &self.visibility);
                 let diff = new_score.0 - self.curr_score.0;
 
                 if diff > 0 {
-                    self.placements = new_placements;
                     self.curr_score = new_score;
 
                     self.cycles_count += 1;
@@ -269,6 +312,8 @@ impl Solver for Expand {
                         self.problem.id, self.cycles_count, group_size
                     );
                     break;
+                } else {
+                    self.move_to(&old_placements);
                 }
             }
         }
@@ -278,7 +323,22 @@ impl Solver for Expand {
                 placements: self.placements.clone(),
                 ..Default::default()
             },
-            self.cycles_count >= self.cycles_cap.unwrap_or(u32::MAX),
+            self.cycles_count >= self.cycles_cap.unwrap_or(u32::MAX) || self.deadline.passed(),
         )
     }
 }
+
+impl Expand {
+    /// Moves the musicians that differ, one at a time, keeping the visibility up to date
+    fn move_to(&mut self, placements: &[Point2D]) {
+        for (musician_i, placement) in placements.iter().enumerate() {
+            if self.placements[musician_i] != *placement {
+                let from = self.placements[musician_i];
+                self.placements[musician_i] = *placement;
+                self.collider.move_musician(musician_i, *placement);
+                self.visibility
+                    .move_musician(&self.collider, musician_i, &from);
+            }
+        }
+    }
+}
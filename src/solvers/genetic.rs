@@ -1,70 +1,224 @@
 use std::{
     cmp::{self},
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    time::Duration,
 };
 
 use log::debug;
 use rand::Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     common::{calculate_invalid_positions, generate_random_placement},
     dto::{Point2D, ProblemDto, SolutionDto},
 };
 
-use super::{Problem, Solver};
+use super::{
+    parameters::name_suffix, Deadline, Parameter, ParameterKind, ParameterSpec, Problem, Solver,
+    SolverRng,
+};
 
 #[derive(Clone, Debug)]
 pub struct Genetic {
     pub population_size: u32,
+    /// The parameters given, for the name
+    parameters_suffix: String,
     problem: Problem,
     population: Vec<Individual>,
     max_generations: u32,
     generation: u32,
+    initial_mutation_rate: f32,
     mutation_rate: f32,
+    min_mutation_rate: f32,
+    max_mutation_rate: f32,
+    /// Generations without improvement before the mutation rate goes up
+    stall: usize,
+    volume_mutation_rate: f32,
     elitism_rate: f32,
     crossover_rate: f32,
     best_fitness: i64,
     generations_without_improvement: usize,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+    seed: Option<u64>,
+    rng: SolverRng,
 }
 
-#[derive(Clone, Debug)]
+/// What `checkpoint` saves, the rest comes from the parameters
+#[derive(Serialize, Deserialize)]
+struct GeneticState {
+    population: Vec<Individual>,
+    generation: u32,
+    mutation_rate: f32,
+    best_fitness: i64,
+    generations_without_improvement: usize,
+    rng: SolverRng,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Individual {
     fitness: i64,
     placements: Vec<Point2D>,
-    volumes: Option<Vec<f32>>,
+    volumes: Vec<f32>,
 }
 
 impl Default for Genetic {
     fn default() -> Self {
         Self {
             population_size: 100,
+            parameters_suffix: String::new(),
             problem: Problem::default(),
             population: Vec::new(),
             max_generations: 100,
             generation: 0,
+            initial_mutation_rate: 0.01,
             mutation_rate: 0.01,
+            min_mutation_rate: 0.01,
+            max_mutation_rate: 0.05,
+            stall: 2,
+            volume_mutation_rate: 0.1,
             elitism_rate: 0.025,
             crossover_rate: 0.75,
             best_fitness: 0,
             generations_without_improvement: 0,
+            time_budget: None,
+            deadline: Deadline::default(),
+            seed: None,
+            rng: SolverRng::default(),
         }
     }
 }
 
 impl Solver for Genetic {
     fn name(&self) -> String {
-        "genetic".to_string()
+        format!("genetic{}", self.parameters_suffix)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[
+            ParameterSpec {
+                name: "population",
+                kind: ParameterKind::Int,
+                default: Some("100"),
+                min: Some(2.0),
+                description: "individuals in every generation",
+            },
+            ParameterSpec {
+                name: "generations",
+                kind: ParameterKind::Int,
+                default: Some("100"),
+                min: Some(0.0),
+                description: "generations before stopping",
+            },
+            ParameterSpec {
+                name: "mutation",
+                kind: ParameterKind::Float,
+                default: Some("0.01"),
+                min: Some(0.0),
+                description: "initial mutation rate, adapted between min_mutation and max_mutation",
+            },
+            ParameterSpec {
+                name: "min_mutation",
+                kind: ParameterKind::Float,
+                default: Some("0.01"),
+                min: Some(0.0),
+                description: "lowest mutation rate, while the best fitness improves",
+            },
+            ParameterSpec {
+                name: "max_mutation",
+                kind: ParameterKind::Float,
+                default: Some("0.05"),
+                min: Some(0.0),
+                description: "highest mutation rate, while the best fitness is stuck",
+            },
+            ParameterSpec {
+                name: "stall",
+                kind: ParameterKind::Int,
+                default: Some("2"),
+                min: Some(1.0),
+                description: "generations without improvement before the mutation rate goes up",
+            },
+            ParameterSpec {
+                name: "volume_mutation",
+                kind: ParameterKind::Float,
+                default: Some("0.1"),
+                min: Some(0.0),
+                description: "chance of a child to have some of its volumes flipped",
+            },
+            ParameterSpec {
+                name: "elitism",
+                kind: ParameterKind::Float,
+                default: Some("0.025"),
+                min: Some(0.0),
+                description: "share of the best individuals kept as they are",
+            },
+            ParameterSpec {
+                name: "crossover",
+                kind: ParameterKind::Float,
+                default: Some("0.75"),
+                min: Some(0.0),
+                description: "share of elites and crossover children in a generation",
+            },
+        ]
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("population", Parameter::Int(v)) => self.population_size = v as u32,
+                ("generations", Parameter::Int(v)) => self.max_generations = v as u32,
+                ("mutation", Parameter::Float(v)) => self.initial_mutation_rate = v as f32,
+                ("min_mutation", Parameter::Float(v)) => self.min_mutation_rate = v as f32,
+                ("max_mutation", Parameter::Float(v)) => self.max_mutation_rate = v as f32,
+                ("stall", Parameter::Int(v)) => self.stall = v as usize,
+                ("volume_mutation", Parameter::Float(v)) => self.volume_mutation_rate = v as f32,
+                ("elitism", Parameter::Float(v)) => self.elitism_rate = v as f32,
+                ("crossover", Parameter::Float(v)) => self.crossover_rate = v as f32,
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
+    }
+
+    fn check_parameters(&self) -> Result<(), String> {
+        if self.min_mutation_rate > self.max_mutation_rate {
+            return Err("`min_mutation` is above `max_mutation`".to_owned());
+        }
+        if !(0.0 < self.crossover_rate && self.crossover_rate <= 1.0) {
+            return Err("`crossover` must be in (0, 1]".to_owned());
+        }
+        if self.elitism_rate >= self.crossover_rate {
+            return Err("`elitism` must be below `crossover`".to_owned());
+        }
+        Ok(())
     }
 
     fn get_problem(&self) -> &Problem {
         &self.problem
     }
 
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
     fn initialize(&mut self, problem: &super::Problem, solution: SolutionDto) {
         self.problem = problem.clone();
-        self.population = self.create_initial_population(self.population_size, &self.problem);
+        self.deadline = Deadline::start(self.time_budget);
+        self.rng = SolverRng::new(self.seed);
+        self.generation = 0;
+        self.mutation_rate = self.initial_mutation_rate;
+        self.generations_without_improvement = 0;
+        self.population = self.create_initial_population(self.population_size);
         if !solution.placements.is_empty() {
             self.population[0].placements = solution.placements;
+            if let Some(volumes) = solution.volumes {
+                self.population[0].volumes = volumes;
+            }
             self.population[0].recalculate_fitness(problem);
         }
 
@@ -72,6 +226,51 @@ impl Solver for Genetic {
         self.best_fitness = self.population[0].fitness;
     }
 
+    fn checkpoint(&self) -> Option<serde_json::Value> {
+        let state = GeneticState {
+            population: self.population.clone(),
+            generation: self.generation,
+            mutation_rate: self.mutation_rate,
+            best_fitness: self.best_fitness,
+            generations_without_improvement: self.generations_without_improvement,
+            rng: self.rng.clone(),
+        };
+        Some(serde_json::to_value(state).unwrap())
+    }
+
+    fn restore(&mut self, problem: &Problem, state: serde_json::Value) -> Result<(), String> {
+        let state: GeneticState = serde_json::from_value(state).map_err(|e| e.to_string())?;
+        let musician_count = problem.data.musicians.len();
+        if state.population.is_empty() {
+            return Err("genetic: empty population".to_owned());
+        }
+        if let Some(individual) = state
+            .population
+            .iter()
+            .find(|i| i.placements.len() != musician_count || i.volumes.len() != musician_count)
+        {
+            return Err(format!(
+                "genetic: {} placements and {} volumes for {} musicians",
+                individual.placements.len(),
+                individual.volumes.len(),
+                musician_count
+            ));
+        }
+        self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
+        self.population = state.population;
+        self.generation = state.generation;
+        self.mutation_rate = state.mutation_rate;
+        self.best_fitness = state.best_fitness;
+        self.generations_without_improvement = state.generations_without_improvement;
+        self.rng = state.rng;
+        debug!(
+            "genetic({}): restored at generation {} out of {}",
+            self.problem.id, self.generation, self.max_generations
+        );
+        Ok(())
+    }
+
     fn solve_step(&mut self) -> (SolutionDto, bool) {
         debug!(
             "generation: {} out of {}",
@@ -81,7 +280,7 @@ impl Solver for Genetic {
         self.selection();
 
         self.generation += 1;
-        let is_finished = self.generation >= self.max_generations;
+        let is_finished = self.generation >= self.max_generations || self.deadline.passed();
 
         let best_population = self.population.first().expect("population is empty");
 
@@ -94,12 +293,12 @@ impl Solver for Genetic {
             self.generations_without_improvement += 1;
         } else {
             self.generations_without_improvement = 0;
-            self.mutation_rate = (self.mutation_rate - 0.005).max(0.01);
+            self.mutation_rate = (self.mutation_rate - 0.005).max(self.min_mutation_rate);
             debug!("decreased mutation rate to {}", self.mutation_rate);
         }
 
-        if self.generations_without_improvement > 2 {
-            self.mutation_rate = (self.mutation_rate + 0.0025).min(0.05);
+        if self.generations_without_improvement > self.stall {
+            self.mutation_rate = (self.mutation_rate + 0.0025).min(self.max_mutation_rate);
             debug!("increased mutation rate to {}", self.mutation_rate);
         }
 
@@ -107,7 +306,7 @@ impl Solver for Genetic {
 
         let solution = SolutionDto {
             placements: best_population.placements.clone(),
-            volumes: best_population.volumes.clone(),
+            volumes: Some(best_population.volumes.clone()),
         };
 
         (solution, is_finished)
@@ -115,42 +314,47 @@ impl Solver for Genetic {
 }
 
 impl Genetic {
-    fn create_initial_population(
-        &self,
-        population_size: u32,
-        problem: &Problem,
-    ) -> Vec<Individual> {
+    fn create_initial_population(&mut self, population_size: u32) -> Vec<Individual> {
+        let problem = &self.problem;
         let mut population = Vec::new();
 
         for _ in 0..population_size {
             let mut placements = Vec::new();
 
             for _ in 0..problem.data.musicians.len() {
-                placements.push(generate_random_placement(&problem.data, &placements));
+                placements.push(generate_random_placement(
+                    &mut self.rng,
+                    &problem.data,
+                    &placements,
+                ));
             }
 
             let len = placements.len();
 
-            let individual = Individual {
-                fitness: problem.score(&placements, None).0,
+            // the volumes start loud, their mutation mutes some musicians
+            population.push(Individual {
+                fitness: 0,
                 placements,
-                volumes: Some(vec![10.0; len]), // TODO volumes
-            };
-
-            population.push(individual);
-
-            debug!(
-                "Created individual with fitness {}",
-                population[population.len() - 1].fitness
-            );
+                volumes: vec![10.0; len],
+            });
         }
 
         population
-    }
+            .par_iter_mut()
+            .for_each(|individual| individual.recalculate_fitness(problem));
+        debug!(
+            "Created {} individuals, best fitness {:?}",
+            population.len(),
+            population.iter().map(|i| i.fitness).max()
+        );
 
-    fn roulette_wheel_selection(population: &Vec<Individual>) -> &Individual {
-        let mut rng = rand::thread_rng();
+        population
+    }
 
+    fn roulette_wheel_selection<'a>(
+        rng: &mut SolverRng,
+        population: &'a [Individual],
+    ) -> &'a Individual {
         // Calculate the total fitness of the population
         let total_fitness: i64 = population.iter().map(|individual| individual.fitness).sum();
 
@@ -177,7 +381,8 @@ impl Genetic {
     }
 
     fn selection(&mut self) {
-        let mut rng = rand::thread_rng();
+        // the children need `self` while the random numbers go on
+        let mut rng = self.rng.clone();
         let mut new_population = Vec::new();
 
         // Elitism: keep x% of the best individuals
@@ -190,27 +395,43 @@ impl Genetic {
             );
         }
 
-        for _ in 0..(self.population_size as f32 * self.crossover_rate) as usize - elitism_size {
-            let parent1 = Self::roulette_wheel_selection(&self.population);
-            let parent2 = Self::roulette_wheel_selection(&self.population);
-
-            let (mut child1, mut child2) = self.pmx_crossover(parent1, parent2);
-
-            if rng.gen_range(0.0..1.0) < self.mutation_rate {
-                child1.mutate(&self.problem.data);
+        // the crossovers make the next generation up to its share, copies of parents the rest
+        let born = self.population_size as usize - elitism_size;
+        let crossed = ((self.population_size as f32 * self.crossover_rate) as usize)
+            .saturating_sub(elitism_size)
+            .min(born);
+        let mut children = Vec::with_capacity(born);
+        while children.len() < crossed {
+            let parent1 = Self::roulette_wheel_selection(&mut rng, &self.population);
+            let parent2 = Self::roulette_wheel_selection(&mut rng, &self.population);
+
+            let (child1, child2) = self.pmx_crossover(&mut rng, parent1, parent2);
+            children.push(child1);
+            if children.len() < crossed {
+                children.push(child2);
             }
+        }
+        while children.len() < born {
+            let parent = Self::roulette_wheel_selection(&mut rng, &self.population);
+            children.push(parent.clone());
+        }
 
+        for child in &mut children {
             if rng.gen_range(0.0..1.0) < self.mutation_rate {
-                child2.mutate(&self.problem.data);
+                child.mutate(&mut rng, &self.problem.data);
+            }
+            if rng.gen_range(0.0..1.0) < self.volume_mutation_rate {
+                child.mutate_volumes(&mut rng);
             }
-
-            child1.recalculate_fitness(&self.problem);
-            child2.recalculate_fitness(&self.problem);
-
-            new_population.push(child1);
-            new_population.push(child2);
         }
 
+        // the children are all made first, so that the random numbers don't depend on threads
+        children
+            .par_iter_mut()
+            .for_each(|child| child.recalculate_fitness(&self.problem));
+        new_population.extend(children);
+
+        self.rng = rng;
         self.population = new_population;
         self.population.sort_by_key(|x| cmp::Reverse(x.fitness));
 
@@ -222,10 +443,10 @@ impl Genetic {
 
     fn _swap_crossover(
         &self,
+        rng: &mut SolverRng,
         parent1: &Individual,
         parent2: &Individual,
     ) -> (Individual, Individual) {
-        let mut rng = rand::thread_rng();
         let size = parent1.placements.len();
 
         // Children start as exact copies of parents
@@ -240,20 +461,22 @@ impl Genetic {
             // Swap the positions of this musician in the children
             child1.placements[musician] = parent2.placements[musician];
             child2.placements[musician] = parent1.placements[musician];
+            child1.volumes[musician] = parent2.volumes[musician];
+            child2.volumes[musician] = parent1.volumes[musician];
         }
 
-        random_repair_invalid_positions(&self.problem.data, &mut child1.placements);
-        random_repair_invalid_positions(&self.problem.data, &mut child2.placements);
+        random_repair_invalid_positions(rng, &self.problem.data, &mut child1.placements);
+        random_repair_invalid_positions(rng, &self.problem.data, &mut child2.placements);
 
         (child1, child2)
     }
 
     fn pmx_crossover(
         &self,
+        rng: &mut SolverRng,
         parent1: &Individual,
         parent2: &Individual,
     ) -> (Individual, Individual) {
-        let mut rng = rand::thread_rng();
         let size = parent1.placements.len();
 
         // Select two random crossover points
@@ -264,9 +487,11 @@ impl Genetic {
         let mut child1 = parent1.clone();
         let mut child2 = parent2.clone();
 
-        // Swap segments between points
+        // Swap segments between points, the volumes go with them
         child1.placements[point1..point2].copy_from_slice(&parent2.placements[point1..point2]);
         child2.placements[point1..point2].copy_from_slice(&parent1.placements[point1..point2]);
+        child1.volumes[point1..point2].copy_from_slice(&parent2.volumes[point1..point2]);
+        child2.volumes[point1..point2].copy_from_slice(&parent1.volumes[point1..point2]);
         assert!(parent1.placements.len() == parent2.placements.len());
         assert!(child1.placements.len() == child2.placements.len());
 
@@ -275,8 +500,8 @@ impl Genetic {
         self.resolve_conflicts(&mut child2, parent2, parent1, point1, point2);
 
         // Repair invalid positions
-        random_repair_invalid_positions(&self.problem.data, &mut child1.placements);
-        random_repair_invalid_positions(&self.problem.data, &mut child2.placements);
+        random_repair_invalid_positions(rng, &self.problem.data, &mut child1.placements);
+        random_repair_invalid_positions(rng, &self.problem.data, &mut child2.placements);
 
         (child1, child2)
     }
@@ -315,12 +540,16 @@ impl Genetic {
     }
 }
 
-fn random_repair_invalid_positions(problem: &ProblemDto, placements: &mut [Point2D]) {
+fn random_repair_invalid_positions(
+    rng: &mut impl Rng,
+    problem: &ProblemDto,
+    placements: &mut [Point2D],
+) {
     let mut invalid_positions = calculate_invalid_positions(placements, problem);
 
     while !invalid_positions.is_empty() {
         for invalid_position in invalid_positions.iter() {
-            let placement = generate_random_placement(problem, placements);
+            let placement = generate_random_placement(rng, problem, placements);
             placements[*invalid_position] = placement;
         }
 
@@ -330,11 +559,24 @@ fn random_repair_invalid_positions(problem: &ProblemDto, placements: &mut [Point
 
 impl Individual {
     fn recalculate_fitness(&mut self, problem: &Problem) {
-        self.fitness = problem.score(&self.placements, self.volumes.as_ref()).0;
+        self.fitness = problem.score(&self.placements, Some(&self.volumes)).0;
     }
 
-    fn mutate(&mut self, problem: &ProblemDto) {
-        let mut rng = rand::thread_rng();
+    /// The score is linear in every volume, so the best ones are 0 or 10: a few musicians
+    /// switch from one to the other
+    fn mutate_volumes(&mut self, rng: &mut impl Rng) {
+        let max_mutation_size = (self.volumes.len() / 20).max(1);
+        for _ in 0..rng.gen_range(1..=max_mutation_size) {
+            let musician = rng.gen_range(0..self.volumes.len());
+            self.volumes[musician] = if self.volumes[musician] < 5.0 {
+                10.0
+            } else {
+                0.0
+            };
+        }
+    }
+
+    fn mutate(&mut self, rng: &mut impl Rng, problem: &ProblemDto) {
         let mutation_type = rng.gen_range(0..3);
         let max_mutation_size = (self.placements.len() / 20).max(1);
         let mutation_size = if max_mutation_size > 1 {
@@ -354,10 +596,10 @@ impl Individual {
                         placement_set.insert(*placement);
                     }
 
-                    let mut placement = generate_random_placement(problem, &self.placements);
+                    let mut placement = generate_random_placement(rng, problem, &self.placements);
 
                     while placement_set.contains(&placement) {
-                        placement = generate_random_placement(problem, &self.placements);
+                        placement = generate_random_placement(rng, problem, &self.placements);
                     }
 
                     self.placements[musician] = placement;
@@ -417,3 +659,86 @@ impl Individual {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solvers::test_problem;
+
+    fn new_solver() -> Genetic {
+        let mut solver = Genetic::default();
+        solver.set_parameters(HashMap::from([(
+            "population".to_owned(),
+            Parameter::Int(10),
+        )]));
+        solver
+    }
+
+    #[test]
+    fn test_generations_keep_their_size() {
+        let problem = test_problem();
+        for (population, crossover) in [(2, 1.0), (3, 0.1), (10, 0.75)] {
+            let mut solver = Genetic::default();
+            solver.set_parameters(HashMap::from([
+                ("population".to_owned(), Parameter::Int(population)),
+                ("crossover".to_owned(), Parameter::Float(crossover)),
+                ("generations".to_owned(), Parameter::Int(3)),
+            ]));
+            solver.set_seed(1);
+            solver.solve(&problem);
+            assert_eq!(solver.population.len(), population as usize);
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_run() {
+        let problem = test_problem();
+        let run = || {
+            let mut solver = new_solver();
+            solver.set_seed(7);
+            solver.initialize(&problem, SolutionDto::default());
+            (0..3).map(|_| solver.solve_step().0).last().unwrap()
+        };
+        assert_eq!(run().placements, run().placements);
+    }
+
+    #[test]
+    fn test_volumes_are_inherited() {
+        let mut solver = new_solver();
+        solver.initialize(&test_problem(), SolutionDto::default());
+        let mut rng = SolverRng::new(Some(1));
+        let mut quiet = solver.population[0].clone();
+        quiet.volumes = vec![0.0; 3];
+        let loud = solver.population[1].clone();
+
+        // every musician gets its volume from one parent, the other child from the other one
+        let (child1, child2) = solver.pmx_crossover(&mut rng, &quiet, &loud);
+        for (v1, v2) in child1.volumes.iter().zip(child2.volumes.iter()) {
+            assert_eq!(v1 + v2, 10.0);
+        }
+
+        quiet.mutate_volumes(&mut rng);
+        assert_eq!(quiet.volumes.iter().sum::<f32>(), 10.0);
+    }
+
+    #[test]
+    fn test_resumes_from_checkpoint() {
+        let problem = test_problem();
+        let mut solver = new_solver();
+        solver.initialize(&problem, SolutionDto::default());
+        solver.solve_step();
+        let state = solver.checkpoint().unwrap();
+        let (expected, _) = solver.solve_step();
+
+        // the same random numbers give the same generation
+        let mut resumed = new_solver();
+        resumed.restore(&problem, state).unwrap();
+        let (solution, _) = resumed.solve_step();
+        assert_eq!(solution.placements, expected.placements);
+        assert_eq!(resumed.generation, 2);
+
+        let mut state = solver.checkpoint().unwrap();
+        state["population"] = serde_json::json!([]);
+        assert!(new_solver().restore(&problem, state).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::debug;
+use rayon::prelude::*;
+
+use crate::{
+    collider::{Collider, OwnedCollider},
+    dto::{Point2D, SolutionDto},
+    geometry::distance2,
+    scoring::visibility::VisibilityMatrix,
+};
+
+use super::{
+    parameters::{name_suffix, CYCLES_CAP},
+    Deadline, Parameter, ParameterKind, ParameterSpec, Problem, Solver,
+};
+
+/// Below this, a musician has converged
+const MIN_STEP: f64 = 0.01;
+/// Distance kept between two musicians, a bit more than 10 against rounding
+const SEPARATION: f64 = 10.0001;
+
+/// Moves the musicians one at a time along the gradient of the score, projected back onto the
+/// stage and away from the other musicians.
+///
+/// As long as nobody starts or stops hiding anyone, the score is a smooth function of the
+/// positions: `1e6 × taste / d²` per attendee, times the closeness. Each step keeps the
+/// visibility of the pairs as it is, and only when the incremental update of the visibility
+/// reports a change (the step crossed a blocking boundary) are the impacts summed again and
+/// the step checked against the score with the new visibility.
+#[derive(Clone)]
+pub struct Gradient {
+    // Parameters
+    parameters_suffix: String,
+    initial_step: f64,
+    cycles_cap: Option<u32>,
+    // Data
+    problem: Problem,
+    placements: Vec<Point2D>,
+    volumes: Option<Vec<f32>>,
+    collider: OwnedCollider,
+    visibility: VisibilityMatrix,
+    /// Sum of the impacts of every musician at volume 1 on the attendees who hear it
+    impacts: Vec<f64>,
+    /// Of the next step of every musician, grows when it improves and halves when not
+    steps: Vec<f64>,
+    /// The musicians of every instrument, for the closeness
+    same_instrument: Vec<Vec<usize>>,
+    cycles_count: u32,
+    crossings: usize,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Self {
+            parameters_suffix: String::new(),
+            initial_step: 1.0,
+            cycles_cap: None,
+            problem: Problem::default(),
+            placements: vec![],
+            volumes: None,
+            collider: OwnedCollider::default(),
+            visibility: VisibilityMatrix::default(),
+            impacts: vec![],
+            steps: vec![],
+            same_instrument: vec![],
+            cycles_count: 0,
+            crossings: 0,
+            time_budget: None,
+            deadline: Deadline::default(),
+        }
+    }
+}
+
+impl Solver for Gradient {
+    fn name(&self) -> String {
+        format!("gradient{}", self.parameters_suffix)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[
+            ParameterSpec {
+                name: "step",
+                kind: ParameterKind::Float,
+                default: Some("1"),
+                min: Some(MIN_STEP),
+                description: "length of the first step of every musician",
+            },
+            CYCLES_CAP,
+        ]
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("step", Parameter::Float(v)) => self.initial_step = v,
+                ("cap", Parameter::Int(v)) => self.cycles_cap = Some(v as u32),
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
+    }
+
+    fn get_problem(&self) -> &Problem {
+        &self.problem
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        assert!(
+            !solution.placements.is_empty(),
+            "gradient({}): must not be the start of the chain",
+            problem.id
+        );
+        self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
+        self.placements = solution.placements;
+        self.volumes = solution.volumes;
+        self.collider = Collider::new_owned(problem.data.clone(), self.placements.clone());
+        self.visibility = VisibilityMatrix::new(&self.collider);
+        let instrument_count = problem.data.musicians.iter().map(|i| i.0).max().unwrap() + 1;
+        self.same_instrument = vec![vec![]; instrument_count as usize];
+        for (musician_i, instrument) in problem.data.musicians.iter().enumerate() {
+            self.same_instrument[instrument.0 as usize].push(musician_i);
+        }
+        self.sum_impacts();
+        self.steps = vec![self.initial_step; self.placements.len()];
+        self.cycles_count = 0;
+        self.crossings = 0;
+        debug!(
+            "gradient({}): initialized, model score {:.0}",
+            problem.id,
+            self.model_score()
+        );
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        let mut moved = 0;
+        for musician_i in 0..self.placements.len() {
+            if self.deadline.passed() {
+                debug!("gradient({}): out of time", self.problem.id);
+                return (self.serialize(), true);
+            }
+            if self.steps[musician_i] < MIN_STEP {
+                continue;
+            }
+            if self.step(musician_i) {
+                moved += 1;
+                self.steps[musician_i] *= 1.5;
+            } else {
+                self.steps[musician_i] /= 2.0;
+            }
+        }
+        self.cycles_count += 1;
+
+        let converged = self.steps.iter().all(|&step| step < MIN_STEP);
+        debug!(
+            "gradient({}): cycle {} moved {} musicians, model score {:.0}, {} crossings",
+            self.problem.id,
+            self.cycles_count,
+            moved,
+            self.model_score(),
+            self.crossings
+        );
+        let capped = self.cycles_cap.is_some_and(|cap| self.cycles_count >= cap);
+        (self.serialize(), converged || capped)
+    }
+}
+
+impl Gradient {
+    fn serialize(&self) -> SolutionDto {
+        SolutionDto {
+            placements: self.placements.clone(),
+            volumes: self.volumes.clone(),
+        }
+    }
+
+    fn volume(&self, musician_i: usize) -> f64 {
+        self.volumes.as_ref().map_or(1.0, |v| v[musician_i] as f64)
+    }
+
+    fn has_closeness(&self) -> bool {
+        !self.problem.data.pillars.is_empty()
+    }
+
+    fn same_instrument_as(&self, musician_i: usize) -> &[usize] {
+        &self.same_instrument[self.problem.data.musicians[musician_i].0 as usize]
+    }
+
+    /// The score as the model sees it, without the rounding of each pair
+    fn model_score(&self) -> f64 {
+        (0..self.placements.len())
+            .map(|i| self.volume(i) * self.closeness(i, &self.placements[i]) * self.impacts[i])
+            .sum()
+    }
+
+    fn sum_impacts(&mut self) {
+        self.impacts = (0..self.placements.len())
+            .into_par_iter()
+            .map(|musician_i| {
+                self.impact_and_gradient(musician_i, &self.placements[musician_i])
+                    .0
+            })
+            .collect();
+    }
+
+    /// Sum of the impacts of the musician at `position` on the attendees who hear it now,
+    /// and its gradient
+    fn impact_and_gradient(&self, musician_i: usize, position: &Point2D) -> (f64, (f64, f64)) {
+        let instrument = self.problem.data.musicians[musician_i].0 as usize;
+        let (x, y) = (position.x as f64, position.y as f64);
+        let mut impact = 0.0;
+        let mut gradient = (0.0, 0.0);
+        for (attendee_i, attendee) in self.problem.data.attendees.iter().enumerate() {
+            if !self.visibility.is_audible(attendee_i, musician_i) {
+                continue;
+            }
+            let (dx, dy) = (x - attendee.x as f64, y - attendee.y as f64);
+            let d2 = dx * dx + dy * dy;
+            let value = 1_000_000.0 * attendee.tastes[instrument] as f64 / d2;
+            impact += value;
+            // d(1/d²) = -2 (p - a) / d⁴
+            gradient.0 -= 2.0 * value * dx / d2;
+            gradient.1 -= 2.0 * value * dy / d2;
+        }
+        (impact, gradient)
+    }
+
+    /// Closeness factor of the musician if it were at `position`
+    fn closeness(&self, musician_i: usize, position: &Point2D) -> f64 {
+        if !self.has_closeness() {
+            return 1.0;
+        }
+        1.0 + self
+            .same_instrument_as(musician_i)
+            .iter()
+            .filter(|&&other_i| other_i != musician_i)
+            .map(|&other_i| 1.0 / (distance2(position, &self.placements[other_i]) as f64).sqrt())
+            .sum::<f64>()
+    }
+
+    /// The part of the model score which depends on where the musician is, and its gradient:
+    /// its own impacts times its closeness, and the closeness it brings to the musicians
+    /// with the same instrument
+    fn local_score(&self, musician_i: usize, position: &Point2D) -> (f64, f64, (f64, f64)) {
+        let (impact, impact_gradient) = self.impact_and_gradient(musician_i, position);
+        let volume = self.volume(musician_i);
+        let closeness = self.closeness(musician_i, position);
+        let mut score = volume * closeness * impact;
+        let mut gradient = (
+            volume * closeness * impact_gradient.0,
+            volume * closeness * impact_gradient.1,
+        );
+        if self.has_closeness() {
+            for &other_i in self.same_instrument_as(musician_i) {
+                if other_i == musician_i {
+                    continue;
+                }
+                let other = &self.placements[other_i];
+                let (dx, dy) = (
+                    position.x as f64 - other.x as f64,
+                    position.y as f64 - other.y as f64,
+                );
+                let d = (dx * dx + dy * dy).sqrt();
+                // both closeness factors have 1/d, weighted by the impacts they multiply
+                let weight = volume * impact + self.volume(other_i) * self.impacts[other_i];
+                score += self.volume(other_i) * self.impacts[other_i] / d;
+                gradient.0 -= weight * dx / (d * d * d);
+                gradient.1 -= weight * dy / (d * d * d);
+            }
+        }
+        (score, impact, gradient)
+    }
+
+    /// Tries a step of the musician along its gradient, returns whether it moved
+    fn step(&mut self, musician_i: usize) -> bool {
+        let from = self.placements[musician_i];
+        let (score, _, gradient) = self.local_score(musician_i, &from);
+        let norm = (gradient.0 * gradient.0 + gradient.1 * gradient.1).sqrt();
+        if norm == 0.0 || !norm.is_finite() {
+            return false;
+        }
+        let step = self.steps[musician_i];
+        let target = (
+            from.x as f64 + gradient.0 / norm * step,
+            from.y as f64 + gradient.1 / norm * step,
+        );
+        let Some(to) = self.project(musician_i, target) else {
+            return false;
+        };
+        let (new_score, new_impact, _) = self.local_score(musician_i, &to);
+        if new_score <= score {
+            return false;
+        }
+
+        let before = self.model_score();
+        self.move_musician(musician_i, to);
+        if self.visibility_changed(musician_i, &from) == 0 {
+            self.impacts[musician_i] = new_impact;
+            return true;
+        }
+        // someone starts or stops hiding someone, the model changes
+        self.crossings += 1;
+        self.sum_impacts();
+        if self.model_score() > before {
+            return true;
+        }
+        self.move_musician(musician_i, from);
+        self.visibility_changed(musician_i, &to);
+        self.sum_impacts();
+        false
+    }
+
+    fn move_musician(&mut self, musician_i: usize, to: Point2D) {
+        self.placements[musician_i] = to;
+        self.collider.move_musician(musician_i, to);
+    }
+
+    /// Updates the visibility once the musician moved from `from`, returns the changed pairs
+    fn visibility_changed(&mut self, musician_i: usize, from: &Point2D) -> usize {
+        self.visibility
+            .move_musician(&self.collider, musician_i, from)
+    }
+
+    /// The closest valid position to `target`: on the stage, away from the other musicians.
+    /// `None` when pushing it away from one musician keeps bumping it into another.
+    fn project(&self, musician_i: usize, target: (f64, f64)) -> Option<Point2D> {
+        let data = &self.problem.data;
+        let (left, bottom) = data.stage_bottom_left;
+        let min_x = left as f64 + 10.0;
+        let min_y = bottom as f64 + 10.0;
+        let max_x = (left + data.stage_width) as f64 - 10.0;
+        let max_y = (bottom + data.stage_height) as f64 - 10.0;
+        let clamp = |(x, y): (f64, f64)| (x.clamp(min_x, max_x), y.clamp(min_y, max_y));
+
+        let mut position = clamp(target);
+        for _ in 0..4 {
+            let mut pushed = false;
+            for (other_i, other) in self.placements.iter().enumerate() {
+                if other_i == musician_i {
+                    continue;
+                }
+                let (dx, dy) = (position.0 - other.x as f64, position.1 - other.y as f64);
+                let d = (dx * dx + dy * dy).sqrt();
+                if d >= SEPARATION {
+                    continue;
+                }
+                if d == 0.0 {
+                    return None;
+                }
+                position = (
+                    other.x as f64 + dx / d * SEPARATION,
+                    other.y as f64 + dy / d * SEPARATION,
+                );
+                pushed = true;
+            }
+            position = clamp(position);
+            if !pushed {
+                break;
+            }
+        }
+
+        let position = Point2D {
+            x: position.0 as f32,
+            y: position.1 as f32,
+        };
+        let valid =
+            self.placements.iter().enumerate().all(|(other_i, other)| {
+                other_i == musician_i || distance2(&position, other) >= 100.0
+            });
+        valid.then_some(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::calculate_invalid_positions,
+        dto::{Attendee, Instrument, PillarDto, ProblemDto},
+        scoring::{new_scorer::NewScorer, Scorer},
+    };
+
+    #[test]
+    fn test_climbs_towards_the_fans() {
+        let problem = Problem {
+            data: ProblemDto {
+                stage_width: 100.0,
+                stage_height: 100.0,
+                musicians: vec![Instrument(0), Instrument(0), Instrument(1)],
+                attendees: vec![Attendee {
+                    x: 50.0,
+                    y: 150.0,
+                    tastes: vec![1000.0, 500.0],
+                }],
+                // far away, only for the closeness
+                pillars: vec![PillarDto {
+                    center: (500.0, 500.0),
+                    radius: 1.0,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let solution = SolutionDto {
+            placements: vec![
+                Point2D { x: 20.0, y: 20.0 },
+                Point2D { x: 80.0, y: 20.0 },
+                Point2D { x: 50.0, y: 50.0 },
+            ],
+            volumes: None,
+        };
+        let start = NewScorer.score(&problem.data, &solution.placements, None);
+
+        let mut solver = Gradient::default();
+        solver.initialize(&problem, solution);
+        let solution = solver.finish(&problem, None);
+
+        assert!(solution.score.0 > start.0);
+        assert!(calculate_invalid_positions(&solution.data.placements, &problem.data).is_empty());
+        // everyone went up, where nobody hides anyone from the only attendee
+        assert!(solution.data.placements.iter().all(|p| p.y > 50.0));
+        let model = solver.model_score();
+        assert!((model - solution.score.0 as f64).abs() < 10.0, "{model}");
+    }
+}
@@ -1,13 +1,18 @@
-use log::debug;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use log::{debug, trace};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use rayon::prelude::*;
 
 use crate::{
+    collider::Collider,
     common::Grid,
     dto::{Instrument, Point2D, SolutionDto},
     geometry::distance2,
-    scoring::impact_map::{ImpactMap, PillarBlockageMap},
+    scoring::{
+        impact_map::{ImpactMap, PillarBlockageMap},
+        scorer::{calculate_impact, is_sound_blocked},
+        visibility::VisibilityMatrix,
+    },
 };
 
 use super::{Problem, Solver};
@@ -17,9 +22,11 @@ pub struct Greedy {
     problem: Problem,
     grid: Grid,
     placements: Vec<Point2D>,
-    remaining_musicians: HashSet<usize>,
+    /// In order, so that the same instrument always goes to the same musician
+    remaining_musicians: BTreeSet<usize>,
     impact_maps: HashMap<Instrument, ImpactMap>,
     pillar_blockage_map: PillarBlockageMap,
+    visibility: VisibilityMatrix,
 }
 
 impl Solver for Greedy {
@@ -46,59 +53,44 @@ impl Solver for Greedy {
             problem.id
         );
 
-        self.problem = problem.clone();
-
-        self.grid = Grid::new(&self.problem);
-
-        let max_instrument = self
-            .problem
-            .data
-            .musicians
-            .iter()
-            .map(|i| i.0)
-            .max()
-            .unwrap();
-
-        for i in 0..self.problem.data.musicians.len() {
-            self.remaining_musicians.insert(i);
-            self.placements.push(Point2D {
+        let grid = Grid::new(problem);
+        debug!("greedy({}): computing pillar blockage map", problem.id);
+        let pillar_blockage_map = PillarBlockageMap::load_or_new(problem, &grid);
+        let placements = vec![
+            Point2D {
                 x: f32::NAN,
                 y: f32::NAN,
-            });
-        }
+            };
+            problem.data.musicians.len()
+        ];
+        self.start(problem, grid, pillar_blockage_map, placements);
+    }
 
-        debug!("greedy({}): computing pillar blockage map", self.problem.id);
-        self.pillar_blockage_map = PillarBlockageMap::new(
-            &self.grid,
-            &self.problem.data.pillars,
-            &self.problem.data.attendees,
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        assert!(
+            self.place_next(),
+            "greedy({}): no free position left",
+            self.problem.id
         );
         debug!(
-            "greedy({}): {} blocked pairs",
+            "greedy({}): {} musicians left",
             self.problem.id,
-            self.pillar_blockage_map.blocked_positions.len()
+            self.remaining_musicians.len()
         );
 
-        debug!("greedy({}): computing impact maps", self.problem.id);
-        self.impact_maps = (0..=max_instrument)
-            .map(Instrument)
-            .collect::<Vec<_>>()
-            .par_iter()
-            .map(|i| {
-                let impact_map = ImpactMap::new(
-                    i,
-                    &self.problem.data.attendees,
-                    &self.grid,
-                    &self.pillar_blockage_map,
-                );
-                (*i, impact_map)
-            })
-            .collect();
-
-        debug!("greedy({}): initialized", self.problem.id);
+        (
+            SolutionDto {
+                placements: self.placements.clone(),
+                ..Default::default()
+            },
+            self.remaining_musicians.is_empty(),
+        )
     }
+}
 
-    fn solve_step(&mut self) -> (SolutionDto, bool) {
+impl Greedy {
+    /// Places the best remaining musician on the best free position, false when there is none
+    pub(super) fn place_next(&mut self) -> bool {
         let mut best_pos_idx = usize::MAX;
         let mut best_instrument = Instrument(u32::MAX);
         let mut best_score = i64::MIN;
@@ -124,6 +116,9 @@ impl Solver for Greedy {
             }
         }
 
+        if best_pos_idx == usize::MAX {
+            return false;
+        }
         let idx = *self
             .remaining_musicians
             .iter()
@@ -146,39 +141,203 @@ impl Solver for Greedy {
 
         if remaining_instruments[&best_instrument] == 0 {
             remaining_instruments.remove(&best_instrument);
+        } else if !self.problem.data.pillars.is_empty() {
+            // The next ones of the instrument are better off close to this one
+            self.impact_maps
+                .get_mut(&best_instrument)
+                .unwrap()
+                .add_peer(&self.grid, best_pos_idx);
         }
 
-        let blocked_positions = ImpactMap::calculate_blocked_positions(
-            &best_pos.p,
+        // Only the pairs that were still audible lose their impact, taken positions don't matter anymore
+        let newly_hidden = self.visibility.add_obstacle(
+            &self.grid.positions,
             &self.problem.data.attendees,
-            &self.grid,
+            &best_pos.p,
+            5.0,
+            |idx_pos| self.grid.positions[idx_pos].taken,
         );
         self.impact_maps.par_iter_mut().for_each(|(i, im)| {
             if !remaining_instruments.contains_key(i) {
                 return;
             }
             im.update(
-                &best_instrument,
+                i,
                 &self.problem.data.attendees,
                 &self.grid,
                 &new_taken_positions,
-                &blocked_positions,
-                &self.pillar_blockage_map,
+                &newly_hidden,
             );
         });
 
-        debug!(
-            "greedy({}): {} musicians left",
+        true
+    }
+
+    /// Where the musicians are, NaN for the ones not placed yet
+    pub(super) fn placements(&self) -> &[Point2D] {
+        &self.placements
+    }
+
+    /// Places the musicians one at a time on the positions of `grid`, see `Edges` for another
+    /// set of positions than the full grid. Only the musicians at NaN in `placements` are
+    /// placed, the others stay where they are: they take the positions around them and hide
+    /// the ones behind them.
+    pub(super) fn start(
+        &mut self,
+        problem: &Problem,
+        grid: Grid,
+        pillar_blockage_map: PillarBlockageMap,
+        placements: Vec<Point2D>,
+    ) {
+        self.problem = problem.clone();
+        self.grid = grid;
+
+        self.remaining_musicians = (0..placements.len())
+            .filter(|&i| placements[i].x.is_nan())
+            .collect();
+        self.placements = placements;
+        self.grid.recalculate_taken(&self.placements);
+
+        self.pillar_blockage_map = pillar_blockage_map;
+        trace!(
+            "greedy({}): {} blocked pairs",
             self.problem.id,
-            self.remaining_musicians.len()
+            self.pillar_blockage_map.blocked_pairs()
         );
 
-        (
-            SolutionDto {
-                placements: self.placements.clone(),
-                ..Default::default()
+        self.visibility = VisibilityMatrix::for_positions(
+            &self.grid,
+            &self.problem.data.attendees,
+            &self.pillar_blockage_map,
+        );
+
+        trace!("greedy({}): computing impact maps", self.problem.id);
+        // only the instruments still to place need one
+        let remaining_instruments: BTreeSet<Instrument> = self
+            .remaining_musicians
+            .iter()
+            .map(|i| self.problem.data.musicians[*i])
+            .collect();
+        self.impact_maps = remaining_instruments
+            .into_iter()
+            .collect::<Vec<_>>()
+            .par_iter()
+            .map(|i| {
+                let impact_map = ImpactMap::new(
+                    i,
+                    &self.problem.data.attendees,
+                    &self.grid,
+                    &self.pillar_blockage_map,
+                );
+                (*i, impact_map)
+            })
+            .collect();
+
+        self.add_placed();
+        trace!("greedy({}): initialized", self.problem.id);
+    }
+
+    /// Hides the positions behind the musicians already placed, and brings the closeness
+    /// of the ones of the remaining instruments
+    fn add_placed(&mut self) {
+        let data = &self.problem.data;
+        let placed: Vec<usize> = (0..self.placements.len())
+            .filter(|i| !self.placements[*i].x.is_nan())
+            .collect();
+        if placed.is_empty() {
+            return;
+        }
+        // the pillars hide nothing more, the visibility started from them
+        let placed_locations: Vec<Point2D> = placed.iter().map(|i| self.placements[*i]).collect();
+        let collider = Collider::new(data, &placed_locations);
+        let newly_hidden =
+            self.visibility
+                .add_obstacles(&self.grid.positions, &collider, |idx_pos| {
+                    self.grid.positions[idx_pos].taken
+                });
+        let remaining_instruments: HashSet<Instrument> = self
+            .remaining_musicians
+            .iter()
+            .map(|i| data.musicians[*i])
+            .collect();
+        let (grid, placements) = (&self.grid, &self.placements);
+        self.impact_maps.par_iter_mut().for_each(|(i, im)| {
+            if !remaining_instruments.contains(i) {
+                return;
+            }
+            im.update(i, &data.attendees, grid, &HashSet::new(), &newly_hidden);
+            if data.pillars.is_empty() {
+                return;
+            }
+            for &musician_i in &placed {
+                if data.musicians[musician_i] == *i {
+                    let peer = &placements[musician_i];
+                    let peer_score = data
+                        .attendees
+                        .iter()
+                        .filter(|a| {
+                            !data
+                                .pillars
+                                .iter()
+                                .any(|p| is_sound_blocked(peer, &p.center, p.radius, *a))
+                        })
+                        .map(|a| calculate_impact(a, i, peer))
+                        .sum::<i64>();
+                    im.add_peer_at(grid, peer, peer_score as f64);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::{Attendee, PillarDto, ProblemDto};
+
+    /// Two musicians of the first instrument, heard by attendees above the two top corners
+    /// of the stage
+    fn problem(pillars: Vec<PillarDto>) -> Problem {
+        Problem {
+            data: ProblemDto {
+                room_width: 300.0,
+                room_height: 300.0,
+                stage_width: 100.0,
+                stage_height: 100.0,
+                stage_bottom_left: (100.0, 100.0),
+                musicians: vec![Instrument(0), Instrument(0), Instrument(1)],
+                attendees: vec![
+                    Attendee {
+                        x: 60.0,
+                        y: 240.0,
+                        tastes: vec![1000.0, 0.0],
+                    },
+                    Attendee {
+                        x: 240.0,
+                        y: 240.0,
+                        tastes: vec![1000.0, 0.0],
+                    },
+                ],
+                pillars,
             },
-            self.remaining_musicians.is_empty(),
-        )
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_closeness_brings_peers_together() {
+        // one corner each without the closeness
+        let apart = Greedy::default().solve(&problem(vec![]));
+        let apart = apart.data.placements;
+        assert!(apart[0].distance(&apart[1]) > 50.0);
+
+        // with a pillar out of the way the closeness counts: the second one is worth more next
+        // to the first than in the other corner
+        let together = Greedy::default().solve(&problem(vec![PillarDto {
+            center: (10.0, 10.0),
+            radius: 1.0,
+        }]));
+        let together = together.data.placements;
+        assert!(together[0].distance(&together[1]) < 11.0);
     }
 }
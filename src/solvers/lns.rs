@@ -0,0 +1,417 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Duration,
+};
+
+use log::debug;
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    common::{Grid, Position},
+    dto::{Point2D, SolutionDto},
+    geometry::distance2,
+    scoring::{delta_scorer::DeltaScorer, impact_map::PillarBlockageMap},
+};
+
+use super::{
+    greedy::Greedy, parameters::name_suffix, Deadline, Parameter, ParameterKind, ParameterSpec,
+    Problem, Score, Solver, SolverRng,
+};
+
+/// How the musicians to place again are chosen
+#[derive(Clone, Copy, Debug)]
+enum Destroy {
+    /// The ones around a random musician
+    Region,
+    /// Some of the musicians of a random instrument
+    Instrument,
+    /// Some of the ones bringing the least
+    Worst,
+}
+
+const OPERATORS: [Destroy; 3] = [Destroy::Region, Destroy::Instrument, Destroy::Worst];
+/// Share of the weight of an operator coming from its last run
+const REACTION: f64 = 0.1;
+/// So that an operator out of luck still gets picked from time to time
+const MIN_WEIGHT: f64 = 0.05;
+/// Between the positions the freed musicians can go to
+const REPAIR_STEP: f32 = 2.0;
+
+/// Large neighbourhood search: removes a few musicians and places them again with the greedy,
+/// on the positions around where they were, keeping the result when the score improves.
+///
+/// The operator choosing who to remove is drawn by weights following how often each one
+/// improved the score lately.
+///
+/// Every repair starts a greedy from scratch, as the musicians staying change every time: the
+/// visibility of its positions from every attendee behind all of them, and an impact map per
+/// instrument to place. That is most of the time of an iteration, growing with the attendees,
+/// the musicians and the positions (`size` × `radius`²): about 0.3s on one core for a thousand
+/// attendees and a hundred musicians.
+#[derive(Clone)]
+pub struct Lns {
+    // Parameters
+    parameters_suffix: String,
+    size: usize,
+    radius: f32,
+    iterations: usize,
+    seed: Option<u64>,
+    // Data
+    problem: Problem,
+    placements: Vec<Point2D>,
+    volumes: Option<Vec<f32>>,
+    scorer: DeltaScorer,
+    weights: [f64; OPERATORS.len()],
+    successes: [usize; OPERATORS.len()],
+    iteration: usize,
+    start_score: Score,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+    rng: SolverRng,
+}
+
+impl Default for Lns {
+    fn default() -> Self {
+        Self {
+            parameters_suffix: String::new(),
+            size: 8,
+            radius: 20.0,
+            iterations: 1000,
+            seed: None,
+            problem: Problem::default(),
+            placements: vec![],
+            volumes: None,
+            scorer: DeltaScorer::default(),
+            weights: [1.0; OPERATORS.len()],
+            successes: [0; OPERATORS.len()],
+            iteration: 0,
+            start_score: Score::default(),
+            time_budget: None,
+            deadline: Deadline::default(),
+            rng: SolverRng::default(),
+        }
+    }
+}
+
+impl Solver for Lns {
+    fn name(&self) -> String {
+        format!("lns{}", self.parameters_suffix)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[
+            ParameterSpec {
+                name: "size",
+                kind: ParameterKind::Int,
+                default: Some("8"),
+                min: Some(1.0),
+                description: "musicians removed and placed again at every iteration",
+            },
+            ParameterSpec {
+                name: "radius",
+                kind: ParameterKind::Float,
+                default: Some("20"),
+                min: Some(0.0),
+                description: "how far from where they were the removed musicians can go",
+            },
+            ParameterSpec {
+                name: "iterations",
+                kind: ParameterKind::Int,
+                default: Some("1000"),
+                min: Some(0.0),
+                description: "iterations when there is no time budget",
+            },
+        ]
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("size", Parameter::Int(v)) => self.size = v as usize,
+                ("radius", Parameter::Float(v)) => self.radius = v as f32,
+                ("iterations", Parameter::Int(v)) => self.iterations = v as usize,
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
+    }
+
+    fn get_problem(&self) -> &Problem {
+        &self.problem
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
+        self.rng = SolverRng::new(self.seed);
+        let solution = if solution.placements.is_empty() {
+            debug!("lns({}): starting from the greedy", problem.id);
+            Greedy::default().solve(problem).data
+        } else {
+            solution
+        };
+        self.placements = solution.placements;
+        self.volumes = solution.volumes;
+        self.scorer = DeltaScorer::new(&problem.data, &self.placements, self.volumes.as_ref());
+        self.start_score = self.scorer.score();
+        self.weights = [1.0; OPERATORS.len()];
+        self.successes = [0; OPERATORS.len()];
+        self.iteration = 0;
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        let operator_i = self.choose_operator();
+        let removed = self.destroy(OPERATORS[operator_i]);
+        let delta = match self.repair(&removed) {
+            Some(changes) => {
+                let delta = self.scorer.delta(&changes);
+                if delta > 0 {
+                    self.scorer.apply(&changes);
+                    for (musician_i, position) in changes {
+                        self.placements[musician_i] = position;
+                    }
+                }
+                delta
+            }
+            None => 0,
+        };
+        let reward = if delta > 0 { 1.0 } else { 0.0 };
+        let weight = &mut self.weights[operator_i];
+        *weight = ((1.0 - REACTION) * *weight + REACTION * reward).max(MIN_WEIGHT);
+        self.iteration += 1;
+        if delta > 0 {
+            self.successes[operator_i] += 1;
+            debug!(
+                "lns({}): iteration {} {:?} {:+} => {}",
+                self.problem.id,
+                self.iteration,
+                OPERATORS[operator_i],
+                delta,
+                self.scorer.score().0
+            );
+        }
+
+        let done_iterations = self.time_budget.is_none() && self.iteration >= self.iterations;
+        let done = done_iterations || self.deadline.passed();
+        if done {
+            debug!(
+                "lns({}): {} => {} in {} iterations, successes {:?}, weights {:.2?}",
+                self.problem.id,
+                self.start_score.0,
+                self.scorer.score().0,
+                self.iteration,
+                self.successes,
+                self.weights
+            );
+        }
+        (
+            SolutionDto {
+                placements: self.placements.clone(),
+                volumes: self.volumes.clone(),
+            },
+            done,
+        )
+    }
+}
+
+impl Lns {
+    /// Roulette on the weights
+    fn choose_operator(&mut self) -> usize {
+        let total: f64 = self.weights.iter().sum();
+        let mut pick = self.rng.gen::<f64>() * total;
+        for (i, weight) in self.weights.iter().enumerate() {
+            if pick < *weight {
+                return i;
+            }
+            pick -= weight;
+        }
+        self.weights.len() - 1
+    }
+
+    fn destroy(&mut self, operator: Destroy) -> Vec<usize> {
+        let musician_count = self.placements.len();
+        let size = self.size.min(musician_count);
+        match operator {
+            Destroy::Region => {
+                let center = self.placements[self.rng.gen_range(0..musician_count)];
+                let mut musicians: Vec<usize> = (0..musician_count).collect();
+                musicians.sort_by(|a, b| {
+                    distance2(&self.placements[*a], &center)
+                        .total_cmp(&distance2(&self.placements[*b], &center))
+                });
+                musicians.truncate(size);
+                musicians
+            }
+            Destroy::Instrument => {
+                let instruments = &self.problem.data.musicians;
+                let instrument = instruments[self.rng.gen_range(0..musician_count)];
+                let mut musicians: Vec<usize> = (0..musician_count)
+                    .filter(|i| instruments[*i] == instrument)
+                    .collect();
+                musicians.shuffle(&mut self.rng);
+                musicians.truncate(size);
+                musicians
+            }
+            Destroy::Worst => {
+                let scores = self.scorer.musician_scores();
+                let mut musicians: Vec<usize> = (0..musician_count).collect();
+                musicians.sort_by_key(|i| scores[*i]);
+                musicians.truncate((size * 2).min(musician_count));
+                musicians.shuffle(&mut self.rng);
+                musicians.truncate(size);
+                musicians
+            }
+        }
+    }
+
+    /// Places the removed musicians again with the greedy, on the positions within `radius`
+    /// of where any of them was. `None` when the greedy runs out of positions.
+    fn repair(&self, removed: &[usize]) -> Option<Vec<(usize, Point2D)>> {
+        let data = &self.problem.data;
+        let (left, bottom) = data.stage_bottom_left;
+        let (min_x, min_y) = (left + 10.0, bottom + 10.0);
+        let max_x = left + data.stage_width - 10.0;
+        let max_y = bottom + data.stage_height - 10.0;
+
+        // on one lattice for the whole stage, so that the discs share their positions
+        let mut cells = BTreeSet::new();
+        let reach = (self.radius / REPAIR_STEP).ceil() as i64;
+        for musician_i in removed {
+            let p = self.placements[*musician_i];
+            let cx = ((p.x - min_x) / REPAIR_STEP).round() as i64;
+            let cy = ((p.y - min_y) / REPAIR_STEP).round() as i64;
+            for ix in cx - reach..=cx + reach {
+                for iy in cy - reach..=cy + reach {
+                    let cell = Point2D {
+                        x: min_x + ix as f32 * REPAIR_STEP,
+                        y: min_y + iy as f32 * REPAIR_STEP,
+                    };
+                    let on_stage =
+                        min_x <= cell.x && cell.x <= max_x && min_y <= cell.y && cell.y <= max_y;
+                    if on_stage && distance2(&cell, &p) <= self.radius * self.radius {
+                        cells.insert((ix, iy));
+                    }
+                }
+            }
+        }
+        let mut positions: Vec<Position> = cells
+            .into_iter()
+            .map(|(ix, iy)| Position {
+                p: Point2D {
+                    x: min_x + ix as f32 * REPAIR_STEP,
+                    y: min_y + iy as f32 * REPAIR_STEP,
+                },
+                taken: false,
+            })
+            .collect();
+        // where they were too, though the first ones placed may take the room of the others:
+        // the repair fails then
+        positions.extend(removed.iter().map(|i| Position {
+            p: self.placements[*i],
+            taken: false,
+        }));
+        let grid = Grid {
+            width: positions.len(),
+            height: 1,
+            positions,
+        };
+
+        let mut placements = self.placements.clone();
+        for musician_i in removed {
+            placements[*musician_i] = Point2D {
+                x: f32::NAN,
+                y: f32::NAN,
+            };
+        }
+        let pillar_blockage_map = PillarBlockageMap::new(&grid, &data.pillars, &data.attendees);
+        let mut greedy = Greedy::default();
+        greedy.start(&self.problem, grid, pillar_blockage_map, placements);
+        for _ in removed {
+            if !greedy.place_next() {
+                return None;
+            }
+        }
+        Some(
+            removed
+                .iter()
+                .map(|i| (*i, greedy.placements()[*i]))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::calculate_invalid_positions,
+        dto::{Attendee, Instrument, ProblemDto},
+    };
+
+    #[test]
+    fn test_improves_a_poor_start() {
+        let problem = Problem {
+            data: ProblemDto {
+                stage_width: 100.0,
+                stage_height: 100.0,
+                musicians: vec![Instrument(0), Instrument(1), Instrument(0), Instrument(1)],
+                attendees: vec![
+                    Attendee {
+                        x: 50.0,
+                        y: 200.0,
+                        tastes: vec![1000.0, -100.0],
+                    },
+                    Attendee {
+                        x: 200.0,
+                        y: 50.0,
+                        tastes: vec![-100.0, 1000.0],
+                    },
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // every musician in the corner furthest from its fans
+        let solution = SolutionDto {
+            placements: vec![
+                Point2D { x: 20.0, y: 20.0 },
+                Point2D { x: 20.0, y: 40.0 },
+                Point2D { x: 40.0, y: 20.0 },
+                Point2D { x: 40.0, y: 40.0 },
+            ],
+            volumes: None,
+        };
+
+        let mut solver = Lns::default();
+        solver.set_parameters(HashMap::from([
+            ("size".to_owned(), Parameter::Int(2)),
+            ("iterations".to_owned(), Parameter::Int(50)),
+        ]));
+        solver.set_seed(3);
+        solver.initialize(&problem, solution);
+        let start = solver.start_score;
+        let solution = solver.finish(&problem, None);
+
+        assert!(solution.score.0 > start.0);
+        assert!(solver.successes.iter().sum::<usize>() > 0);
+        assert!(calculate_invalid_positions(&solution.data.placements, &problem.data).is_empty());
+
+        // and from nothing, after the greedy
+        let mut solver = Lns::default();
+        solver.set_parameters(HashMap::from([(
+            "iterations".to_owned(),
+            Parameter::Int(5),
+        )]));
+        let solution = solver.solve(&problem);
+        assert!(solution.score.0 >= solver.start_score.0);
+        assert!(calculate_invalid_positions(&solution.data.placements, &problem.data).is_empty());
+    }
+}
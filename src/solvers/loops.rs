@@ -0,0 +1,224 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::debug;
+
+use crate::{
+    common::Grid,
+    dto::{Instrument, SolutionDto},
+    scoring::impact_map::ImpactMap,
+};
+
+use super::{
+    combinator_name, exact_score, parameters::name_suffix, sub_seed, Deadline, Parameter,
+    ParameterKind, ParameterSpec, Problem, Score, Solver,
+};
+
+pub const LOOP_PARAMETERS: &[ParameterSpec] = &[ParameterSpec {
+    name: "max",
+    kind: ParameterKind::Int,
+    default: None,
+    min: Some(1.0),
+    description: "stop after this many rounds, even if it still improves",
+}];
+
+/// Runs its pipeline again from the best solution, as long as it improves it
+#[derive(Clone)]
+pub struct Loop {
+    // Parameters
+    max_rounds: Option<usize>,
+    parameters_suffix: String,
+    body: Box<dyn Solver>,
+    // Data
+    current: Box<dyn Solver>,
+    problem: Problem,
+    round: usize,
+    best: Option<(Score, SolutionDto)>,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+    seed: Option<u64>,
+}
+
+impl Solver for Loop {
+    fn name(&self) -> String {
+        combinator_name("loop", &self.parameters_suffix, &*self.body)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        LOOP_PARAMETERS
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("max", Parameter::Int(v)) => self.max_rounds = Some(v as usize),
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
+    }
+
+    fn get_impact_map(&self, instrument: &Instrument) -> Option<&ImpactMap> {
+        self.current.get_impact_map(instrument)
+    }
+
+    fn get_grid(&self) -> Option<&Grid> {
+        self.current.get_grid()
+    }
+
+    fn get_problem(&self) -> &Problem {
+        self.current.get_problem()
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    fn stage_count(&self) -> usize {
+        self.body.stage_count()
+    }
+
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
+        self.round = 0;
+        self.best = if solution.placements.is_empty() {
+            None
+        } else {
+            Some((exact_score(problem, &solution), solution.clone()))
+        };
+        self.start_round(solution);
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        let (solution, done) = self.current.solve_step();
+        if !done {
+            return (solution, false);
+        }
+
+        let score = exact_score(self.current.get_problem(), &solution);
+        self.round += 1;
+        let improved = match &self.best {
+            Some((best, _)) => score.0 > best.0,
+            None => true,
+        };
+        debug!(
+            "loop({}): round {} scored {}{}",
+            self.problem.id,
+            self.round,
+            score.0,
+            if improved { ", improved" } else { "" }
+        );
+        if improved {
+            self.best = Some((score, solution.clone()));
+        }
+
+        let finished = !improved
+            || self.max_rounds.is_some_and(|max| self.round >= max)
+            || self.deadline.passed();
+        if finished {
+            let (_, best) = self.best.clone().unwrap();
+            return (best, true);
+        }
+        self.start_round(solution.clone());
+        (solution, false)
+    }
+}
+
+impl Loop {
+    pub fn new(body: Box<dyn Solver>) -> Self {
+        Loop {
+            max_rounds: None,
+            parameters_suffix: String::new(),
+            current: body.clone(),
+            body,
+            problem: Problem::default(),
+            round: 0,
+            best: None,
+            time_budget: None,
+            deadline: Deadline::default(),
+            seed: None,
+        }
+    }
+
+    fn start_round(&mut self, solution: SolutionDto) {
+        self.current = self.body.clone();
+        if let Some(seed) = self.seed {
+            self.current.set_seed(sub_seed(seed, self.round));
+        }
+        self.current.set_time_budget(self.deadline.remaining());
+        self.current.initialize(&self.problem, solution);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::{Attendee, Point2D, ProblemDto};
+
+    /// Moves its only musician 10 closer to x = 50 in one step
+    #[derive(Clone, Default)]
+    struct Approach {
+        problem: Problem,
+        x: f32,
+    }
+
+    impl Solver for Approach {
+        fn name(&self) -> String {
+            "approach".to_owned()
+        }
+
+        fn get_problem(&self) -> &Problem {
+            &self.problem
+        }
+
+        fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+            self.problem = problem.clone();
+            self.x = solution.placements.first().map_or(10.0, |p| p.x);
+        }
+
+        fn solve_step(&mut self) -> (SolutionDto, bool) {
+            let x = (self.x + 10.0).min(50.0);
+            let solution = SolutionDto {
+                placements: vec![Point2D { x, y: 10.0 }],
+                ..Default::default()
+            };
+            (solution, true)
+        }
+    }
+
+    fn problem() -> Problem {
+        Problem {
+            data: ProblemDto {
+                stage_width: 100.0,
+                stage_height: 20.0,
+                musicians: vec![Instrument(0)],
+                attendees: vec![Attendee {
+                    x: 50.0,
+                    y: 30.0,
+                    tastes: vec![1000.0],
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_loops_until_stable() {
+        let mut solver = Loop::new(Box::<Approach>::default());
+        let solution = solver.solve(&problem());
+        assert_eq!(solution.data.placements[0].x, 50.0);
+        // 20, 30, 40, 50, then 50 again
+        assert_eq!(solver.round, 5);
+
+        let mut solver = Loop::new(Box::<Approach>::default());
+        solver.set_parameters(HashMap::from([("max".to_owned(), Parameter::Int(2))]));
+        assert_eq!(solver.name(), "loop_max_2(approach)");
+        let solution = solver.solve(&problem());
+        assert_eq!(solution.data.placements[0].x, 30.0);
+    }
+}
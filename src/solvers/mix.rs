@@ -1,9 +1,14 @@
 use log::debug;
 
-use crate::dto::SolutionDto;
+use crate::{dto::SolutionDto, scoring::breakdown::ScoreBreakdown};
 
 use super::{Problem, Solver};
 
+/// Sets every volume to 0 or 10.
+///
+/// Every attendee/musician pair only depends on the volume of its musician, so the score is
+/// (up to the rounding of each pair) linear in each volume: a musician is at full volume when
+/// its total contribution is positive, and silent otherwise. One breakdown is enough to know.
 #[derive(Default, Clone)]
 pub struct Mix {
     problem: Problem,
@@ -32,56 +37,37 @@ impl Solver for Mix {
     fn solve_step(&mut self) -> (SolutionDto, bool) {
         const VOLUME_MIN: f32 = 0.0;
         const VOLUME_MAX: f32 = 10.0;
-        const VOLUME_DEFAULT: f32 = 1.0;
-        const VOLUME_STEP: f32 = 0.5;
 
-        let mut volumes = vec![VOLUME_DEFAULT; self.solution.placements.len()];
-        let orig_score = self
-            .problem
-            .score(&self.solution.placements, Some(&volumes));
-        for idx in 0..self.solution.placements.len() {
-            let mut volume = VOLUME_MIN;
-            while volume < VOLUME_MAX {
-                let old_volume = volumes[idx];
-                volumes[idx] = volume;
-                let new_score = self
-                    .problem
-                    .score(&self.solution.placements, Some(&volumes));
-                if new_score.0 < orig_score.0 {
-                    volumes[idx] = old_volume;
-                }
-                volume += VOLUME_STEP;
-            }
-            volume = VOLUME_MAX;
-            // Copy-paste but whatever
-            let old_volume = volumes[idx];
-            volumes[idx] = volume;
-            let new_score = self
-                .problem
-                .score(&self.solution.placements, Some(&volumes));
-            if new_score.0 < orig_score.0 {
-                volumes[idx] = old_volume;
-            }
-            debug!(
-                "mix({}): {} musicians left",
-                self.problem.id,
-                self.solution.placements.len() - idx - 1
-            );
-        }
+        let loud = vec![VOLUME_MAX; self.solution.placements.len()];
+        let breakdown = ScoreBreakdown::new(
+            &self.problem.data,
+            &self.solution.placements,
+            Some(&loud),
+            false,
+        );
+
+        let volumes: Vec<f32> = breakdown
+            .musicians
+            .iter()
+            .map(|m| if m.score > 0 { VOLUME_MAX } else { VOLUME_MIN })
+            .collect();
+        let silenced = volumes.iter().filter(|&&v| v == VOLUME_MIN).count();
+        let score: i64 = breakdown
+            .musicians
+            .iter()
+            .map(|m| m.score)
+            .filter(|&s| s > 0)
+            .sum();
 
         debug!(
-            "mix({}): {} full volume, {} silent, {} others",
+            "mix({}): {} full volume, {} silenced, score {} (all at full volume: {})",
             self.problem.id,
-            volumes.iter().filter(|&&v| v == VOLUME_MAX).count(),
-            volumes.iter().filter(|&&v| v == VOLUME_MIN).count(),
-            volumes
-                .iter()
-                .filter(|&&v| v != VOLUME_MIN && v != VOLUME_MAX)
-                .count(),
+            volumes.len() - silenced,
+            silenced,
+            score,
+            breakdown.total
         );
 
-        // dbg!(&volumes);
-
         (
             SolutionDto {
                 placements: self.solution.placements.clone(),
@@ -91,3 +77,46 @@ impl Solver for Mix {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dto::Point2D,
+        scoring::{new_scorer::NewScorer, Scorer},
+        solvers::test_problem,
+    };
+
+    #[test]
+    fn test_volumes_follow_the_sign_of_the_pairs() {
+        let problem = test_problem();
+        // the first musician is heard, the second is disliked and the third is behind the first
+        let placements = vec![
+            Point2D { x: 50.0, y: 90.0 },
+            Point2D { x: 20.0, y: 20.0 },
+            Point2D { x: 50.0, y: 70.0 },
+        ];
+        let mut solver = Mix::default();
+        solver.initialize(
+            &problem,
+            SolutionDto {
+                placements: placements.clone(),
+                volumes: None,
+            },
+        );
+        let (solution, done) = solver.solve_step();
+        assert!(done);
+
+        let loud = vec![10.0; placements.len()];
+        let breakdown = ScoreBreakdown::new(&problem.data, &placements, Some(&loud), false);
+        let volumes = solution.volumes.as_ref().unwrap();
+        for (musician, volume) in breakdown.musicians.iter().zip(volumes) {
+            assert_eq!(*volume, if musician.score > 0 { 10.0 } else { 0.0 });
+        }
+        assert_eq!(volumes, &vec![10.0, 0.0, 0.0]);
+
+        let positive: i64 = breakdown.musicians.iter().map(|m| m.score.max(0)).sum();
+        let score = NewScorer.score(&problem.data, &solution.placements, Some(volumes));
+        assert_eq!(score.0, positive);
+    }
+}
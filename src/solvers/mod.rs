@@ -1,29 +1,45 @@
 mod annealer;
+mod assign;
 mod chain;
+pub mod checkpoint;
+mod edges;
 mod expand;
 mod genetic;
+mod gradient;
 mod greedy;
+mod lns;
 mod load_best;
+mod loops;
 mod mix;
+mod parameters;
+mod pipeline;
+mod portfolio;
+mod restart;
 mod set;
 mod shake;
+mod shield;
 mod swarm;
+mod tempering;
 mod vol10;
 
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use derivative::Derivative;
 use dyn_clone::DynClone;
 use log::debug;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
 
 use crate::common::{prune_attendees_and_pillars, Grid};
 use crate::dto::{Attendee, Instrument, PillarDto, Point2D};
 use crate::scoring::impact_map::ImpactMap;
 use crate::scoring::new_scorer::NewScorer;
+use crate::scoring::visibility::VisibilityMatrix;
 use crate::scoring::Scorer;
 use crate::{
     dto::{ProblemDto, SolutionDto, SolutionMetaDto},
@@ -31,21 +47,32 @@ use crate::{
 };
 
 use self::annealer::Annealer;
-use self::chain::Chain;
+use self::assign::Assign;
+use self::checkpoint::Checkpointer;
+use self::edges::Edges;
 use self::expand::Expand;
 use self::genetic::Genetic;
+use self::gradient::Gradient;
 use self::greedy::Greedy;
+use self::lns::Lns;
 use self::load_best::LoadBest;
+use self::loops::{Loop, LOOP_PARAMETERS};
 use self::mix::Mix;
+pub use self::parameters::{Parameter, ParameterKind, ParameterSpec};
+use self::restart::{Restart, RESTART_PARAMETERS};
 use self::set::Set;
 use self::shake::Shake;
+use self::shield::Shield;
 use self::swarm::Swarm;
+use self::tempering::Tempering;
 use self::vol10::Vol10;
 
 #[derive(Default, Clone, Derivative)]
 #[derivative(Debug)]
 pub struct Problem {
     pub id: String,
+    /// Where it was loaded from, empty otherwise
+    pub path: PathBuf,
     pub data: ProblemDto,
     pub removed_attendees: Vec<Attendee>,
     pub removed_pillars: Vec<PillarDto>,
@@ -60,7 +87,8 @@ impl Problem {
         let reader = BufReader::new(file);
 
         let mut problem = Problem {
-            id: id.clone(),
+            id,
+            path: problem_path.to_owned(),
             data: serde_json::from_reader(reader)?,
             removed_attendees: vec![],
             removed_pillars: vec![],
@@ -73,9 +101,7 @@ impl Problem {
                 pruned_pillars: Vec<PillarDto>,
             }
 
-            let mut pruned_data_path = problem_path.parent().unwrap().to_owned();
-            pruned_data_path.set_file_name("problems_extra");
-            let pruned_data_path = pruned_data_path.join(id + "_pruned_data.json");
+            let pruned_data_path = problem.extra_data_path("pruned_data.json").unwrap();
 
             let (pruned_attendees, pruned_pillars) = if pruned_data_path.exists() {
                 debug!("prune: found cached pruned data, loading");
@@ -156,18 +182,112 @@ impl Problem {
         Ok(problem)
     }
 
+    /// Where to cache data computed from the problem, `problems_extra/{id}_{name}`,
+    /// if it was loaded from a file
+    pub fn extra_data_path(&self, name: &str) -> Option<PathBuf> {
+        let mut path = self.path.parent()?.to_owned();
+        path.set_file_name("problems_extra");
+        Some(path.join(format!("{}_{name}", self.id)))
+    }
+
     pub fn score(&self, placements: &[Point2D], volumes: Option<&Vec<f32>>) -> Score {
         self.scorer.score(&self.data, placements, volumes)
     }
+
+    pub fn score_with_visibility(
+        &self,
+        placements: &[Point2D],
+        volumes: Option<&Vec<f32>>,
+        visibility: &VisibilityMatrix,
+    ) -> Score {
+        self.scorer
+            .score_with_visibility(&self.data, placements, volumes, visibility)
+    }
 }
 
+/// When a solver with a time budget has to stop
 #[derive(Default, Clone, Copy, Debug)]
+pub struct Deadline {
+    start: Option<Instant>,
+    budget: Option<Duration>,
+}
+
+impl Deadline {
+    pub fn start(budget: Option<Duration>) -> Self {
+        Deadline {
+            start: Some(Instant::now()),
+            budget,
+        }
+    }
+
+    /// Share of the budget already used, if there is a budget
+    pub fn progress(&self) -> Option<f32> {
+        let (start, budget) = (self.start?, self.budget?);
+        Some(start.elapsed().as_secs_f32() / budget.as_secs_f32().max(f32::EPSILON))
+    }
+
+    pub fn passed(&self) -> bool {
+        self.progress().is_some_and(|p| p >= 1.0)
+    }
+
+    pub fn remaining(&self) -> Option<Duration> {
+        let (start, budget) = (self.start?, self.budget?);
+        Some(budget.saturating_sub(start.elapsed()))
+    }
+}
+
+/// Random numbers of a solver, which can be saved with its checkpoint
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SolverRng(ChaCha8Rng);
+
+impl SolverRng {
+    /// Reproducible with a seed, from the OS entropy otherwise
+    pub fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => SolverRng(ChaCha8Rng::seed_from_u64(seed)),
+            None => SolverRng(ChaCha8Rng::from_entropy()),
+        }
+    }
+}
+
+impl Default for SolverRng {
+    fn default() -> Self {
+        SolverRng::new(None)
+    }
+}
+
+/// Seed of the `i`-th solver run by a combinator, so that they don't all draw the same numbers
+pub fn sub_seed(seed: u64, i: usize) -> u64 {
+    seed.wrapping_add((i as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+impl RngCore for SolverRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Score(pub i64);
 
 #[derive(Default)]
 pub struct Solution {
     pub score: Score,
     pub data: SolutionDto,
+    /// Seed of the run, to generate it again
+    pub seed: Option<u64>,
 }
 
 impl Solution {
@@ -190,6 +310,7 @@ impl Solution {
         let solution = Solution {
             score: Score(metadata.score),
             data,
+            seed: metadata.seed,
         };
         Ok((solution, metadata))
     }
@@ -200,6 +321,7 @@ impl Solution {
         let solution_meta = SolutionMetaDto {
             solver_name,
             score: self.score.0,
+            seed: self.seed,
         };
 
         // write metadata
@@ -222,14 +344,14 @@ impl Solution {
     }
 }
 
-pub enum Parameter {
-    Int(i64),
-    String(String),
-}
-
 pub trait Solver: DynClone + Sync + Send {
     fn name(&self) -> String;
 
+    /// What `set_parameters` accepts
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[]
+    }
+    /// Only called with parameters from `parameters()`, with values of the right kind
     fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
         assert!(
             parameters.is_empty(),
@@ -237,25 +359,57 @@ pub trait Solver: DynClone + Sync + Send {
             self.name()
         );
     }
+    /// Whether the parameters set go together (`min <= max`...), checked when the pipeline
+    /// is compiled, after `set_parameters`
+    fn check_parameters(&self) -> Result<(), String> {
+        Ok(())
+    }
+    /// Wall-clock time the solver may take from `initialize` on. Solvers which build
+    /// a solution in one go ignore it.
+    fn set_time_budget(&mut self, _budget: Option<Duration>) {}
+    /// Makes the runs reproducible: every random number then comes from the seed.
+    /// Solvers which draw none ignore it.
+    fn set_seed(&mut self, _seed: u64) {}
+    /// How many solvers share the time budget
+    fn stage_count(&self) -> usize {
+        1
+    }
     fn get_problem(&self) -> &Problem;
     fn initialize(&mut self, problem: &Problem, solution: SolutionDto);
     fn solve_step(&mut self) -> (SolutionDto, bool);
 
+    /// State to resume from with `restore`, for the solvers which run for long
+    fn checkpoint(&self) -> Option<serde_json::Value> {
+        None
+    }
+    /// Instead of `initialize`, goes back to the state of a `checkpoint`
+    fn restore(&mut self, _problem: &Problem, _state: serde_json::Value) -> Result<(), String> {
+        Err(format!("{} can't resume from a checkpoint", self.name()))
+    }
+
     fn solve(&mut self, problem: &Problem) -> Solution {
         self.initialize(problem, SolutionDto::default());
+        self.finish(problem, None)
+    }
+
+    /// Steps until done, after `initialize` or `restore`
+    fn finish(
+        &mut self,
+        problem: &Problem,
+        mut checkpointer: Option<&mut Checkpointer>,
+    ) -> Solution {
         loop {
             let (solution, done) = self.solve_step();
             if !done {
+                if let Some(checkpointer) = checkpointer.as_mut() {
+                    checkpointer.tick(problem, &*self);
+                }
                 continue;
             }
             return Solution {
-                // Always score with a good scorer here
-                score: NewScorer.score(
-                    &problem.data,
-                    &solution.placements,
-                    solution.volumes.as_ref(),
-                ),
+                score: exact_score(problem, &solution),
                 data: solution,
+                ..Default::default()
             };
         }
     }
@@ -271,65 +425,121 @@ pub trait Solver: DynClone + Sync + Send {
 
 dyn_clone::clone_trait_object!(Solver);
 
-pub const SOLVERS: &[&str] = &["expand", "greedy", "genetic"];
+/// Always score with a good scorer, whichever one the problem uses
+fn exact_score(problem: &Problem, solution: &SolutionDto) -> Score {
+    NewScorer.score(
+        &problem.data,
+        &solution.placements,
+        solution.volumes.as_ref(),
+    )
+}
 
-pub fn create_solver(solver_name: &str) -> Box<dyn Solver> {
-    if solver_name.contains('+') {
-        let mut solvers = solver_name.split('+').map(create_individual_solver);
-        let chain = Box::new(Chain::new(solvers.next().unwrap(), solvers.next().unwrap()));
-        solvers.fold(chain, |chain, next| Box::new(Chain::new(chain, next)))
+/// `name{suffix}(body)` for a combinator, without doubling the parentheses a body like a
+/// portfolio already has
+fn combinator_name(name: &str, suffix: &str, body: &dyn Solver) -> String {
+    let body = body.name();
+    let mut depth = 0;
+    let enclosed = body.starts_with('(')
+        && body.char_indices().all(|(i, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            // the first parenthesis only closes at the end
+            depth > 0 || i == body.len() - 1
+        });
+    if enclosed {
+        format!("{name}{suffix}{body}")
     } else {
-        create_individual_solver(solver_name)
+        format!("{name}{suffix}({body})")
     }
 }
 
-fn create_individual_solver(solver_name: &str) -> Box<dyn Solver> {
-    let (solver_name, parameters) = if solver_name.contains('{') {
-        assert!(
-            solver_name.ends_with('}'),
-            "Invalid solver name {}",
-            solver_name
-        );
-        let (solver_name, rest) = solver_name.split_at(solver_name.find('{').unwrap());
-        let rest = &rest[1..rest.len() - 1];
-        let parameters = rest
-            .split(',')
-            .map(|s| {
-                let (name, value) = s.split_at(
-                    s.find('=')
-                        .expect("Invalid parameter format, expected `name=value`"),
-                );
-                (
-                    name.to_owned(),
-                    if value.chars().nth(1).unwrap().is_ascii_digit() {
-                        Parameter::Int(
-                            value[1..]
-                                .parse::<i64>()
-                                .expect("Failed to parse solver parameter as i64"),
-                        )
-                    } else {
-                        Parameter::String(value[1..].to_owned())
-                    },
-                )
-            })
-            .collect::<HashMap<_, _>>();
-        (solver_name, parameters)
-    } else {
-        (solver_name, HashMap::new())
-    };
-    let mut solver: Box<dyn Solver> = match solver_name {
-        "annealer" => Box::<Annealer>::default(),
-        "expand" => Box::<Expand>::default(),
-        "genetic" => Box::<Genetic>::default(),
-        "greedy" => Box::<Greedy>::default(),
-        "load_best" => Box::<LoadBest>::default(),
-        "mix" => Box::<Mix>::default(),
-        "set" => Box::<Set>::default(),
-        "shake" => Box::<Shake>::default(),
-        "vol10" => Box::<Vol10>::default(),
-        "swarm" => Box::<Swarm>::default(),
-        n => panic!("Unknown solver `{}`", n),
-    };
-    solver.set_parameters(parameters);
-    solver
+pub const SOLVERS: &[&str] = &["expand", "greedy", "genetic"];
+
+type NewSolver = fn() -> Box<dyn Solver>;
+
+/// Every solver, by the name used to create it
+const REGISTRY: &[(&str, NewSolver)] = &[
+    ("annealer", || Box::<Annealer>::default()),
+    ("assign", || Box::<Assign>::default()),
+    ("edges", || Box::<Edges>::default()),
+    ("expand", || Box::<Expand>::default()),
+    ("genetic", || Box::<Genetic>::default()),
+    ("gradient", || Box::<Gradient>::default()),
+    ("greedy", || Box::<Greedy>::default()),
+    ("lns", || Box::<Lns>::default()),
+    ("load_best", || Box::<LoadBest>::default()),
+    ("mix", || Box::<Mix>::default()),
+    ("set", || Box::<Set>::default()),
+    ("shake", || Box::<Shake>::default()),
+    ("shield", || Box::<Shield>::default()),
+    ("swarm", || Box::<Swarm>::default()),
+    ("tempering", || Box::<Tempering>::default()),
+    ("vol10", || Box::<Vol10>::default()),
+];
+
+type NewCombinator = fn(Box<dyn Solver>) -> Box<dyn Solver>;
+
+/// Solvers running a pipeline, as in `loop(shake+mix)`
+const COMBINATORS: &[(&str, &[ParameterSpec], NewCombinator)] = &[
+    ("loop", LOOP_PARAMETERS, |body| Box::new(Loop::new(body))),
+    ("restart", RESTART_PARAMETERS, |body| {
+        Box::new(Restart::new(body))
+    }),
+];
+
+/// Every solver with its default parameters, by name
+pub fn all_solvers() -> Vec<(&'static str, Box<dyn Solver>)> {
+    REGISTRY.iter().map(|(name, new)| (*name, new())).collect()
+}
+
+/// Every combinator with the parameters it accepts, by name
+pub fn all_combinators() -> Vec<(&'static str, &'static [ParameterSpec])> {
+    COMBINATORS
+        .iter()
+        .map(|(name, specs, _)| (*name, *specs))
+        .collect()
+}
+
+/// Solver for a pipeline like `greedy+(shake{cap=2}+mix)*3`, see `pipeline`
+pub fn create_solver(pipeline: &str) -> Result<Box<dyn Solver>, String> {
+    pipeline::parse(pipeline)
+        .and_then(|p| p.compile())
+        .map_err(|e| e.render(pipeline))
+}
+
+fn new_solver(name: &str) -> Option<Box<dyn Solver>> {
+    REGISTRY
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, new)| new())
+}
+
+fn new_combinator(name: &str, body: Box<dyn Solver>) -> Option<Box<dyn Solver>> {
+    COMBINATORS
+        .iter()
+        .find(|(n, _, _)| *n == name)
+        .map(|(_, _, new)| new(body))
+}
+
+/// Two instruments on a 100x100 stage, heard by one attendee above it who loves the first one
+/// and hates the second, for the tests of the solvers
+#[cfg(test)]
+fn test_problem() -> Problem {
+    Problem {
+        data: ProblemDto {
+            stage_width: 100.0,
+            stage_height: 100.0,
+            musicians: vec![Instrument(0), Instrument(1), Instrument(0)],
+            attendees: vec![Attendee {
+                x: 50.0,
+                y: 150.0,
+                tastes: vec![1000.0, -500.0],
+            }],
+            ..Default::default()
+        },
+        ..Default::default()
+    }
 }
@@ -0,0 +1,219 @@
+use std::{collections::HashMap, fmt, time::Duration};
+
+#[derive(Clone, Copy, Debug)]
+pub enum ParameterKind {
+    Int,
+    Float,
+    Bool,
+    /// One of the given values
+    Enum(&'static [&'static str]),
+    /// Seconds, or a number followed by `ms`, `s`, `m` or `h`
+    Duration,
+}
+
+/// A parameter a solver accepts, as in `name{key=value,...}`
+#[derive(Clone, Copy, Debug)]
+pub struct ParameterSpec {
+    pub name: &'static str,
+    pub kind: ParameterKind,
+    /// Value when not given, `None` when it is unset (no cap, no seed...)
+    pub default: Option<&'static str>,
+    /// Smallest value of a number, `None` when any goes
+    pub min: Option<f64>,
+    pub description: &'static str,
+}
+
+/// `cap` of the solvers which improve one cycle at a time
+pub const CYCLES_CAP: ParameterSpec = ParameterSpec {
+    name: "cap",
+    kind: ParameterKind::Int,
+    default: None,
+    min: Some(0.0),
+    description: "stop after this many improving cycles",
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Parameter {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Enum(String),
+    Duration(Duration),
+}
+
+impl fmt::Display for ParameterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterKind::Int => write!(f, "int"),
+            ParameterKind::Float => write!(f, "float"),
+            ParameterKind::Bool => write!(f, "bool"),
+            ParameterKind::Enum(values) => write!(f, "{}", values.join("|")),
+            ParameterKind::Duration => write!(f, "duration"),
+        }
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Parameter::Int(v) => write!(f, "{v}"),
+            Parameter::Float(v) => write!(f, "{v}"),
+            Parameter::Bool(v) => write!(f, "{v}"),
+            Parameter::Enum(v) => write!(f, "{v}"),
+            Parameter::Duration(v) => write!(f, "{}s", v.as_secs_f64()),
+        }
+    }
+}
+
+impl ParameterSpec {
+    pub fn parse(&self, value: &str) -> Result<Parameter, String> {
+        let invalid = || format!("invalid {} `{}` for `{}`", self.kind, value, self.name);
+        let at_least_min = |v: f64| match self.min {
+            Some(min) if v < min => Err(format!(
+                "`{}` is below the minimum {} of `{}`",
+                value, min, self.name
+            )),
+            _ => Ok(()),
+        };
+        match self.kind {
+            ParameterKind::Int => {
+                let v: i64 = value.parse().map_err(|_| invalid())?;
+                at_least_min(v as f64).map(|_| Parameter::Int(v))
+            }
+            ParameterKind::Float => {
+                // `nan` and `inf` parse, but no rate or temperature is meant to be one
+                let v: f64 = value
+                    .parse()
+                    .ok()
+                    .filter(|v: &f64| v.is_finite())
+                    .ok_or_else(invalid)?;
+                at_least_min(v).map(|_| Parameter::Float(v))
+            }
+            ParameterKind::Bool => match value {
+                "true" | "yes" | "1" => Ok(Parameter::Bool(true)),
+                "false" | "no" | "0" => Ok(Parameter::Bool(false)),
+                _ => Err(invalid()),
+            },
+            ParameterKind::Enum(values) => values
+                .contains(&value)
+                .then(|| Parameter::Enum(value.to_owned()))
+                .ok_or_else(invalid),
+            ParameterKind::Duration => parse_duration(value).ok_or_else(invalid),
+        }
+    }
+}
+
+fn parse_duration(value: &str) -> Option<Parameter> {
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .map(Parameter::Duration)
+}
+
+/// `_key_value` for every parameter, sorted by key, to tell runs apart in solver names
+pub fn name_suffix(parameters: &HashMap<String, Parameter>) -> String {
+    let mut keys = parameters.keys().collect::<Vec<_>>();
+    keys.sort();
+    keys.into_iter()
+        .map(|k| format!("_{}_{}", k, parameters[k]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPECS: &[ParameterSpec] = &[
+        ParameterSpec {
+            name: "rate",
+            kind: ParameterKind::Float,
+            default: Some("0.5"),
+            min: None,
+            description: "",
+        },
+        ParameterSpec {
+            name: "count",
+            kind: ParameterKind::Int,
+            default: None,
+            min: Some(1.0),
+            description: "",
+        },
+        ParameterSpec {
+            name: "mode",
+            kind: ParameterKind::Enum(&["fast", "slow"]),
+            default: None,
+            min: None,
+            description: "",
+        },
+        ParameterSpec {
+            name: "timeout",
+            kind: ParameterKind::Duration,
+            default: None,
+            min: None,
+            description: "",
+        },
+    ];
+
+    #[test]
+    fn test_parse() {
+        let parse = |name, value| SPECS.iter().find(|s| s.name == name).unwrap().parse(value);
+        let parameters = HashMap::from([
+            ("rate".to_owned(), parse("rate", "0.75").unwrap()),
+            ("mode".to_owned(), parse("mode", "slow").unwrap()),
+            ("timeout".to_owned(), parse("timeout", "2m").unwrap()),
+        ]);
+        assert_eq!(parameters["rate"], Parameter::Float(0.75));
+        assert_eq!(parameters["mode"], Parameter::Enum("slow".to_owned()));
+        assert_eq!(
+            parameters["timeout"],
+            Parameter::Duration(Duration::from_secs(120))
+        );
+        assert_eq!(
+            name_suffix(&parameters),
+            "_mode_slow_rate_0.75_timeout_120s"
+        );
+
+        assert_eq!(parse("count", "1"), Ok(Parameter::Int(1)));
+        assert!(parse("count", "0").is_err());
+        assert!(parse("count", "-1").is_err());
+        assert!(parse("mode", "medium").is_err());
+        assert!(parse("rate", "fast").is_err());
+        assert!(parse("rate", "nan").is_err());
+        assert!(parse("rate", "inf").is_err());
+        assert!(parse("rate", "-inf").is_err());
+        assert!(parse("timeout", "5x").is_err());
+        assert_eq!(
+            parse("timeout", "250ms"),
+            Ok(Parameter::Duration(Duration::from_millis(250)))
+        );
+    }
+
+    #[test]
+    fn test_defaults_parse() {
+        for (name, solver) in crate::solvers::all_solvers() {
+            for spec in solver.parameters() {
+                if let Some(default) = spec.default {
+                    assert!(spec.parse(default).is_ok(), "{name}: {}", spec.name);
+                }
+            }
+        }
+        for (name, specs) in crate::solvers::all_combinators() {
+            for spec in specs {
+                if let Some(default) = spec.default {
+                    assert!(spec.parse(default).is_ok(), "{name}: {}", spec.name);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! Solver pipelines, as given on the command line:
+//!
+//! ```text
+//! pipeline     := alternatives
+//! alternatives := sequence ('|' sequence)*
+//! sequence     := repeat ('+' repeat)*
+//! repeat       := atom ('*' count)?
+//! atom         := name parameters? ('(' pipeline ')')? | '(' pipeline ')'
+//! parameters   := '{' (key '=' value (',' key '=' value)*)? '}'
+//! value        := '"' anything but '"' '"' | anything but whitespace, ',' and '}'
+//! ```
+//!
+//! e.g. `greedy+(shake{cap=2}+mix)*3`, `(expand|annealer)+shake` or `greedy+loop(shake+mix)`: a
+//! name followed by a pipeline is a combinator, running that pipeline. Whitespace is allowed
+//! between tokens.
+
+use std::{collections::HashMap, ops::Range};
+
+use super::{
+    all_combinators, chain::Chain, new_combinator, new_solver, portfolio::Portfolio, Parameter,
+    ParameterSpec, Solver,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pipeline {
+    Solver {
+        name: String,
+        span: Range<usize>,
+        parameters: Vec<ParameterText>,
+        /// What a combinator runs
+        body: Option<Box<Pipeline>>,
+    },
+    /// One after the other, each starting from the solution of the previous one
+    Sequence(Vec<Pipeline>),
+    Repeat(Box<Pipeline>, usize),
+    /// All of them on the same problem, keeping the best
+    Alternatives(Vec<Pipeline>),
+}
+
+/// `key=value` as written, checked against the solver parameters when compiling
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterText {
+    pub key: String,
+    pub key_span: Range<usize>,
+    pub value: String,
+    pub value_span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineError {
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl PipelineError {
+    fn new(span: Range<usize>, message: impl Into<String>) -> Self {
+        PipelineError {
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// The message, with the pipeline and the faulty part underlined
+    pub fn render(&self, input: &str) -> String {
+        let start = input[..self.span.start.min(input.len())].chars().count();
+        let width = input
+            .get(self.span.clone())
+            .map_or(1, |s| s.chars().count().max(1));
+        format!(
+            "{}\n    {}\n    {}{}",
+            self.message,
+            input,
+            " ".repeat(start),
+            "^".repeat(width)
+        )
+    }
+}
+
+pub fn parse(input: &str) -> Result<Pipeline, PipelineError> {
+    let mut parser = Parser { input, pos: 0 };
+    let pipeline = parser.alternatives()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(pipeline),
+        Some(')') => Err(parser.error_here("unmatched `)`")),
+        Some(c) => Err(parser.error_here(format!("expected `+`, `|` or `*`, found `{c}`"))),
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) {
+        if let Some(c) = self.peek() {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    /// Skips whitespace, then consumes `c` if it is next
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> (&str, Range<usize>) {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.bump();
+        }
+        (&self.input[start..self.pos], start..self.pos)
+    }
+
+    fn error_here(&self, message: impl Into<String>) -> PipelineError {
+        let end = self.peek().map_or(self.pos, |c| self.pos + c.len_utf8());
+        PipelineError::new(self.pos..end, message)
+    }
+
+    fn expected(&self, what: &str) -> PipelineError {
+        match self.peek() {
+            Some(c) => self.error_here(format!("expected {what}, found `{c}`")),
+            None => self.error_here(format!("expected {what}, found the end")),
+        }
+    }
+
+    fn alternatives(&mut self) -> Result<Pipeline, PipelineError> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.eat('|') {
+            alternatives.push(self.sequence()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Pipeline::Alternatives(alternatives)
+        })
+    }
+
+    fn sequence(&mut self) -> Result<Pipeline, PipelineError> {
+        let mut stages = vec![self.repeat()?];
+        while self.eat('+') {
+            stages.push(self.repeat()?);
+        }
+        Ok(if stages.len() == 1 {
+            stages.pop().unwrap()
+        } else {
+            Pipeline::Sequence(stages)
+        })
+    }
+
+    fn repeat(&mut self) -> Result<Pipeline, PipelineError> {
+        let atom = self.atom()?;
+        if !self.eat('*') {
+            return Ok(atom);
+        }
+        self.skip_whitespace();
+        let (count, span) = self.take_while(|c| c.is_ascii_digit());
+        if count.is_empty() {
+            return Err(self.expected("a repetition count"));
+        }
+        match count.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(Pipeline::Repeat(Box::new(atom), count)),
+            _ => Err(PipelineError::new(
+                span,
+                "the repetition count must be at least 1",
+            )),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Pipeline, PipelineError> {
+        if self.eat('(') {
+            let open = self.pos - 1;
+            let pipeline = self.alternatives()?;
+            if !self.eat(')') {
+                return Err(match self.peek() {
+                    None => PipelineError::new(open..open + 1, "unclosed `(`"),
+                    Some(_) => self.expected("`)`"),
+                });
+            }
+            return Ok(pipeline);
+        }
+
+        self.skip_whitespace();
+        let (name, span) = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if name.is_empty() {
+            return Err(self.expected("a solver name"));
+        }
+        let name = name.to_owned();
+        let parameters = if self.eat('{') {
+            self.parameters()?
+        } else {
+            vec![]
+        };
+        let body = if self.eat('(') {
+            let open = self.pos - 1;
+            let body = self.alternatives()?;
+            if !self.eat(')') {
+                return Err(match self.peek() {
+                    None => PipelineError::new(open..open + 1, "unclosed `(`"),
+                    Some(_) => self.expected("`)`"),
+                });
+            }
+            Some(Box::new(body))
+        } else {
+            None
+        };
+        Ok(Pipeline::Solver {
+            name,
+            span,
+            parameters,
+            body,
+        })
+    }
+
+    /// After the `{`, up to and including the `}`
+    fn parameters(&mut self) -> Result<Vec<ParameterText>, PipelineError> {
+        let mut parameters = vec![];
+        if self.eat('}') {
+            return Ok(parameters);
+        }
+        loop {
+            self.skip_whitespace();
+            let (key, key_span) = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            if key.is_empty() {
+                return Err(self.expected("a parameter name"));
+            }
+            let key = key.to_owned();
+            if !self.eat('=') {
+                return Err(self.expected(&format!("`=` after `{key}`")));
+            }
+            self.skip_whitespace();
+            let (value, value_span) = if self.peek() == Some('"') {
+                let open = self.pos;
+                self.bump();
+                let (value, span) = self.take_while(|c| c != '"');
+                let value = value.to_owned();
+                if self.peek().is_none() {
+                    return Err(PipelineError::new(open..open + 1, "unclosed `\"`"));
+                }
+                self.bump();
+                (value, span)
+            } else {
+                let (value, span) =
+                    self.take_while(|c| !(c.is_whitespace() || c == ',' || c == '}'));
+                if value.is_empty() {
+                    return Err(self.expected(&format!("a value for `{key}`")));
+                }
+                (value.to_owned(), span)
+            };
+            parameters.push(ParameterText {
+                key,
+                key_span,
+                value,
+                value_span,
+            });
+
+            if self.eat('}') {
+                return Ok(parameters);
+            }
+            if !self.eat(',') {
+                return Err(self.expected("`,` or `}`"));
+            }
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn compile(&self) -> Result<Box<dyn Solver>, PipelineError> {
+        match self {
+            Pipeline::Solver {
+                name,
+                span,
+                parameters,
+                body,
+            } => {
+                let mut solver = match body {
+                    None => new_solver(name).ok_or_else(|| {
+                        let message = if all_combinators().iter().any(|(n, _)| n == name) {
+                            format!("`{name}` runs a pipeline, as in `{name}(shake+mix)`")
+                        } else {
+                            format!("unknown solver `{name}`")
+                        };
+                        PipelineError::new(span.clone(), message)
+                    })?,
+                    Some(body) => new_combinator(name, body.compile()?).ok_or_else(|| {
+                        PipelineError::new(span.clone(), format!("unknown combinator `{name}`"))
+                    })?,
+                };
+                solver.set_parameters(parameter_values(name, solver.parameters(), parameters)?);
+                solver.check_parameters().map_err(|e| {
+                    let span = match (parameters.first(), parameters.last()) {
+                        (Some(first), Some(last)) => first.key_span.start..last.value_span.end,
+                        _ => span.clone(),
+                    };
+                    PipelineError::new(span, e)
+                })?;
+                Ok(solver)
+            }
+            Pipeline::Sequence(stages) => {
+                let mut stages = stages.iter().map(Pipeline::compile);
+                let first = stages.next().unwrap()?;
+                stages.try_fold(first, |chain, next| {
+                    Ok(Box::new(Chain::new(chain, next?)) as Box<dyn Solver>)
+                })
+            }
+            Pipeline::Repeat(pipeline, count) => {
+                let solver = pipeline.compile()?;
+                Ok((1..*count).fold(solver.clone(), |chain, _| {
+                    Box::new(Chain::new(chain, solver.clone()))
+                }))
+            }
+            Pipeline::Alternatives(alternatives) => Ok(Box::new(Portfolio::new(
+                alternatives
+                    .iter()
+                    .map(Pipeline::compile)
+                    .collect::<Result<_, _>>()?,
+            ))),
+        }
+    }
+}
+
+/// Checks the parameters against what the solver accepts
+fn parameter_values(
+    name: &str,
+    specs: &[ParameterSpec],
+    parameters: &[ParameterText],
+) -> Result<HashMap<String, Parameter>, PipelineError> {
+    let mut values = HashMap::new();
+    for parameter in parameters {
+        let spec = specs
+            .iter()
+            .find(|s| s.name == parameter.key)
+            .ok_or_else(|| {
+                let message = if specs.is_empty() {
+                    format!("`{name}` doesn't accept parameters")
+                } else {
+                    let names = specs.iter().map(|s| s.name).collect::<Vec<_>>();
+                    format!(
+                        "unknown parameter `{}` for `{name}`, expected one of: {}",
+                        parameter.key,
+                        names.join(", ")
+                    )
+                };
+                PipelineError::new(parameter.key_span.clone(), message)
+            })?;
+        let value = spec
+            .parse(&parameter.value)
+            .map_err(|e| PipelineError::new(parameter.value_span.clone(), e))?;
+        if values.insert(parameter.key.clone(), value).is_some() {
+            return Err(PipelineError::new(
+                parameter.key_span.clone(),
+                format!("parameter `{}` given twice", parameter.key),
+            ));
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solver(name: &str, span: Range<usize>) -> Pipeline {
+        Pipeline::Solver {
+            name: name.to_owned(),
+            span,
+            parameters: vec![],
+            body: None,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(" greedy + (shake+mix) * 3 | expand").unwrap(),
+            Pipeline::Alternatives(vec![
+                Pipeline::Sequence(vec![
+                    solver("greedy", 1..7),
+                    Pipeline::Repeat(
+                        Box::new(Pipeline::Sequence(vec![
+                            solver("shake", 11..16),
+                            solver("mix", 17..20)
+                        ])),
+                        3
+                    ),
+                ]),
+                solver("expand", 28..34),
+            ])
+        );
+
+        let Pipeline::Solver { parameters, .. } =
+            parse(r#"set{ scorer=approx , name="a,b}c", empty=""}"#).unwrap()
+        else {
+            panic!("expected a solver");
+        };
+        let values = parameters
+            .iter()
+            .map(|p| (p.key.as_str(), p.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![("scorer", "approx"), ("name", "a,b}c"), ("empty", "")]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input| {
+            parse(input)
+                .and_then(|p| p.compile().map(|_| ()))
+                .unwrap_err()
+        };
+        assert_eq!(error("greedy+").span, 7..7);
+        assert_eq!(error("(greedy+mix").span, 0..1);
+        assert_eq!(error("greedy)").span, 6..7);
+        assert_eq!(error("shake*0").span, 6..7);
+        assert_eq!(error("shake{cap=1").span, 11..11);
+        assert_eq!(error("shake{cap}").span, 9..10);
+        assert_eq!(error("shaker").span, 0..6);
+        assert_eq!(error("shake{cup=1}").span, 6..9);
+        assert_eq!(error("shake{cap=x}").span, 10..11);
+        assert_eq!(error("shake{cap=1,cap=2}").span, 12..15);
+        assert_eq!(error("expand{cap=-1}").span, 11..13);
+        assert_eq!(error("swarm{particles=0}").span, 16..17);
+        assert_eq!(error("genetic{crossover=0}").span, 8..19);
+        assert_eq!(error("genetic{elitism=0.5,crossover=0.5}").span, 8..33);
+        assert_eq!(error("genetic{min_mutation=0.1}").span, 8..24);
+        assert_eq!(error("tempering{cold=0}").span, 10..16);
+        assert_eq!(error("tempering{cold=2}").span, 10..16);
+        assert_eq!(error("greedy+loop").span, 7..11);
+        assert_eq!(error("greedy(mix)").span, 0..6);
+        assert_eq!(error("loop(mix").span, 4..5);
+        assert_eq!(
+            error("shake{cap=x}").render("shake{cap=x}"),
+            "invalid int `x` for `cap`\n    shake{cap=x}\n              ^"
+        );
+    }
+
+    #[test]
+    fn test_compile() {
+        let solver = parse("greedy+(shake{cap=2}+mix)*2")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(solver.name(), "greedy+shake_cap_2+mix+shake_cap_2+mix");
+        assert_eq!(solver.stage_count(), 5);
+
+        let solver = parse("greedy+(expand{cap=1}|shake*2)+mix")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(solver.name(), "greedy+(expand_cap_1|shake+shake)+mix");
+        assert_eq!(solver.stage_count(), 4);
+
+        let solver = parse("greedy+loop{max=3}(shake+mix)+restart{times=4}(expand|annealer)")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            solver.name(),
+            "greedy+loop_max_3(shake+mix)+restart_times_4(expand|annealer)"
+        );
+        assert_eq!(solver.stage_count(), 1 + 2 + 4);
+
+        // only the parentheses of a single portfolio go
+        let solver = parse("restart((shake|mix)+(expand|annealer))")
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert_eq!(
+            solver.name(),
+            "restart_times_10((shake|mix)+(expand|annealer))"
+        );
+    }
+}
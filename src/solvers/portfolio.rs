@@ -0,0 +1,229 @@
+use std::time::{Duration, Instant};
+
+use log::debug;
+use rayon::prelude::*;
+
+use crate::{
+    common::Grid,
+    dto::{Instrument, SolutionDto},
+    scoring::impact_map::ImpactMap,
+};
+
+use super::{exact_score, sub_seed, Deadline, Problem, Score, Solver};
+
+/// How long every solver runs between two comparisons
+const SLICE: Duration = Duration::from_secs(1);
+
+/// Runs several solvers on the same problem at once, and keeps the best solution
+#[derive(Clone)]
+pub struct Portfolio {
+    solvers: Vec<Entry>,
+    leader: usize,
+    problem: Problem,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+}
+
+#[derive(Clone)]
+struct Entry {
+    solver: Box<dyn Solver>,
+    best: Option<(Score, SolutionDto)>,
+    done: bool,
+}
+
+impl Solver for Portfolio {
+    fn name(&self) -> String {
+        let names = self
+            .solvers
+            .iter()
+            .map(|e| e.solver.name())
+            .collect::<Vec<_>>();
+        format!("({})", names.join("|"))
+    }
+
+    fn get_impact_map(&self, instrument: &Instrument) -> Option<&ImpactMap> {
+        self.solvers[self.leader].solver.get_impact_map(instrument)
+    }
+
+    fn get_grid(&self) -> Option<&Grid> {
+        self.solvers[self.leader].solver.get_grid()
+    }
+
+    fn get_problem(&self) -> &Problem {
+        self.solvers[self.leader].solver.get_problem()
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        for (i, entry) in self.solvers.iter_mut().enumerate() {
+            entry.solver.set_seed(sub_seed(seed, i));
+        }
+    }
+
+    /// They run side by side, so only the longest one counts
+    fn stage_count(&self) -> usize {
+        self.solvers
+            .iter()
+            .map(|e| e.solver.stage_count())
+            .max()
+            .unwrap_or(1)
+    }
+
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
+        self.leader = 0;
+        let budget = self.time_budget;
+        self.solvers.par_iter_mut().for_each(|entry| {
+            entry.solver.set_time_budget(budget);
+            entry.solver.initialize(problem, solution.clone());
+            entry.best = None;
+            entry.done = false;
+        });
+        debug!(
+            "portfolio({}): initialized {}",
+            self.problem.id,
+            self.name()
+        );
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        self.solvers
+            .par_iter_mut()
+            .filter(|entry| !entry.done)
+            .for_each(|entry| {
+                let start = Instant::now();
+                let (solution, done) = loop {
+                    let (solution, done) = entry.solver.solve_step();
+                    if done || start.elapsed() >= SLICE {
+                        break (solution, done);
+                    }
+                };
+                let score = exact_score(entry.solver.get_problem(), &solution);
+                let improved = match &entry.best {
+                    Some((best, _)) => score.0 > best.0,
+                    None => true,
+                };
+                if improved {
+                    entry.best = Some((score, solution));
+                }
+                entry.done = done;
+            });
+
+        let (leader, (score, solution)) = self
+            .solvers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| e.best.as_ref().map(|best| (i, best)))
+            .max_by_key(|(_, (score, _))| score.0)
+            .expect("portfolio: no solution");
+        if leader != self.leader {
+            debug!(
+                "portfolio({}): {} takes the lead with {}",
+                self.problem.id,
+                self.solvers[leader].solver.name(),
+                score.0
+            );
+            self.leader = leader;
+        }
+        let solution = solution.clone();
+
+        let done = self.solvers.iter().all(|e| e.done) || self.deadline.passed();
+        (solution, done)
+    }
+}
+
+impl Portfolio {
+    pub fn new(solvers: Vec<Box<dyn Solver>>) -> Self {
+        assert!(!solvers.is_empty(), "portfolio: no solvers");
+        Portfolio {
+            solvers: solvers
+                .into_iter()
+                .map(|solver| Entry {
+                    solver,
+                    best: None,
+                    done: false,
+                })
+                .collect(),
+            leader: 0,
+            problem: Problem::default(),
+            time_budget: None,
+            deadline: Deadline::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::{Attendee, Point2D, ProblemDto};
+
+    /// Moves its only musician to `x` over `steps` steps
+    #[derive(Clone, Default)]
+    struct Walk {
+        x: f32,
+        steps: usize,
+        step: usize,
+        problem: Problem,
+    }
+
+    impl Solver for Walk {
+        fn name(&self) -> String {
+            format!("walk_{}", self.x)
+        }
+
+        fn get_problem(&self) -> &Problem {
+            &self.problem
+        }
+
+        fn initialize(&mut self, problem: &Problem, _solution: SolutionDto) {
+            self.problem = problem.clone();
+            self.step = 0;
+        }
+
+        fn solve_step(&mut self) -> (SolutionDto, bool) {
+            self.step += 1;
+            let x = 10.0 + (self.x - 10.0) * self.step as f32 / self.steps as f32;
+            let solution = SolutionDto {
+                placements: vec![Point2D { x, y: 10.0 }],
+                ..Default::default()
+            };
+            (solution, self.step == self.steps)
+        }
+    }
+
+    #[test]
+    fn test_keeps_the_best() {
+        let problem = Problem {
+            data: ProblemDto {
+                stage_width: 100.0,
+                stage_height: 20.0,
+                musicians: vec![Instrument(0)],
+                attendees: vec![Attendee {
+                    x: 50.0,
+                    y: 30.0,
+                    tastes: vec![1000.0],
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let walk = |x, steps| -> Box<dyn Solver> {
+            Box::new(Walk {
+                x,
+                steps,
+                ..Default::default()
+            })
+        };
+        // the closest to the attendee wins, even if it stopped early
+        let mut portfolio = Portfolio::new(vec![walk(90.0, 3), walk(50.0, 1), walk(30.0, 2)]);
+        assert_eq!(portfolio.name(), "(walk_90|walk_50|walk_30)");
+
+        let solution = portfolio.solve(&problem);
+        assert_eq!(solution.data.placements[0].x, 50.0);
+        assert_eq!(portfolio.leader, 1);
+    }
+}
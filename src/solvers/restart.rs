@@ -0,0 +1,150 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::debug;
+
+use crate::{
+    common::Grid,
+    dto::{Instrument, SolutionDto},
+    scoring::impact_map::ImpactMap,
+};
+
+use super::{
+    combinator_name, exact_score, parameters::name_suffix, sub_seed, Deadline, Parameter,
+    ParameterKind, ParameterSpec, Problem, Score, Solver,
+};
+
+pub const RESTART_PARAMETERS: &[ParameterSpec] = &[ParameterSpec {
+    name: "times",
+    kind: ParameterKind::Int,
+    default: Some("10"),
+    min: Some(1.0),
+    description: "runs of the pipeline, each from the same solution",
+}];
+
+/// Runs its pipeline several times from the same solution, and keeps the best run
+#[derive(Clone)]
+pub struct Restart {
+    // Parameters
+    times: usize,
+    body: Box<dyn Solver>,
+    // Data
+    current: Box<dyn Solver>,
+    problem: Problem,
+    start: SolutionDto,
+    run: usize,
+    best: Option<(Score, SolutionDto)>,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+    seed: Option<u64>,
+}
+
+impl Solver for Restart {
+    fn name(&self) -> String {
+        // always with the count, a default could change
+        let parameters = HashMap::from([("times".to_owned(), Parameter::Int(self.times as i64))]);
+        combinator_name("restart", &name_suffix(&parameters), &*self.body)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        RESTART_PARAMETERS
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("times", Parameter::Int(v)) => self.times = v as usize,
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
+    }
+
+    fn get_impact_map(&self, instrument: &Instrument) -> Option<&ImpactMap> {
+        self.current.get_impact_map(instrument)
+    }
+
+    fn get_grid(&self) -> Option<&Grid> {
+        self.current.get_grid()
+    }
+
+    fn get_problem(&self) -> &Problem {
+        self.current.get_problem()
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    fn stage_count(&self) -> usize {
+        self.body.stage_count() * self.times
+    }
+
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
+        self.start = solution;
+        self.run = 0;
+        self.best = None;
+        self.start_run();
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        let (solution, done) = self.current.solve_step();
+        if !done {
+            return (solution, false);
+        }
+
+        let score = exact_score(self.current.get_problem(), &solution);
+        self.run += 1;
+        debug!(
+            "restart({}): run {}/{} scored {}",
+            self.problem.id, self.run, self.times, score.0
+        );
+        let improved = match &self.best {
+            Some((best, _)) => score.0 > best.0,
+            None => true,
+        };
+        if improved {
+            self.best = Some((score, solution));
+        }
+
+        if self.run >= self.times || self.deadline.passed() {
+            let (_, best) = self.best.clone().unwrap();
+            return (best, true);
+        }
+        self.start_run();
+        (self.best.as_ref().unwrap().1.clone(), false)
+    }
+}
+
+impl Restart {
+    pub fn new(body: Box<dyn Solver>) -> Self {
+        Restart {
+            times: 10,
+            current: body.clone(),
+            body,
+            problem: Problem::default(),
+            start: SolutionDto::default(),
+            run: 0,
+            best: None,
+            time_budget: None,
+            deadline: Deadline::default(),
+            seed: None,
+        }
+    }
+
+    /// Every remaining run gets the same share of the time left
+    fn start_run(&mut self) {
+        let runs_left = (self.times - self.run) as u32;
+        self.current = self.body.clone();
+        if let Some(seed) = self.seed {
+            self.current.set_seed(sub_seed(seed, self.run));
+        }
+        self.current
+            .set_time_budget(self.deadline.remaining().map(|left| left / runs_left));
+        self.current.initialize(&self.problem, self.start.clone());
+    }
+}
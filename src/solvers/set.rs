@@ -1,13 +1,13 @@
 use std::collections::HashMap;
 
-use log::debug;
+use log::{debug, info};
 
 use crate::{
     dto::SolutionDto,
     scoring::{approximate::ApproximateScorer, new_scorer::NewScorer, scorer::LegacyScorer},
 };
 
-use super::{Parameter, Problem, Solver};
+use super::{Parameter, ParameterKind, ParameterSpec, Problem, Solver};
 
 #[derive(Clone, Copy)]
 enum Scorer {
@@ -30,6 +30,8 @@ impl Scorer {
 pub struct Set {
     // Parameters
     scorer: Option<Scorer>,
+    clusters: Option<usize>,
+    seed: u64,
     // Data
     problem: Problem,
     solution: SolutionDto,
@@ -41,13 +43,45 @@ impl Solver for Set {
         if let Some(scorer) = self.scorer {
             name += &format!("_scorer_{}", scorer.name(),);
         }
+        if let Some(clusters) = self.clusters {
+            name += &format!("_clusters_{}", clusters);
+        }
+        if self.seed != 0 {
+            name += &format!("_seed_{}", self.seed);
+        }
         name
     }
 
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[
+            ParameterSpec {
+                name: "scorer",
+                kind: ParameterKind::Enum(&["legacy", "new", "approx"]),
+                default: None,
+                min: None,
+                description: "scorer the next solvers use",
+            },
+            ParameterSpec {
+                name: "clusters",
+                kind: ParameterKind::Int,
+                default: None,
+                min: Some(1.0),
+                description: "attendee clusters of the approx scorer, automatic when unset",
+            },
+            ParameterSpec {
+                name: "seed",
+                kind: ParameterKind::Int,
+                default: Some("0"),
+                min: Some(0.0),
+                description: "seed of the approx scorer clustering",
+            },
+        ]
+    }
+
     fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
         for (k, v) in parameters.into_iter() {
             match (k.as_str(), v) {
-                ("scorer", Parameter::String(v)) => {
+                ("scorer", Parameter::Enum(v)) => {
                     self.scorer = Some(match v.as_str() {
                         "legacy" => Scorer::Legacy,
                         "new" => Scorer::New,
@@ -55,6 +89,8 @@ impl Solver for Set {
                         _ => panic!("Unknown scorer {}", v),
                     })
                 }
+                ("clusters", Parameter::Int(v)) => self.clusters = Some(v as usize),
+                ("seed", Parameter::Int(v)) => self.seed = v as u64,
                 _ => panic!("Unknown parameter {}", k),
             }
         }
@@ -79,7 +115,22 @@ impl Solver for Set {
             self.problem.scorer = match scorer {
                 Scorer::Legacy => Box::<LegacyScorer>::default(),
                 Scorer::New => Box::<NewScorer>::default(),
-                Scorer::Approximate => Box::new(ApproximateScorer::new(&self.problem)),
+                Scorer::Approximate => {
+                    let scorer = ApproximateScorer::new(
+                        &self.problem,
+                        &self.solution.placements,
+                        self.clusters,
+                        self.seed,
+                    );
+                    info!(
+                        "set({}): {} attendees clustered into {}, estimated error {:.3}%",
+                        self.problem.id,
+                        self.problem.data.attendees.len(),
+                        scorer.clusters(),
+                        scorer.estimated_error() * 100.0
+                    );
+                    Box::new(scorer)
+                }
             }
         }
 
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use log::debug;
 
@@ -6,9 +6,10 @@ use crate::{
     common::calculate_invalid_positions,
     dto::{Point2D, SolutionDto},
     geometry::Coords2D,
+    scoring::delta_scorer::DeltaScorer,
 };
 
-use super::{Parameter, Problem, Score, Solver};
+use super::{parameters::CYCLES_CAP, Deadline, Parameter, ParameterSpec, Problem, Score, Solver};
 
 #[derive(Default, Clone)]
 pub struct Shake {
@@ -17,6 +18,7 @@ pub struct Shake {
     // Data
     problem: Problem,
     solution: SolutionDto,
+    scorer: DeltaScorer,
     orig_score: Score,
     curr_score: Score,
     idx: usize,
@@ -24,6 +26,8 @@ pub struct Shake {
     delta: f32,
     any_improvement_this_cycle: bool,
     cycles_count: u32,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
 }
 
 impl Solver for Shake {
@@ -35,6 +39,10 @@ impl Solver for Shake {
         name
     }
 
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[CYCLES_CAP]
+    }
+
     fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
         for (k, v) in parameters.into_iter() {
             match (k.as_str(), v) {
@@ -48,6 +56,10 @@ impl Solver for Shake {
         &self.problem
     }
 
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
     fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
         assert!(
             !solution.placements.is_empty(),
@@ -55,10 +67,14 @@ impl Solver for Shake {
             problem.id
         );
         self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
         self.solution = solution;
-        self.curr_score = self
-            .problem
-            .score(&self.solution.placements, self.solution.volumes.as_ref());
+        self.scorer = DeltaScorer::new(
+            &self.problem.data,
+            &self.solution.placements,
+            self.solution.volumes.as_ref(),
+        );
+        self.curr_score = self.scorer.score();
         self.orig_score = self.curr_score;
         self.idx = 0;
         self.idx_change = 0;
@@ -89,6 +105,11 @@ impl Solver for Shake {
             ];
 
             for i_pos in self.idx..self.solution.placements.len() {
+                if self.deadline.passed() {
+                    debug!("shake({}): out of time", self.problem.id);
+                    self.idx = self.solution.placements.len();
+                    return (self.solution.clone(), true);
+                }
                 #[allow(clippy::needless_range_loop)]
                 for i_change in self.idx_change..changes.len() {
                     let curr_pos = self.solution.placements[i_pos];
@@ -105,13 +126,13 @@ impl Solver for Shake {
                         self.solution.placements[i_pos] = curr_pos;
                         continue;
                     }
-                    let new_score = self
-                        .problem
-                        .score(&self.solution.placements, self.solution.volumes.as_ref());
-                    if new_score.0 <= self.curr_score.0 {
+                    let score_delta = self.scorer.move_delta(i_pos, new_pos);
+                    if score_delta <= 0 {
                         self.solution.placements[i_pos] = curr_pos;
                         continue;
                     }
+                    self.scorer.apply_move(i_pos, new_pos);
+                    let new_score = Score(self.curr_score.0 + score_delta);
                     debug!(
                         "shake({}): {} => {}",
                         self.problem.id, self.orig_score.0, new_score.0
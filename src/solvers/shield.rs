@@ -0,0 +1,296 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::{debug, info};
+
+use crate::{
+    dto::{Point2D, SolutionDto},
+    geometry::distance2,
+    scoring::{breakdown::ScoreBreakdown, delta_scorer::DeltaScorer},
+};
+
+use super::{
+    parameters::name_suffix, Deadline, Parameter, ParameterKind, ParameterSpec, Problem, Score,
+    Solver,
+};
+
+/// How far in front of the musician a blocker goes, on the line to the attendee: the closer,
+/// the wider the shadow
+const DISTANCES: [f32; 3] = [10.001, 12.5, 15.0];
+/// Blockers tried for every line, the ones bringing the least first
+const CANDIDATES: usize = 3;
+
+/// Silences musicians who bring little and moves them in front of the musicians that attendees
+/// hate: a musician at volume 0 still blocks the sound of the others.
+///
+/// The lines are the attendee/musician pairs losing the most points, the blockers go on them
+/// just in front of the musician, and a move is only kept when the exact score improves.
+#[derive(Clone)]
+pub struct Shield {
+    // Parameters
+    parameters_suffix: String,
+    max_lines: usize,
+    // Data
+    problem: Problem,
+    placements: Vec<Point2D>,
+    volumes: Vec<f32>,
+    scorer: DeltaScorer,
+    /// The worst pairs as `(attendee, musician)`, the worst first
+    lines: Vec<(usize, usize)>,
+    /// Musicians which may become blockers, the ones bringing the least first
+    candidates: Vec<usize>,
+    line_i: usize,
+    blockers: usize,
+    start_score: Score,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+}
+
+impl Default for Shield {
+    fn default() -> Self {
+        Self {
+            parameters_suffix: String::new(),
+            max_lines: 100,
+            problem: Problem::default(),
+            placements: vec![],
+            volumes: vec![],
+            scorer: DeltaScorer::default(),
+            lines: vec![],
+            candidates: vec![],
+            line_i: 0,
+            blockers: 0,
+            start_score: Score::default(),
+            time_budget: None,
+            deadline: Deadline::default(),
+        }
+    }
+}
+
+impl Solver for Shield {
+    fn name(&self) -> String {
+        format!("shield{}", self.parameters_suffix)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[ParameterSpec {
+            name: "lines",
+            kind: ParameterKind::Int,
+            default: Some("100"),
+            min: Some(0.0),
+            description: "attendee/musician pairs losing the most points to try to block",
+        }]
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("lines", Parameter::Int(v)) => self.max_lines = v as usize,
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
+    }
+
+    fn get_problem(&self) -> &Problem {
+        &self.problem
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        assert!(
+            !solution.placements.is_empty(),
+            "shield({}): must not be the start of the chain",
+            problem.id
+        );
+        self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
+        let musician_count = solution.placements.len();
+        self.volumes = solution
+            .volumes
+            .unwrap_or_else(|| vec![1.0; musician_count]);
+        self.placements = solution.placements;
+        self.scorer = DeltaScorer::new(&problem.data, &self.placements, Some(&self.volumes));
+        self.start_score = self.scorer.score();
+
+        let breakdown =
+            ScoreBreakdown::new(&problem.data, &self.placements, Some(&self.volumes), true);
+        let mut pairs: Vec<_> = breakdown
+            .pairs
+            .unwrap()
+            .into_iter()
+            .filter(|pair| pair.score < 0)
+            .collect();
+        pairs.sort_by_key(|pair| pair.score);
+        self.lines = pairs
+            .iter()
+            .take(self.max_lines)
+            .map(|pair| (pair.attendee, pair.musician))
+            .collect();
+        self.candidates = (0..musician_count).collect();
+        self.candidates
+            .sort_by_key(|&musician_i| breakdown.musicians[musician_i].score);
+        self.line_i = 0;
+        self.blockers = 0;
+        debug!(
+            "shield({}): {} losing pairs, trying the worst {}",
+            problem.id,
+            pairs.len(),
+            self.lines.len()
+        );
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        if self.line_i < self.lines.len() && !self.deadline.passed() {
+            let (attendee_i, musician_i) = self.lines[self.line_i];
+            self.line_i += 1;
+            // an earlier blocker may be in the way already, or the musician a blocker now
+            let losing =
+                self.scorer.is_audible(attendee_i, musician_i) && self.volumes[musician_i] > 0.0;
+            if losing {
+                self.shield(attendee_i, musician_i);
+            }
+            if self.line_i < self.lines.len() {
+                return (self.serialize(), false);
+            }
+        }
+
+        info!(
+            "shield({}): {} blockers, {} => {}",
+            self.problem.id,
+            self.blockers,
+            self.start_score.0,
+            self.scorer.score().0
+        );
+        (self.serialize(), true)
+    }
+}
+
+impl Shield {
+    fn serialize(&self) -> SolutionDto {
+        SolutionDto {
+            placements: self.placements.clone(),
+            volumes: Some(self.volumes.clone()),
+        }
+    }
+
+    /// Tries the cheapest musicians as a silent blocker on the line, keeps the first which
+    /// improves the score
+    fn shield(&mut self, attendee_i: usize, musician_i: usize) {
+        let musician = self.placements[musician_i];
+        let attendee = &self.problem.data.attendees[attendee_i];
+        let (dx, dy) = (attendee.x - musician.x, attendee.y - musician.y);
+        let length = (dx * dx + dy * dy).sqrt();
+
+        let candidates: Vec<usize> = self
+            .candidates
+            .iter()
+            .copied()
+            .filter(|&blocker_i| blocker_i != musician_i)
+            .take(CANDIDATES)
+            .collect();
+        for blocker_i in candidates {
+            for distance in DISTANCES {
+                let position = Point2D {
+                    x: musician.x + dx / length * distance,
+                    y: musician.y + dy / length * distance,
+                };
+                if !self.is_free(blocker_i, &position) {
+                    continue;
+                }
+                let volume = self.volumes[blocker_i];
+                let mute_delta = self.scorer.set_volume(blocker_i, 0.0);
+                let move_delta = self.scorer.move_delta(blocker_i, position);
+                if mute_delta + move_delta > 0 {
+                    self.scorer.apply_move(blocker_i, position);
+                    self.placements[blocker_i] = position;
+                    self.volumes[blocker_i] = 0.0;
+                    // a blocker only moves once
+                    self.candidates.retain(|&c| c != blocker_i);
+                    self.blockers += 1;
+                    debug!(
+                        "shield({}): musician {} blocks musician {} for attendee {}, {:+}",
+                        self.problem.id,
+                        blocker_i,
+                        musician_i,
+                        attendee_i,
+                        mute_delta + move_delta
+                    );
+                    return;
+                }
+                self.scorer.set_volume(blocker_i, volume);
+            }
+        }
+    }
+
+    /// Whether the musician can stand at `position`: on the stage and away from the others
+    fn is_free(&self, musician_i: usize, position: &Point2D) -> bool {
+        let data = &self.problem.data;
+        let (left, bottom) = data.stage_bottom_left;
+        let on_stage = left + 10.0 <= position.x
+            && position.x <= left + data.stage_width - 10.0
+            && bottom + 10.0 <= position.y
+            && position.y <= bottom + data.stage_height - 10.0;
+        on_stage
+            && self.placements.iter().enumerate().all(|(other_i, other)| {
+                other_i == musician_i || distance2(position, other) >= 100.0
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::calculate_invalid_positions,
+        dto::{Attendee, Instrument, ProblemDto},
+        scoring::{new_scorer::NewScorer, Scorer},
+    };
+
+    #[test]
+    fn test_blocks_the_haters() {
+        let problem = Problem {
+            data: ProblemDto {
+                stage_width: 100.0,
+                stage_height: 100.0,
+                musicians: vec![Instrument(0), Instrument(1)],
+                attendees: vec![
+                    Attendee {
+                        x: 50.0,
+                        y: 200.0,
+                        tastes: vec![1000.0, 0.0],
+                    },
+                    Attendee {
+                        x: 200.0,
+                        y: 50.0,
+                        tastes: vec![-500.0, 0.0],
+                    },
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // the second musician brings nothing, and is far from the line to the hater
+        let solution = SolutionDto {
+            placements: vec![Point2D { x: 50.0, y: 50.0 }, Point2D { x: 20.0, y: 20.0 }],
+            volumes: Some(vec![10.0, 10.0]),
+        };
+        let start = NewScorer.score(
+            &problem.data,
+            &solution.placements,
+            solution.volumes.as_ref(),
+        );
+
+        let mut solver = Shield::default();
+        solver.initialize(&problem, solution);
+        let solution = solver.finish(&problem, None);
+
+        assert_eq!(solver.blockers, 1);
+        assert!(solution.score.0 > start.0);
+        assert_eq!(solution.data.volumes, Some(vec![10.0, 0.0]));
+        assert_eq!(solution.data.placements[1].y, 50.0);
+        assert!(solution.data.placements[1].x > 50.0);
+        assert!(calculate_invalid_positions(&solution.data.placements, &problem.data).is_empty());
+    }
+}
@@ -1,7 +1,8 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use log::debug;
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     common::calculate_invalid_positions,
@@ -9,9 +10,12 @@ use crate::{
     geometry::Coords2D,
 };
 
-use super::{Parameter, Problem, Score, Solver};
+use super::{
+    parameters::{name_suffix, CYCLES_CAP},
+    Deadline, Parameter, ParameterKind, ParameterSpec, Problem, Score, Solver, SolverRng,
+};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct Particle {
     positions: Vec<Point2D>,
     best_positions: Vec<Point2D>,
@@ -23,6 +27,12 @@ struct Particle {
 pub struct Swarm {
     // Parameters
     cycles_cap: Option<u32>,
+    /// The parameters given, for the name
+    parameters_suffix: String,
+    swarm_size: Option<usize>,
+    inertia: Option<f32>,
+    cognitive_coeff: Option<f32>,
+    social_coeff: Option<f32>,
     // Data
     problem: Problem,
     min_x: f32,
@@ -33,21 +43,70 @@ pub struct Swarm {
     best_score: Score,
     particles: Vec<Particle>,
     cycles_count: u32,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+    seed: Option<u64>,
+    rng: SolverRng,
+}
+
+/// What `checkpoint` saves, the bounds come back from the problem
+#[derive(Serialize, Deserialize)]
+struct SwarmState {
+    particles: Vec<Particle>,
+    best_positions: Vec<Point2D>,
+    best_score: Score,
+    cycles_count: u32,
+    rng: SolverRng,
 }
 
 impl Solver for Swarm {
     fn name(&self) -> String {
-        let mut name = "expand".to_owned();
-        if let Some(cap) = self.cycles_cap {
-            name += &format!("_cap_{}", cap);
-        }
-        name
+        format!("swarm{}", self.parameters_suffix)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[
+            CYCLES_CAP,
+            ParameterSpec {
+                name: "particles",
+                kind: ParameterKind::Int,
+                default: Some("20"),
+                min: Some(1.0),
+                description: "size of the swarm",
+            },
+            ParameterSpec {
+                name: "inertia",
+                kind: ParameterKind::Float,
+                default: Some("0.02"),
+                min: Some(0.0),
+                description: "mean share of the velocity kept every step (±50%)",
+            },
+            ParameterSpec {
+                name: "cognitive",
+                kind: ParameterKind::Float,
+                default: Some("2.1"),
+                min: Some(0.0),
+                description: "pull towards the best positions of the particle",
+            },
+            ParameterSpec {
+                name: "social",
+                kind: ParameterKind::Float,
+                default: Some("1.7"),
+                min: Some(0.0),
+                description: "pull towards the best positions of the neighbours",
+            },
+        ]
     }
 
     fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
         for (k, v) in parameters.into_iter() {
             match (k.as_str(), v) {
                 ("cap", Parameter::Int(v)) => self.cycles_cap = Some(v as u32),
+                ("particles", Parameter::Int(v)) => self.swarm_size = Some(v as usize),
+                ("inertia", Parameter::Float(v)) => self.inertia = Some(v as f32),
+                ("cognitive", Parameter::Float(v)) => self.cognitive_coeff = Some(v as f32),
+                ("social", Parameter::Float(v)) => self.social_coeff = Some(v as f32),
                 _ => panic!("Unknown parameter {}", k),
             }
         }
@@ -57,35 +116,36 @@ impl Solver for Swarm {
         &self.problem
     }
 
-    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
-        self.problem = problem.clone();
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
 
-        const SWARM_SIZE: usize = 20;
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
 
-        let mut rng = rand::thread_rng();
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        self.start(problem);
+        self.rng = SolverRng::new(self.seed);
+
+        let swarm_size = self.swarm_size.unwrap_or(20);
 
         debug!(
             "swarm({}): initializing {} particles",
-            self.problem.id, SWARM_SIZE
+            self.problem.id, swarm_size
         );
 
-        self.min_x = self.problem.data.stage_bottom_left.x() + 10.0;
-        self.min_y = self.problem.data.stage_bottom_left.y() + 10.0;
-        self.max_x = self.problem.data.stage_bottom_left.x() + self.problem.data.stage_width - 10.0;
-        self.max_y =
-            self.problem.data.stage_bottom_left.y() + self.problem.data.stage_height - 10.0;
-
         let mut best_positions = vec![];
         let mut best_score = Score(i64::MIN);
-        for i in 0..SWARM_SIZE {
+        for i in 0..swarm_size {
             let mut positions = vec![];
             if !solution.placements.is_empty() {
                 positions = solution.placements.clone();
             } else {
                 for _ in 0..self.problem.data.musicians.len() {
                     positions.push(Point2D {
-                        x: rng.gen_range(self.min_x..self.max_x),
-                        y: rng.gen_range(self.min_y..self.max_y),
+                        x: self.rng.gen_range(self.min_x..self.max_x),
+                        y: self.rng.gen_range(self.min_y..self.max_y),
                     })
                 }
             }
@@ -93,9 +153,12 @@ impl Solver for Swarm {
             let mut velocities = vec![];
             for _ in 0..self.problem.data.musicians.len() {
                 velocities.push(Point2D {
-                    x: rng.gen_range(-self.problem.data.stage_width..self.problem.data.stage_width)
+                    x: self
+                        .rng
+                        .gen_range(-self.problem.data.stage_width..self.problem.data.stage_width)
                         / 50.0,
-                    y: rng
+                    y: self
+                        .rng
                         .gen_range(-self.problem.data.stage_height..self.problem.data.stage_height)
                         / 50.0,
                 })
@@ -158,12 +221,52 @@ impl Solver for Swarm {
         debug!("swarm({}): initialized", self.problem.id);
     }
 
-    fn solve_step(&mut self) -> (SolutionDto, bool) {
-        let mut rng = rand::thread_rng();
+    fn checkpoint(&self) -> Option<serde_json::Value> {
+        let state = SwarmState {
+            particles: self.particles.clone(),
+            best_positions: self.best_positions.clone(),
+            best_score: self.best_score,
+            cycles_count: self.cycles_count,
+            rng: self.rng.clone(),
+        };
+        Some(serde_json::to_value(state).unwrap())
+    }
+
+    fn restore(&mut self, problem: &Problem, state: serde_json::Value) -> Result<(), String> {
+        let state: SwarmState = serde_json::from_value(state).map_err(|e| e.to_string())?;
+        let musician_count = problem.data.musicians.len();
+        if state.particles.is_empty() {
+            return Err("swarm: no particles".to_owned());
+        }
+        if state.best_positions.len() != musician_count
+            || state
+                .particles
+                .iter()
+                .any(|p| p.positions.len() != musician_count)
+        {
+            return Err(format!(
+                "swarm: the positions are not for {} musicians",
+                musician_count
+            ));
+        }
+        self.start(problem);
+        self.particles = state.particles;
+        self.best_positions = state.best_positions;
+        self.best_score = state.best_score;
+        self.cycles_count = state.cycles_count;
+        self.rng = state.rng;
+        debug!(
+            "swarm({}): restored at cycle {}",
+            self.problem.id, self.cycles_count
+        );
+        Ok(())
+    }
 
-        let weight: f32 = rng.gen_range(0.01..0.03);
-        const COGNITIVE_COEFF: f32 = 2.1;
-        const SOCIAL_COEFF: f32 = 1.7;
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        let inertia = self.inertia.unwrap_or(0.02);
+        let weight: f32 = self.rng.gen_range(inertia * 0.5..=inertia * 1.5);
+        let cognitive_coeff = self.cognitive_coeff.unwrap_or(2.1);
+        let social_coeff = self.social_coeff.unwrap_or(1.7);
 
         for i in 0..self.particles.len() {
             let i_prev = i.wrapping_sub(1) % self.particles.len();
@@ -181,14 +284,14 @@ impl Solver for Swarm {
                 let p = &mut self.particles[i];
 
                 // Update velocity
-                let rp = rng.gen::<f32>();
-                let rg = rng.gen::<f32>();
+                let rp = self.rng.gen::<f32>();
+                let rg = self.rng.gen::<f32>();
                 p.velocities[idx].x = weight * p.velocities[idx].x
-                    + COGNITIVE_COEFF * rp * (p.best_positions[idx].x - p.positions[idx].x)
-                    + SOCIAL_COEFF * rg * (best_position.x - p.positions[idx].x);
+                    + cognitive_coeff * rp * (p.best_positions[idx].x - p.positions[idx].x)
+                    + social_coeff * rg * (best_position.x - p.positions[idx].x);
                 p.velocities[idx].y = weight * p.velocities[idx].y
-                    + COGNITIVE_COEFF * rp * (p.best_positions[idx].y - p.positions[idx].y)
-                    + SOCIAL_COEFF * rg * (best_position.y - p.positions[idx].y);
+                    + cognitive_coeff * rp * (p.best_positions[idx].y - p.positions[idx].y)
+                    + social_coeff * rg * (best_position.y - p.positions[idx].y);
 
                 // Update position
                 let x = &mut p.positions[idx].x;
@@ -269,12 +372,25 @@ impl Solver for Swarm {
                 placements: self.best_positions.clone(),
                 ..Default::default()
             },
-            self.cycles_count >= self.cycles_cap.unwrap_or(u32::MAX),
+            self.cycles_count >= self.cycles_cap.unwrap_or(u32::MAX) || self.deadline.passed(),
         )
     }
 }
 
 impl Swarm {
+    fn start(&mut self, problem: &Problem) {
+        self.problem = problem.clone();
+        self.deadline = Deadline::start(self.time_budget);
+        self.particles = vec![];
+        self.cycles_count = 0;
+
+        self.min_x = self.problem.data.stage_bottom_left.x() + 10.0;
+        self.min_y = self.problem.data.stage_bottom_left.y() + 10.0;
+        self.max_x = self.problem.data.stage_bottom_left.x() + self.problem.data.stage_width - 10.0;
+        self.max_y =
+            self.problem.data.stage_bottom_left.y() + self.problem.data.stage_height - 10.0;
+    }
+
     fn score(&self, positions: &[Point2D]) -> Score {
         // let invalid = calculate_invalid_positions(&positions, &self.problem.data);
         // Score(self.problem.score(positions, None).0 / (1 + invalid.len()) as i64)
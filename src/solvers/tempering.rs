@@ -0,0 +1,326 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::debug;
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::dto::SolutionDto;
+
+use super::{
+    annealer::{acceptance_probability, Annealer},
+    parameters::name_suffix,
+    sub_seed, Deadline, Parameter, ParameterKind, ParameterSpec, Problem, Score, Solver, SolverRng,
+};
+
+/// Parallel tempering: annealers at fixed temperatures, from cold to hot, which swap their
+/// solutions with their neighbours on the ladder from time to time
+#[derive(Clone)]
+pub struct Tempering {
+    // Parameters
+    /// The parameters given, for the name
+    parameters_suffix: String,
+    replica_count: usize,
+    hot: f32,
+    cold: f32,
+    exchange_steps: usize,
+    steps_per_musician: usize,
+    timeout: Duration,
+    space: Option<String>,
+    seed: Option<u64>,
+    // Data
+    problem: Problem,
+    /// By temperature, the coldest first
+    replicas: Vec<Annealer>,
+    temperatures: Vec<f32>,
+    best: Option<(Score, SolutionDto)>,
+    max_steps: usize,
+    step_i: usize,
+    swaps: usize,
+    time_budget: Option<Duration>,
+    deadline: Deadline,
+    rng: SolverRng,
+}
+
+impl Default for Tempering {
+    fn default() -> Self {
+        Self {
+            parameters_suffix: String::new(),
+            replica_count: 8,
+            hot: 1.0,
+            cold: 0.01,
+            exchange_steps: 100,
+            steps_per_musician: 500,
+            timeout: Duration::from_secs(60 * 20),
+            space: None,
+            seed: None,
+            problem: Problem::default(),
+            replicas: vec![],
+            temperatures: vec![],
+            best: None,
+            max_steps: 0,
+            step_i: 0,
+            swaps: 0,
+            time_budget: None,
+            deadline: Deadline::default(),
+            rng: SolverRng::default(),
+        }
+    }
+}
+
+impl Solver for Tempering {
+    fn name(&self) -> String {
+        format!("tempering{}", self.parameters_suffix)
+    }
+
+    fn parameters(&self) -> &'static [ParameterSpec] {
+        &[
+            ParameterSpec {
+                name: "replicas",
+                kind: ParameterKind::Int,
+                default: Some("8"),
+                min: Some(2.0),
+                description: "annealers running side by side, one per temperature",
+            },
+            ParameterSpec {
+                name: "hot",
+                kind: ParameterKind::Float,
+                default: Some("1"),
+                min: Some(0.0),
+                description: "temperature of the hottest replica, 1 is where the annealer starts",
+            },
+            ParameterSpec {
+                name: "cold",
+                kind: ParameterKind::Float,
+                default: Some("0.01"),
+                min: Some(0.0),
+                description: "temperature of the coldest replica, the ladder is geometric",
+            },
+            ParameterSpec {
+                name: "exchange",
+                kind: ParameterKind::Int,
+                default: Some("100"),
+                min: Some(1.0),
+                description: "steps of every replica between two rounds of swaps",
+            },
+            ParameterSpec {
+                name: "steps",
+                kind: ParameterKind::Int,
+                default: Some("500"),
+                min: Some(1.0),
+                description: "steps per musician of every replica, when there is no time budget",
+            },
+            ParameterSpec {
+                name: "timeout",
+                kind: ParameterKind::Duration,
+                default: Some("20m"),
+                min: None,
+                description: "longest run when there is no time budget",
+            },
+            ParameterSpec {
+                name: "space",
+                kind: ParameterKind::Enum(&["grid", "continuous"]),
+                default: Some("grid"),
+                min: None,
+                description: "where the musicians move, as for the annealer",
+            },
+        ]
+    }
+
+    fn set_parameters(&mut self, parameters: HashMap<String, Parameter>) {
+        self.parameters_suffix = name_suffix(&parameters);
+        for (k, v) in parameters.into_iter() {
+            match (k.as_str(), v) {
+                ("replicas", Parameter::Int(v)) => self.replica_count = v as usize,
+                ("hot", Parameter::Float(v)) => self.hot = v as f32,
+                ("cold", Parameter::Float(v)) => self.cold = v as f32,
+                ("exchange", Parameter::Int(v)) => self.exchange_steps = v as usize,
+                ("steps", Parameter::Int(v)) => self.steps_per_musician = v as usize,
+                ("timeout", Parameter::Duration(v)) => self.timeout = v,
+                ("space", Parameter::Enum(v)) => self.space = Some(v),
+                _ => panic!("Unknown parameter {}", k),
+            }
+        }
+    }
+
+    fn check_parameters(&self) -> Result<(), String> {
+        if !(0.0 < self.cold && self.cold < self.hot) {
+            return Err("must have 0 < `cold` < `hot`".to_owned());
+        }
+        Ok(())
+    }
+
+    fn get_problem(&self) -> &Problem {
+        &self.problem
+    }
+
+    fn set_time_budget(&mut self, budget: Option<Duration>) {
+        self.time_budget = budget;
+    }
+
+    fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+    }
+
+    fn initialize(&mut self, problem: &Problem, solution: SolutionDto) {
+        self.problem = problem.clone();
+        self.deadline = Deadline::start(Some(self.time_budget.unwrap_or(self.timeout)));
+        self.rng = SolverRng::new(self.seed.map(|seed| sub_seed(seed, 0)));
+        self.step_i = 0;
+        self.swaps = 0;
+        self.max_steps = problem.data.musicians.len() * self.steps_per_musician;
+
+        let ratio = self.hot / self.cold;
+        let last = (self.replica_count - 1) as f32;
+        self.temperatures = (0..self.replica_count)
+            .map(|i| self.cold * ratio.powf(i as f32 / last))
+            .collect();
+
+        let mut replica = Annealer::default();
+        if let Some(space) = &self.space {
+            replica.set_parameters(HashMap::from([(
+                "space".to_owned(),
+                Parameter::Enum(space.clone()),
+            )]));
+        }
+        self.replicas = (0..self.replica_count)
+            .map(|i| {
+                let mut replica = replica.clone();
+                if let Some(seed) = self.seed {
+                    replica.set_seed(sub_seed(seed, i + 1));
+                }
+                replica
+            })
+            .collect();
+        self.replicas
+            .par_iter_mut()
+            .for_each(|replica| replica.initialize(problem, solution.clone()));
+        // the replicas keep the prior solution as their best if snapping it lost score
+        self.best = self
+            .replicas
+            .iter()
+            .map(|replica| replica.best())
+            .max_by_key(|(score, _)| score.0)
+            .map(|(score, solution)| (score, solution.clone()));
+        self.update_best();
+
+        debug!(
+            "tempering({}): initialized {} replicas, temperatures {:?}",
+            self.problem.id, self.replica_count, self.temperatures
+        );
+    }
+
+    fn solve_step(&mut self) -> (SolutionDto, bool) {
+        let exchange_steps = self.exchange_steps;
+        self.replicas
+            .par_iter_mut()
+            .zip(self.temperatures.par_iter())
+            .for_each(|(replica, temperature)| {
+                for _ in 0..exchange_steps {
+                    replica.step_at(*temperature);
+                }
+            });
+        self.step_i += exchange_steps;
+
+        // a colder replica takes the solution of the hotter one with the chance of
+        // an annealer at the temperature between theirs
+        for i in 0..self.replicas.len() - 1 {
+            let score_delta = self.replicas[i + 1].score().0 - self.replicas[i].score().0;
+            let temperature = 1.0 / (1.0 / self.temperatures[i] - 1.0 / self.temperatures[i + 1]);
+            let probability = acceptance_probability(score_delta, temperature).min(1.0);
+            if self.rng.gen_bool(probability as f64) {
+                self.replicas.swap(i, i + 1);
+                self.swaps += 1;
+            }
+        }
+        self.update_best();
+
+        let (score, solution) = self.best.clone().unwrap();
+        debug!(
+            "tempering({}): step {:>7}   best={:<12} coldest={:<12} swaps={}",
+            self.problem.id,
+            self.step_i,
+            score.0,
+            self.replicas[0].score().0,
+            self.swaps
+        );
+        let done_steps = self.time_budget.is_none() && self.step_i >= self.max_steps;
+        (solution, done_steps || self.deadline.passed())
+    }
+}
+
+impl Tempering {
+    /// The replicas move away from their best solutions, so it's kept on the side
+    fn update_best(&mut self) {
+        let replica = self
+            .replicas
+            .iter()
+            .max_by_key(|replica| replica.score().0)
+            .unwrap();
+        let improved = match &self.best {
+            Some((best, _)) => replica.score().0 > best.0,
+            None => true,
+        };
+        if improved {
+            self.best = Some((replica.score(), replica.serialize()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dto::Point2D,
+        solvers::{exact_score, test_problem},
+    };
+
+    #[test]
+    fn test_keeps_the_best_replica() {
+        let problem = test_problem();
+        let mut solver = Tempering::default();
+        solver.set_parameters(HashMap::from([
+            ("replicas".to_owned(), Parameter::Int(4)),
+            ("exchange".to_owned(), Parameter::Int(10)),
+            ("steps".to_owned(), Parameter::Int(50)),
+        ]));
+        solver.set_seed(5);
+        let solution = solver.solve(&problem);
+
+        assert_eq!(solver.temperatures.len(), 4);
+        assert!((solver.temperatures[0] - 0.01).abs() < 1e-6);
+        assert!((solver.temperatures[3] - 1.0).abs() < 1e-6);
+        assert!((solver.temperatures[1] / solver.temperatures[0] - 100f32.cbrt()).abs() < 1e-3);
+        assert_eq!(solver.step_i, 150);
+
+        let (best, _) = solver.best.unwrap();
+        assert_eq!(solution.score.0, best.0);
+        assert!(solver.replicas.iter().all(|r| r.score().0 <= best.0));
+    }
+
+    #[test]
+    fn test_keeps_the_prior_solution() {
+        let problem = test_problem();
+        // off the grid, next to the attendee, with the disliked instrument muted
+        let prior = SolutionDto {
+            placements: vec![
+                Point2D { x: 45.0, y: 90.0 },
+                Point2D { x: 90.0, y: 10.0 },
+                Point2D { x: 55.0, y: 90.0 },
+            ],
+            volumes: Some(vec![10.0, 0.0, 10.0]),
+        };
+        let prior_score = exact_score(&problem, &prior);
+
+        let mut solver = Tempering::default();
+        solver.set_parameters(HashMap::from([
+            ("replicas".to_owned(), Parameter::Int(2)),
+            ("cold".to_owned(), Parameter::Float(0.5)),
+            ("exchange".to_owned(), Parameter::Int(1)),
+            ("steps".to_owned(), Parameter::Int(1)),
+        ]));
+        solver.set_seed(5);
+        solver.initialize(&problem, prior);
+        let solution = solver.finish(&problem, None);
+        assert!(solution.score.0 >= prior_score.0);
+    }
+}